@@ -1,4 +1,5 @@
 use cairo;
+use libclient::PixelFormat;
 use pixels::{Error, Pixels, SurfaceTexture};
 use std::f64::consts::PI;
 use std::sync::{mpsc, Arc, Mutex};
@@ -849,7 +850,9 @@ fn game_thread(
   ctx.scale(surface.get_width() as f64, surface.get_height() as f64);
   render_full_board(&ctx, &gs.board);
   drop(ctx);
-  buffer.lock().unwrap().get_frame().copy_from_slice(&surface.get_data().unwrap());
+  // cairo renders premultiplied BGRA, the frame wants RGBA
+  let pixels = PixelFormat::Argb8888.to_internal(&surface.get_data().unwrap());
+  buffer.lock().unwrap().get_frame().copy_from_slice(&pixels);
   tx.send(());
   println!("Game thread end");
 }
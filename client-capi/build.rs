@@ -0,0 +1,15 @@
+// Generates the C header from the exported functions and types. It goes to
+// OUT_DIR; the tests check include/gf_client.h matches it.
+fn main() {
+  let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+  let out_dir = std::env::var("OUT_DIR").unwrap();
+  println!("cargo:rerun-if-changed=src/lib.rs");
+  println!("cargo:rerun-if-changed=cbindgen.toml");
+  let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir)).unwrap();
+  cbindgen::Builder::new()
+    .with_crate(&dir)
+    .with_config(config)
+    .generate()
+    .expect("Unable to generate the C header")
+    .write_to_file(format!("{}/gf_client.h", out_dir));
+}
@@ -0,0 +1,60 @@
+/* Draws a frame and a subsurface, then hangs up. Usage: client [socket] */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "gf_client.h"
+
+int main(int argc, char **argv) {
+  if (gf_client_abi_version() != GF_CLIENT_ABI_VERSION) {
+    fprintf(stderr, "Library ABI mismatch\n");
+    return 1;
+  }
+  GfClient *client = gf_client_connect(argc > 1 ? argv[1] : NULL, "example");
+  if (client == NULL) {
+    fprintf(stderr, "Failed to connect\n");
+    return 1;
+  }
+
+  /* The main surface is sized by the first resize */
+  GfEvent event;
+  do {
+    if (!gf_client_wait_event(client, &event)) {
+      fprintf(stderr, "Server hung up\n");
+      return 1;
+    }
+  } while (event.kind != GF_EVENT_TYPE_RESIZE);
+  uint32_t width, height;
+  gf_surface_size(client, GF_MAIN_SURFACE, &width, &height);
+
+  size_t len = (size_t)width * height * 4;
+  uint8_t *frame = malloc(len);
+  memset(frame, 0xff, len);
+  if (gf_surface_attach(client, GF_MAIN_SURFACE, frame, len, 0, 0, width, height) != 0 ||
+      gf_surface_commit(client, GF_MAIN_SURFACE) != 0) {
+    fprintf(stderr, "Failed to draw the main surface\n");
+    return 1;
+  }
+
+  uint32_t badge;
+  uint16_t pixels[4] = {0xf800, 0x07e0, 0x001f, 0xffff};
+  if (gf_surface_create(client, GF_MAIN_SURFACE, 2, 2, &badge) != 0 ||
+      gf_surface_set_format(client, badge, GF_PIXEL_FORMAT_RGB565) != 0 ||
+      gf_surface_attach(client, badge, (const uint8_t *)pixels, sizeof(pixels), 0, 0, 2, 2) != 0 ||
+      gf_surface_commit(client, badge) != 0 || gf_surface_commit(client, GF_MAIN_SURFACE) != 0) {
+    fprintf(stderr, "Failed to draw the subsurface\n");
+    return 1;
+  }
+  /* Out of bounds uploads are refused */
+  if (gf_surface_attach(client, badge, (const uint8_t *)pixels, sizeof(pixels), 1, 1, 2, 2) == 0) {
+    fprintf(stderr, "Accepted an out of bounds upload\n");
+    return 1;
+  }
+
+  while (gf_client_poll_event(client, &event)) {
+  }
+  gf_surface_destroy(client, badge);
+  gf_client_disconnect(client);
+  free(frame);
+  return 0;
+}
@@ -0,0 +1,101 @@
+/* C API of the gf compositor client library. Link with -lgfclient.
+ *
+ * Every function takes the client returned by gf_client_connect, which must
+ * not be used after gf_client_disconnect. Functions returning int give 0 on
+ * success and -1 on failure. */
+
+#ifndef GF_CLIENT_H
+#define GF_CLIENT_H
+
+/* Generated from src/lib.rs by the build script, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define GF_CLIENT_ABI_VERSION 1
+
+#define GF_MAIN_SURFACE 0
+
+typedef enum GfEventType {
+  GF_EVENT_TYPE_OTHER,
+  GF_EVENT_TYPE_RESIZE,
+  GF_EVENT_TYPE_CLOSE_REQUEST,
+  GF_EVENT_TYPE_KEYBOARD_ENTER,
+  GF_EVENT_TYPE_KEYBOARD_LEAVE,
+  GF_EVENT_TYPE_KEY,
+  GF_EVENT_TYPE_TOUCH_DOWN,
+  GF_EVENT_TYPE_TOUCH_MOTION,
+  GF_EVENT_TYPE_TOUCH_UP,
+  GF_EVENT_TYPE_TOUCH_CANCEL,
+} GfEventType;
+
+typedef enum GfPixelFormat {
+  GF_PIXEL_FORMAT_ARGB8888,
+  GF_PIXEL_FORMAT_XRGB8888,
+  GF_PIXEL_FORMAT_RGBA8888,
+  GF_PIXEL_FORMAT_RGB565,
+} GfPixelFormat;
+
+typedef struct GfClient GfClient;
+
+typedef struct GfEvent {
+  enum GfEventType kind;
+  uint32_t width;
+  uint32_t height;
+  uint32_t scancode;
+  uint32_t keysym;
+  bool pressed;
+  bool repeat;
+  uint64_t touch_id;
+  uint32_t surface;
+  double x;
+  double y;
+} GfEvent;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+uint32_t gf_client_abi_version(void);
+
+struct GfClient *gf_client_connect(const char *socket, const char *title);
+
+void gf_client_disconnect(struct GfClient *client);
+
+int gf_client_poll_event(struct GfClient *client, struct GfEvent *event);
+
+int gf_client_wait_event(struct GfClient *client, struct GfEvent *event);
+
+int gf_surface_create(struct GfClient *client,
+                      uint32_t parent,
+                      uint32_t width,
+                      uint32_t height,
+                      uint32_t *id);
+
+int gf_surface_destroy(struct GfClient *client, uint32_t surface);
+
+int gf_surface_size(struct GfClient *client, uint32_t surface, uint32_t *width, uint32_t *height);
+
+int gf_surface_set_format(struct GfClient *client, uint32_t surface, enum GfPixelFormat format);
+
+int gf_surface_set_position(struct GfClient *client, uint32_t surface, int32_t x, int32_t y);
+
+int gf_surface_attach(struct GfClient *client,
+                      uint32_t surface,
+                      const uint8_t *pixels,
+                      size_t len,
+                      uint32_t x,
+                      uint32_t y,
+                      uint32_t dx,
+                      uint32_t dy);
+
+int gf_surface_commit(struct GfClient *client, uint32_t surface);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* GF_CLIENT_H */
@@ -0,0 +1,312 @@
+// C ABI over the client toolkit, for tools not written in Rust. The header in
+// include/gf_client.h is generated from this file by the build script, and
+// the tests check the checked-in copy is current.
+//
+// Every function takes the client returned by gf_client_connect, which must
+// not be used after gf_client_disconnect. Functions returning int give 0 on
+// success and -1 on failure. Additions keep existing signatures and bump
+// GF_CLIENT_ABI_VERSION; nothing here changes shape within a version.
+
+// Explicit returns and typed self are house style; the pointer contract of
+// every function is the one above
+#![allow(
+  clippy::needless_return,
+  clippy::needless_arbitrary_self_type,
+  clippy::missing_safety_doc
+)]
+
+use libclient::{
+  Client, Event, KeyboardEvent, PixelFormat, Surface, SurfaceId, TouchEvent, WindowEvent,
+  MAIN_SURFACE
+};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+
+pub const GF_CLIENT_ABI_VERSION: u32 = 1;
+
+// Spelled out for the header; always MAIN_SURFACE
+pub const GF_MAIN_SURFACE: u32 = 0;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub enum GfPixelFormat {
+  Argb8888,
+  Xrgb8888,
+  Rgba8888,
+  Rgb565
+}
+
+impl From<GfPixelFormat> for PixelFormat {
+  fn from(format: GfPixelFormat) -> Self {
+    match format {
+      GfPixelFormat::Argb8888 => PixelFormat::Argb8888,
+      GfPixelFormat::Xrgb8888 => PixelFormat::Xrgb8888,
+      GfPixelFormat::Rgba8888 => PixelFormat::Rgba8888,
+      GfPixelFormat::Rgb565 => PixelFormat::Rgb565
+    }
+  }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GfEventType {
+  // An event with no C mapping yet
+  Other,
+  // The main surface was reallocated at width x height
+  Resize,
+  CloseRequest,
+  KeyboardEnter,
+  KeyboardLeave,
+  Key,
+  TouchDown,
+  TouchMotion,
+  TouchUp,
+  TouchCancel
+}
+
+// Only the fields of the event's type are meaningful, the rest are zero
+#[repr(C)]
+pub struct GfEvent {
+  pub kind: GfEventType,
+  // Resize
+  pub width: u32,
+  pub height: u32,
+  // Key
+  pub scancode: u32,
+  pub keysym: u32,
+  pub pressed: bool,
+  pub repeat: bool,
+  // Touch, in buffer pixels of the surface
+  pub touch_id: u64,
+  pub surface: u32,
+  pub x: f64,
+  pub y: f64
+}
+
+impl GfEvent {
+  fn new(kind: GfEventType) -> Self {
+    GfEvent {
+      kind,
+      width: 0,
+      height: 0,
+      scancode: 0,
+      keysym: 0,
+      pressed: false,
+      repeat: false,
+      touch_id: 0,
+      surface: 0,
+      x: 0.0,
+      y: 0.0
+    }
+  }
+
+  fn from_event(event: Event) -> Self {
+    match event {
+      Event::Resize { width, height } => GfEvent {
+        width: width as u32,
+        height: height as u32,
+        ..GfEvent::new(GfEventType::Resize)
+      },
+      Event::Window(WindowEvent::CloseRequest) => GfEvent::new(GfEventType::CloseRequest),
+      Event::Keyboard(KeyboardEvent::Enter(_)) => GfEvent::new(GfEventType::KeyboardEnter),
+      Event::Keyboard(KeyboardEvent::Leave) => GfEvent::new(GfEventType::KeyboardLeave),
+      Event::Keyboard(KeyboardEvent::Key { scancode, keysym, pressed, repeat }) => GfEvent {
+        scancode,
+        keysym,
+        pressed,
+        repeat,
+        ..GfEvent::new(GfEventType::Key)
+      },
+      Event::Touch(TouchEvent::Down { id, surface, x, y }) => GfEvent {
+        touch_id: id,
+        surface,
+        x,
+        y,
+        ..GfEvent::new(GfEventType::TouchDown)
+      },
+      Event::Touch(TouchEvent::Motion { id, x, y }) => {
+        GfEvent { touch_id: id, x, y, ..GfEvent::new(GfEventType::TouchMotion) }
+      }
+      Event::Touch(TouchEvent::Up(id)) => {
+        GfEvent { touch_id: id, ..GfEvent::new(GfEventType::TouchUp) }
+      }
+      Event::Touch(TouchEvent::Cancel(id)) => {
+        GfEvent { touch_id: id, ..GfEvent::new(GfEventType::TouchCancel) }
+      }
+      _ => GfEvent::new(GfEventType::Other)
+    }
+  }
+}
+
+// Opaque to C
+pub struct GfClient {
+  client: Client,
+  // Subsurfaces; the main surface lives in the client
+  surfaces: HashMap<SurfaceId, Surface>
+}
+
+impl GfClient {
+  fn surface(self: &mut Self, id: u32) -> Option<&mut Surface> {
+    if id == MAIN_SURFACE {
+      return Some(self.client.main_surface());
+    }
+    return self.surfaces.get_mut(&id);
+  }
+}
+
+fn status<T>(result: std::io::Result<T>) -> c_int {
+  match result {
+    Ok(_) => 0,
+    Err(_) => -1
+  }
+}
+
+unsafe fn opt_str<'a>(s: *const c_char) -> Option<&'a str> {
+  if s.is_null() {
+    return None;
+  }
+  return CStr::from_ptr(s).to_str().ok();
+}
+
+#[no_mangle]
+pub extern "C" fn gf_client_abi_version() -> u32 {
+  return GF_CLIENT_ABI_VERSION;
+}
+
+// A null socket path connects to the default socket, a null title leaves the
+// window untitled. Returns null if the connection fails.
+#[no_mangle]
+pub unsafe extern "C" fn gf_client_connect(
+  socket: *const c_char, title: *const c_char
+) -> *mut GfClient {
+  let mut builder = Client::builder();
+  if let Some(socket) = opt_str(socket) {
+    builder = builder.socket(socket);
+  }
+  if let Some(title) = opt_str(title) {
+    builder = builder.title(title);
+  }
+  match builder.connect() {
+    Ok(client) => Box::into_raw(Box::new(GfClient { client, surfaces: HashMap::new() })),
+    Err(_) => std::ptr::null_mut()
+  }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gf_client_disconnect(client: *mut GfClient) {
+  if !client.is_null() {
+    drop(Box::from_raw(client));
+  }
+}
+
+// Fills `event` and returns 1 if one was waiting, else returns 0
+#[no_mangle]
+pub unsafe extern "C" fn gf_client_poll_event(client: *mut GfClient, event: *mut GfEvent) -> c_int {
+  match (*client).client.poll_event() {
+    Some(e) => {
+      *event = GfEvent::from_event(e);
+      return 1;
+    }
+    None => return 0
+  }
+}
+
+// Blocks for the next event. Returns 0 once the server hung up.
+#[no_mangle]
+pub unsafe extern "C" fn gf_client_wait_event(client: *mut GfClient, event: *mut GfEvent) -> c_int {
+  match (*client).client.wait_event() {
+    Some(e) => {
+      *event = GfEvent::from_event(e);
+      return 1;
+    }
+    None => return 0
+  }
+}
+
+// Subsurface of `parent` at its origin, its id stored in `id`
+#[no_mangle]
+pub unsafe extern "C" fn gf_surface_create(
+  client: *mut GfClient, parent: u32, width: u32, height: u32, id: *mut u32
+) -> c_int {
+  let client = &mut *client;
+  let built = client.client.subsurface(parent).size(width as usize, height as usize).build();
+  match built {
+    Ok(surface) => {
+      *id = surface.id();
+      client.surfaces.insert(surface.id(), surface);
+      return 0;
+    }
+    Err(_) => return -1
+  }
+}
+
+// Destroys a subsurface with all of its children
+#[no_mangle]
+pub unsafe extern "C" fn gf_surface_destroy(client: *mut GfClient, surface: u32) -> c_int {
+  match (*client).surfaces.remove(&surface) {
+    Some(s) => status(s.destroy()),
+    None => -1
+  }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gf_surface_size(
+  client: *mut GfClient, surface: u32, width: *mut u32, height: *mut u32
+) -> c_int {
+  match (*client).surface(surface) {
+    Some(s) => {
+      let (w, h) = s.size();
+      *width = w as u32;
+      *height = h as u32;
+      return 0;
+    }
+    None => return -1
+  }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gf_surface_set_format(
+  client: *mut GfClient, surface: u32, format: GfPixelFormat
+) -> c_int {
+  match (*client).surface(surface) {
+    Some(s) => status(s.set_format(format.into())),
+    None => -1
+  }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gf_surface_set_position(
+  client: *mut GfClient, surface: u32, x: i32, y: i32
+) -> c_int {
+  match (*client).surface(surface) {
+    Some(s) => status(s.set_position(x, y)),
+    None => -1
+  }
+}
+
+// Attach the rect at (x, y) of size (dx, dy) of `pixels`, a whole buffer of
+// `len` bytes in the surface's format. Shown on the next commit.
+#[no_mangle]
+pub unsafe extern "C" fn gf_surface_attach(
+  client: *mut GfClient, surface: u32, pixels: *const u8, len: usize, x: u32, y: u32, dx: u32,
+  dy: u32
+) -> c_int {
+  if pixels.is_null() {
+    return -1;
+  }
+  let frame = std::slice::from_raw_parts(pixels, len);
+  let rect = (x as usize, y as usize, dx as usize, dy as usize);
+  match (*client).surface(surface) {
+    Some(s) => status(s.upload(frame, rect)),
+    None => -1
+  }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gf_surface_commit(client: *mut GfClient, surface: u32) -> c_int {
+  match (*client).surface(surface) {
+    Some(s) => status(s.commit()),
+    None => -1
+  }
+}
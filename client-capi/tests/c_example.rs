@@ -0,0 +1,100 @@
+// Checks on the C side of the bindings: the checked-in header and the example
+// program, built against the cdylib.
+
+// Explicit returns are house style
+#![allow(clippy::needless_return)]
+
+use libprotocol::{check_hello, recv_message, send_message, Handshake, Message};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+
+#[test]
+fn test_header_current() {
+  let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+  let generated = Path::new(env!("OUT_DIR")).join("gf_client.h");
+  let checked_in = std::fs::read_to_string(manifest.join("include/gf_client.h")).unwrap();
+  assert!(
+    std::fs::read_to_string(&generated).unwrap() == checked_in,
+    "include/gf_client.h is out of date, copy it from {}",
+    generated.display()
+  );
+}
+
+// Builds examples/client.c against the cdylib and runs it against a fake
+// server, checking what it sent
+#[test]
+fn test_c_example() {
+  let dir = std::env::temp_dir().join(format!("gfclient_capi_{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+  // Integration tests build the library with all its crate types, the cdylib
+  // landing next to the test binary in target/<profile>/deps
+  let lib_dir = std::env::current_exe().unwrap().parent().unwrap().to_owned();
+  let exe = dir.join("client");
+  let built = Command::new("cc")
+    .arg(manifest.join("examples/client.c"))
+    .arg("-I")
+    .arg(manifest.join("include"))
+    .arg("-L")
+    .arg(&lib_dir)
+    .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+    .arg("-lgfclient")
+    .arg("-o")
+    .arg(&exe)
+    .status()
+    .unwrap();
+  assert!(built.success());
+
+  let sock = dir.join("sock");
+  let listener = UnixListener::bind(&sock).unwrap();
+  let server = thread::spawn(move || -> Vec<Message> {
+    let (mut stream, _) = listener.accept().unwrap();
+    send_message(Message::Hello(Handshake::supported()), &mut stream).unwrap();
+    check_hello(recv_message(&mut stream).unwrap()).unwrap();
+    send_message(Message::ResizeEvent { width: 4, height: 2, is_main: true }, &mut stream)
+      .unwrap();
+    let mut got = Vec::new();
+    // Until the example hangs up
+    while let Ok(m) = recv_message(&mut stream) {
+      got.push(m);
+    }
+    return got;
+  });
+  let ran = Command::new(&exe).arg(&sock).status().unwrap();
+  let got = server.join().unwrap();
+  let _ = std::fs::remove_dir_all(&dir);
+  assert!(ran.success());
+
+  let summary: Vec<String> = got
+    .iter()
+    .map(|m| match m {
+      Message::SetTitle { title } => format!("title {}", title),
+      Message::DamageUpload { surface, x, y, dx, dy, .. } => {
+        format!("upload {} {} {} {} {}", surface, x, y, dx, dy)
+      }
+      Message::Commit { surface } => format!("commit {}", surface),
+      Message::CreateSubsurface { id, parent, width, height } => {
+        format!("subsurface {} {} {} {}", id, parent, width, height)
+      }
+      Message::SetFormat { surface, .. } => format!("format {}", surface),
+      Message::DestroySurface { id } => format!("destroy {}", id),
+      _ => "other".to_string()
+    })
+    .collect();
+  assert_eq!(
+    summary,
+    vec![
+      "title example",
+      "upload 0 0 0 4 2",
+      "commit 0",
+      "subsurface 1 0 2 2",
+      "format 1",
+      "upload 1 0 0 2 2",
+      "commit 1",
+      "commit 0",
+      "destroy 1"
+    ]
+  );
+}
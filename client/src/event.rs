@@ -0,0 +1,283 @@
+// Typed events delivered to the app, grouped by what they concern. Protocol
+// housekeeping like pings never shows up here.
+
+use crate::Connection;
+use libprotocol::{
+  ClientHandler, CloseReason, DecorationMode, Keymap, Keysym, Message, Modifiers, OutputId,
+  OutputInfo, Permission, Rect, SurfaceId, TouchId
+};
+use std::io::{Error, ErrorKind, Result};
+use std::sync::mpsc;
+
+#[derive(Debug)]
+pub enum Event {
+  // The main surface's buffer was reallocated at this size; upload fresh
+  // contents
+  Resize {
+    width: usize,
+    height: usize
+  },
+  // Answer to Surface::request_frame: the surface was shown, so now is the
+  // time to draw the next frame
+  Frame {
+    surface: SurfaceId,
+    time_ms: u32
+  },
+  Window(WindowEvent),
+  Output(OutputEvent),
+  Keyboard(KeyboardEvent),
+  Touch(TouchEvent),
+  TextInput(TextInputEvent),
+  InputMethod(InputMethodEvent),
+  Permission {
+    permission: Permission,
+    granted: bool
+  },
+  Capture(CaptureEvent),
+  Notification(NotificationEvent),
+  Hotkey(HotkeyEvent),
+  Idle(IdleEvent)
+}
+
+#[derive(Debug)]
+pub enum WindowEvent {
+  DecorationMode(DecorationMode),
+  // The user asked to close the window
+  CloseRequest,
+  State {
+    maximized: bool,
+    minimized: bool
+  },
+  // Whether the window is shown at all; hidden windows can stop drawing
+  Visible(bool)
+}
+
+#[derive(Debug)]
+pub enum OutputEvent {
+  // Added or changed
+  Changed(OutputInfo),
+  Removed(OutputId),
+  // Index of the workspace the output shows, out of `count`
+  Workspace {
+    output: OutputId,
+    current: u32,
+    count: u32
+  }
+}
+
+#[derive(Debug)]
+pub enum KeyboardEvent {
+  Keymap(Keymap),
+  Enter(Modifiers),
+  Leave,
+  Key {
+    scancode: u32,
+    keysym: Keysym,
+    pressed: bool,
+    repeat: bool
+  },
+  Modifiers(Modifiers)
+}
+
+#[derive(Debug)]
+pub enum TouchEvent {
+  Down {
+    id: TouchId,
+    surface: SurfaceId,
+    x: f64,
+    y: f64
+  },
+  Motion {
+    id: TouchId,
+    x: f64,
+    y: f64
+  },
+  Up(TouchId),
+  Cancel(TouchId)
+}
+
+#[derive(Debug)]
+pub enum TextInputEvent {
+  Preedit {
+    text: String,
+    cursor: Option<usize>
+  },
+  Commit(String)
+}
+
+// Only for the client registered as the input method
+#[derive(Debug)]
+pub enum InputMethodEvent {
+  Registered(bool),
+  Activate(Rect),
+  Cursor(Rect),
+  Deactivate
+}
+
+#[derive(Debug)]
+pub enum CaptureEvent {
+  // The contents of the buffer the capture was requested into
+  Done {
+    serial: u32,
+    data: Vec<u8>
+  },
+  Failed {
+    serial: u32,
+    reason: String
+  }
+}
+
+#[derive(Debug)]
+pub enum NotificationEvent {
+  Action {
+    id: u32,
+    action: String
+  },
+  Closed {
+    id: u32,
+    reason: CloseReason
+  }
+}
+
+#[derive(Debug)]
+pub enum HotkeyEvent {
+  Registered(u32),
+  Failed {
+    id: u32,
+    reason: String
+  },
+  Triggered {
+    id: u32,
+    pressed: bool
+  }
+}
+
+#[derive(Debug)]
+pub enum IdleEvent {
+  Idled,
+  Resumed
+}
+
+// Turns server messages into events for the app, answering pings itself
+pub(crate) struct EventQueue {
+  pub conn: Connection,
+  pub events: mpsc::Sender<Event>
+}
+
+impl EventQueue {
+  fn push(self: &Self, event: Event) -> Result<()> {
+    // The client may be gone already, which ends the reader
+    let closed = |_| Error::new(ErrorKind::BrokenPipe, "Client closed");
+    return self.events.send(event).map_err(closed);
+  }
+}
+
+impl ClientHandler for EventQueue {
+  fn resize(self: &mut Self, width: usize, height: usize, _is_main: bool) -> Result<()> {
+    return self.push(Event::Resize { width, height });
+  }
+  fn frame_done(self: &mut Self, surface: SurfaceId, time_ms: u32) -> Result<()> {
+    return self.push(Event::Frame { surface, time_ms });
+  }
+  fn ping(self: &mut Self) -> Result<()> {
+    return self.conn.send(Message::Pong);
+  }
+  fn permission(self: &mut Self, permission: Permission, granted: bool) -> Result<()> {
+    return self.push(Event::Permission { permission, granted });
+  }
+  fn capture_done(self: &mut Self, serial: u32, data: Vec<u8>) -> Result<()> {
+    return self.push(Event::Capture(CaptureEvent::Done { serial, data }));
+  }
+  fn capture_failed(self: &mut Self, serial: u32, reason: String) -> Result<()> {
+    return self.push(Event::Capture(CaptureEvent::Failed { serial, reason }));
+  }
+  fn notification_action(self: &mut Self, id: u32, action: String) -> Result<()> {
+    return self.push(Event::Notification(NotificationEvent::Action { id, action }));
+  }
+  fn notification_closed(self: &mut Self, id: u32, reason: CloseReason) -> Result<()> {
+    return self.push(Event::Notification(NotificationEvent::Closed { id, reason }));
+  }
+  fn decoration_mode(self: &mut Self, mode: DecorationMode) -> Result<()> {
+    return self.push(Event::Window(WindowEvent::DecorationMode(mode)));
+  }
+  fn close_request(self: &mut Self) -> Result<()> {
+    return self.push(Event::Window(WindowEvent::CloseRequest));
+  }
+  fn window_state(self: &mut Self, maximized: bool, minimized: bool) -> Result<()> {
+    return self.push(Event::Window(WindowEvent::State { maximized, minimized }));
+  }
+  fn visibility(self: &mut Self, visible: bool) -> Result<()> {
+    return self.push(Event::Window(WindowEvent::Visible(visible)));
+  }
+  fn idled(self: &mut Self) -> Result<()> {
+    return self.push(Event::Idle(IdleEvent::Idled));
+  }
+  fn resumed(self: &mut Self) -> Result<()> {
+    return self.push(Event::Idle(IdleEvent::Resumed));
+  }
+  fn output(self: &mut Self, info: OutputInfo) -> Result<()> {
+    return self.push(Event::Output(OutputEvent::Changed(info)));
+  }
+  fn output_removed(self: &mut Self, id: OutputId) -> Result<()> {
+    return self.push(Event::Output(OutputEvent::Removed(id)));
+  }
+  fn workspace(self: &mut Self, output: OutputId, current: u32, count: u32) -> Result<()> {
+    return self.push(Event::Output(OutputEvent::Workspace { output, current, count }));
+  }
+  fn keymap(self: &mut Self, keymap: Keymap) -> Result<()> {
+    return self.push(Event::Keyboard(KeyboardEvent::Keymap(keymap)));
+  }
+  fn keyboard_enter(self: &mut Self, modifiers: Modifiers) -> Result<()> {
+    return self.push(Event::Keyboard(KeyboardEvent::Enter(modifiers)));
+  }
+  fn keyboard_leave(self: &mut Self) -> Result<()> {
+    return self.push(Event::Keyboard(KeyboardEvent::Leave));
+  }
+  fn key(
+    self: &mut Self, scancode: u32, keysym: Keysym, pressed: bool, repeat: bool
+  ) -> Result<()> {
+    return self.push(Event::Keyboard(KeyboardEvent::Key { scancode, keysym, pressed, repeat }));
+  }
+  fn modifiers(self: &mut Self, modifiers: Modifiers) -> Result<()> {
+    return self.push(Event::Keyboard(KeyboardEvent::Modifiers(modifiers)));
+  }
+  fn hotkey_registered(self: &mut Self, id: u32) -> Result<()> {
+    return self.push(Event::Hotkey(HotkeyEvent::Registered(id)));
+  }
+  fn hotkey_failed(self: &mut Self, id: u32, reason: String) -> Result<()> {
+    return self.push(Event::Hotkey(HotkeyEvent::Failed { id, reason }));
+  }
+  fn hotkey(self: &mut Self, id: u32, pressed: bool) -> Result<()> {
+    return self.push(Event::Hotkey(HotkeyEvent::Triggered { id, pressed }));
+  }
+  fn preedit(self: &mut Self, text: String, cursor: Option<usize>) -> Result<()> {
+    return self.push(Event::TextInput(TextInputEvent::Preedit { text, cursor }));
+  }
+  fn commit_string(self: &mut Self, text: String) -> Result<()> {
+    return self.push(Event::TextInput(TextInputEvent::Commit(text)));
+  }
+  fn input_method_registered(self: &mut Self, granted: bool) -> Result<()> {
+    return self.push(Event::InputMethod(InputMethodEvent::Registered(granted)));
+  }
+  fn input_method_activate(self: &mut Self, cursor: Rect) -> Result<()> {
+    return self.push(Event::InputMethod(InputMethodEvent::Activate(cursor)));
+  }
+  fn input_method_cursor(self: &mut Self, cursor: Rect) -> Result<()> {
+    return self.push(Event::InputMethod(InputMethodEvent::Cursor(cursor)));
+  }
+  fn input_method_deactivate(self: &mut Self) -> Result<()> {
+    return self.push(Event::InputMethod(InputMethodEvent::Deactivate));
+  }
+  fn touch_down(self: &mut Self, id: TouchId, surface: SurfaceId, x: f64, y: f64) -> Result<()> {
+    return self.push(Event::Touch(TouchEvent::Down { id, surface, x, y }));
+  }
+  fn touch_motion(self: &mut Self, id: TouchId, x: f64, y: f64) -> Result<()> {
+    return self.push(Event::Touch(TouchEvent::Motion { id, x, y }));
+  }
+  fn touch_up(self: &mut Self, id: TouchId) -> Result<()> {
+    return self.push(Event::Touch(TouchEvent::Up(id)));
+  }
+  fn touch_cancel(self: &mut Self, id: TouchId) -> Result<()> {
+    return self.push(Event::Touch(TouchEvent::Cancel(id)));
+  }
+}
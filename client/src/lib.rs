@@ -0,0 +1,294 @@
+// Client toolkit for the compositor. Connect with `Client::builder()`, draw
+// into the main surface or subsurfaces made with `Client::subsurface`, and
+// read typed events with `poll_event` / `wait_event`.
+
+// Explicit returns and typed self are house style
+#![allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
+
+mod event;
+mod surface;
+
+pub use event::{
+  CaptureEvent, Event, HotkeyEvent, IdleEvent, InputMethodEvent, KeyboardEvent, NotificationEvent,
+  OutputEvent, TextInputEvent, TouchEvent, WindowEvent
+};
+pub use libprotocol::{
+  keysym_to_char, CaptureBuffer, CaptureSource, CloseReason, CodecKind, Compression,
+  DecorationMode, Keymap, Keysym, Message, Modifiers, OutputId, OutputInfo, Permission,
+  PixelFormat, Rect, Shortcut, SurfaceId, TouchId, Transform, Urgency, DEFAULT_SOCK_PATH,
+  MAIN_SURFACE
+};
+pub use surface::{Surface, SurfaceBuilder};
+
+use event::EventQueue;
+use libprotocol::{
+  check_hello, dispatch_to_client, negotiate_codec, negotiate_compression, recv_message,
+  send_message, Codec, Handshake, DEFAULT_SURFACE_FORMAT, SUPPORTED_COMPRESSION
+};
+use std::io::{BufReader, Error, ErrorKind};
+use std::os::unix::net::UnixStream;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+pub(crate) fn invalid_input(reason: &str) -> Error {
+  return Error::new(ErrorKind::InvalidInput, reason);
+}
+
+// Write half of the socket, shared by the client and its surfaces, along with
+// what was agreed at Hello
+#[derive(Clone)]
+pub(crate) struct Connection {
+  stream: Arc<Mutex<UnixStream>>,
+  codec: Arc<dyn Codec>,
+  pub compression: Compression,
+  pub formats: Arc<Vec<PixelFormat>>
+}
+
+impl Connection {
+  pub fn send(self: &Self, msg: Message) -> std::io::Result<()> {
+    return self.codec.write(&msg, &mut *self.stream.lock().unwrap());
+  }
+}
+
+pub struct ClientBuilder {
+  socket: String,
+  title: Option<String>,
+  decorations: Option<DecorationMode>,
+  format: Option<PixelFormat>,
+  scale: u32,
+  codec: CodecKind
+}
+
+impl ClientBuilder {
+  pub fn socket(mut self: Self, path: &str) -> Self {
+    self.socket = path.to_string();
+    return self;
+  }
+
+  pub fn title(mut self: Self, title: &str) -> Self {
+    self.title = Some(title.to_string());
+    return self;
+  }
+
+  // Ask for a decoration mode; the answer arrives as a WindowEvent
+  pub fn decorations(mut self: Self, mode: DecorationMode) -> Self {
+    self.decorations = Some(mode);
+    return self;
+  }
+
+  // Pixel format of the main surface
+  pub fn format(mut self: Self, format: PixelFormat) -> Self {
+    self.format = Some(format);
+    return self;
+  }
+
+  pub fn buffer_scale(mut self: Self, scale: u32) -> Self {
+    self.scale = scale;
+    return self;
+  }
+
+  // Encoding of messages after Hello, bincode by default. JSON lines make the
+  // traffic readable, e.g. with strace or socat.
+  pub fn codec(mut self: Self, codec: CodecKind) -> Self {
+    self.codec = codec;
+    return self;
+  }
+
+  pub fn connect(self: Self) -> std::io::Result<Client> {
+    let mut stream = UnixStream::connect(&self.socket)?;
+    let server = check_hello(recv_message(&mut stream)?)?;
+    let hello = Handshake { codecs: vec![self.codec], ..Handshake::supported() };
+    send_message(Message::Hello(hello), &mut stream)?;
+    let codec = negotiate_codec(&server.codecs, &[self.codec]);
+    let conn = Connection {
+      stream: Arc::new(Mutex::new(stream.try_clone()?)),
+      codec: Arc::from(codec.codec()),
+      compression: negotiate_compression(&server.compression, SUPPORTED_COMPRESSION),
+      formats: Arc::new(server.formats)
+    };
+
+    let (events_in, events) = mpsc::channel();
+    let mut queue = EventQueue { conn: conn.clone(), events: events_in };
+    thread::spawn(move || -> std::io::Result<()> {
+      let codec = codec.codec();
+      let mut stream = BufReader::new(stream);
+      loop {
+        dispatch_to_client(&mut queue, codec.read(&mut stream)?)?;
+      }
+    });
+
+    // Sized by the first Resize event
+    let main = Surface::new(MAIN_SURFACE, 0, 0, DEFAULT_SURFACE_FORMAT, conn.clone());
+    let mut client = Client { conn, events, main, next_surface: MAIN_SURFACE + 1 };
+    if let Some(title) = self.title {
+      client.set_title(&title)?;
+    }
+    if let Some(mode) = self.decorations {
+      client.send(Message::SetDecorationMode { mode })?;
+    }
+    if let Some(format) = self.format {
+      client.main.set_format(format)?;
+    }
+    if self.scale != 1 {
+      client.main.set_buffer_scale(self.scale)?;
+    }
+    return Ok(client);
+  }
+}
+
+pub struct Client {
+  conn: Connection,
+  events: mpsc::Receiver<Event>,
+  main: Surface,
+  next_surface: SurfaceId
+}
+
+impl Client {
+  pub fn builder() -> ClientBuilder {
+    ClientBuilder {
+      socket: DEFAULT_SOCK_PATH.to_string(),
+      title: None,
+      decorations: None,
+      format: None,
+      scale: 1,
+      codec: CodecKind::Bincode
+    }
+  }
+
+  pub fn main_surface(self: &mut Self) -> &mut Surface {
+    return &mut self.main;
+  }
+
+  pub fn subsurface(self: &mut Self, parent: SurfaceId) -> SurfaceBuilder<'_> {
+    return SurfaceBuilder::new(self, parent);
+  }
+
+  // Pixel formats the server accepts uploads in
+  pub fn formats(self: &Self) -> &[PixelFormat] {
+    return &self.conn.formats;
+  }
+
+  // Raw protocol access for requests without a wrapper here
+  pub fn send(self: &Self, msg: Message) -> std::io::Result<()> {
+    return self.conn.send(msg);
+  }
+
+  pub fn set_title(self: &Self, title: &str) -> std::io::Result<()> {
+    return self.send(Message::SetTitle { title: title.to_string() });
+  }
+
+  pub fn set_window_state(self: &Self, maximized: bool, minimized: bool) -> std::io::Result<()> {
+    return self.send(Message::SetWindowState { maximized, minimized });
+  }
+
+  pub fn request_permission(self: &Self, permission: Permission) -> std::io::Result<()> {
+    return self.send(Message::RequestPermission { permission });
+  }
+
+  fn track(self: &mut Self, event: &Event) {
+    if let Event::Resize { width, height } = event {
+      self.main.set_size(*width, *height);
+    }
+  }
+
+  // Next event if one is waiting. None also once the server hung up.
+  pub fn poll_event(self: &mut Self) -> Option<Event> {
+    let event = self.events.try_recv().ok()?;
+    self.track(&event);
+    return Some(event);
+  }
+
+  // Blocks for the next event; None once the server hung up
+  pub fn wait_event(self: &mut Self) -> Option<Event> {
+    let event = self.events.recv().ok()?;
+    self.track(&event);
+    return Some(event);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use libprotocol::decode;
+  use std::io::{BufRead, Write};
+  use std::os::unix::net::UnixListener;
+
+  #[test]
+  fn test_connect_and_upload() {
+    let path = std::env::temp_dir().join(format!("gfclient_test_{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let server = thread::spawn(move || -> Vec<Message> {
+      let (mut stream, _) = listener.accept().unwrap();
+      send_message(Message::Hello(Handshake::supported()), &mut stream).unwrap();
+      check_hello(recv_message(&mut stream).unwrap()).unwrap();
+      send_message(Message::Ping, &mut stream).unwrap();
+      send_message(Message::ResizeEvent { width: 2, height: 2, is_main: true }, &mut stream)
+        .unwrap();
+      let mut got = Vec::new();
+      loop {
+        match recv_message(&mut stream).unwrap() {
+          Message::Commit { .. } => return got,
+          m => got.push(m)
+        }
+      }
+    });
+
+    let mut client =
+      Client::builder().socket(path.to_str().unwrap()).title("test").connect().unwrap();
+    match client.wait_event() {
+      Some(Event::Resize { width: 2, height: 2 }) => {}
+      e => panic!("Unexpected event {:?}", e)
+    }
+    assert_eq!(client.main_surface().size(), (2, 2));
+    let frame: Vec<u8> = (0..16).collect();
+    assert!(client.main_surface().upload(&frame[..4], (0, 0, 1, 1)).is_err());
+    let huge = (1, usize::MAX, 1, usize::MAX);
+    assert!(client.main_surface().upload(&frame, huge).is_err());
+    client.main_surface().upload(&frame, (1, 0, 1, 2)).unwrap();
+    client.main_surface().commit().unwrap();
+
+    let got = server.join().unwrap();
+    let _ = std::fs::remove_file(&path);
+    // The pong races the title, both come before the upload
+    assert!(got.iter().any(|m| matches!(m, Message::SetTitle { .. })));
+    assert!(got.iter().any(|m| matches!(m, Message::Pong)));
+    match &got[2] {
+      Message::DamageUpload { x: 1, y: 0, dx: 1, dy: 2, compression, data, .. } => {
+        assert_eq!(decode(data, *compression, 4, 8).unwrap(), vec![4, 5, 6, 7, 12, 13, 14, 15]);
+      }
+      _ => panic!("Expected a damage upload")
+    }
+  }
+
+  #[test]
+  fn test_json_lines() {
+    let path = std::env::temp_dir().join(format!("gfclient_json_{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let server = thread::spawn(move || -> String {
+      let (mut stream, _) = listener.accept().unwrap();
+      send_message(Message::Hello(Handshake::supported()), &mut stream).unwrap();
+      let hello = check_hello(recv_message(&mut stream).unwrap()).unwrap();
+      assert_eq!(hello.codecs, vec![CodecKind::JsonLines]);
+      let events = "\"Ping\"\n{\"ResizeEvent\":{\"width\":3,\"height\":1,\"is_main\":true}}\n";
+      stream.write_all(events.as_bytes()).unwrap();
+      let mut lines = BufReader::new(stream).lines();
+      return format!("{}\n{}", lines.next().unwrap().unwrap(), lines.next().unwrap().unwrap());
+    });
+
+    let mut client = Client::builder()
+      .socket(path.to_str().unwrap())
+      .codec(CodecKind::JsonLines)
+      .title("json")
+      .connect()
+      .unwrap();
+    assert!(matches!(client.wait_event(), Some(Event::Resize { width: 3, height: 1 })));
+    let got = server.join().unwrap();
+    let _ = std::fs::remove_file(&path);
+    // The pong races the title
+    let mut lines: Vec<&str> = got.lines().collect();
+    lines.sort();
+    assert_eq!(lines, vec!["\"Pong\"", "{\"SetTitle\":{\"title\":\"json\"}}"]);
+  }
+}
@@ -0,0 +1,213 @@
+// Client-side handles for surfaces. A handle remembers the surface's size,
+// format and scale so uploads can be checked and encoded before they go out.
+
+use crate::{invalid_input, Connection};
+use libprotocol::{Message, PixelFormat, SurfaceId, MAIN_SURFACE};
+
+pub struct Surface {
+  id: SurfaceId,
+  width: usize,
+  height: usize,
+  format: PixelFormat,
+  scale: u32,
+  conn: Connection
+}
+
+impl Surface {
+  pub(crate) fn new(
+    id: SurfaceId, width: usize, height: usize, format: PixelFormat, conn: Connection
+  ) -> Self {
+    Surface { id, width, height, format, scale: 1, conn }
+  }
+
+  pub fn id(self: &Self) -> SurfaceId {
+    return self.id;
+  }
+
+  // Buffer size in pixels
+  pub fn size(self: &Self) -> (usize, usize) {
+    return (self.width, self.height);
+  }
+
+  pub fn format(self: &Self) -> PixelFormat {
+    return self.format;
+  }
+
+  pub(crate) fn set_size(self: &mut Self, width: usize, height: usize) {
+    self.width = width;
+    self.height = height;
+  }
+
+  // Upload the rect at (x, y) of size (dx, dy) of `frame`, a whole buffer in
+  // the surface's format. Becomes visible on the next commit.
+  pub fn upload(
+    self: &Self, frame: &[u8], (x, y, dx, dy): (usize, usize, usize, usize)
+  ) -> std::io::Result<()> {
+    let bpp = self.format.bytes_per_pixel();
+    if frame.len() != bpp * self.width * self.height {
+      return Err(invalid_input("Frame does not match the surface size"));
+    }
+    let outside = |start: usize, len: usize, size: usize| {
+      return start.checked_add(len).is_none_or(|end| end > size);
+    };
+    if outside(x, dx, self.width) || outside(y, dy, self.height) {
+      return Err(invalid_input("Damage rect out of surface bounds"));
+    }
+    let row_len = bpp * dx;
+    let mut data = Vec::with_capacity(row_len * dy);
+    for row in y..(y + dy) {
+      let ind = bpp * (row * self.width + x);
+      data.extend_from_slice(&frame[ind..ind + row_len]);
+    }
+    let compression = self.conn.compression;
+    return self.conn.send(Message::DamageUpload {
+      surface: self.id,
+      x,
+      y,
+      dx,
+      dy,
+      compression,
+      data: libprotocol::encode(&data, compression, bpp)
+    });
+  }
+
+  pub fn upload_all(self: &Self, frame: &[u8]) -> std::io::Result<()> {
+    return self.upload(frame, (0, 0, self.width, self.height));
+  }
+
+  pub fn commit(self: &Self) -> std::io::Result<()> {
+    return self.conn.send(Message::Commit { surface: self.id });
+  }
+
+  // Get an Event::Frame when the surface is next shown
+  pub fn request_frame(self: &Self) -> std::io::Result<()> {
+    return self.conn.send(Message::RequestFrame { surface: self.id });
+  }
+
+  pub fn set_format(self: &mut Self, format: PixelFormat) -> std::io::Result<()> {
+    if !self.conn.formats.contains(&format) {
+      return Err(invalid_input("Server does not support the format"));
+    }
+    self.format = format;
+    return self.conn.send(Message::SetFormat { surface: self.id, format });
+  }
+
+  // The main surface is reallocated by the server at the new scale, which
+  // arrives as a Resize event
+  pub fn set_buffer_scale(self: &mut Self, scale: u32) -> std::io::Result<()> {
+    if scale == 0 {
+      return Err(invalid_input("Buffer scale must be positive"));
+    }
+    self.scale = scale;
+    return self.conn.send(Message::SetBufferScale { surface: self.id, scale });
+  }
+
+  pub fn buffer_scale(self: &Self) -> u32 {
+    return self.scale;
+  }
+
+  // Position relative to the parent, applied on the parent's next commit
+  pub fn set_position(self: &Self, x: i32, y: i32) -> std::io::Result<()> {
+    return self.conn.send(Message::SetSubsurfacePosition { id: self.id, x, y });
+  }
+
+  pub fn place_above(self: &Self, sibling: &Surface) -> std::io::Result<()> {
+    return self.conn.send(Message::PlaceAbove { id: self.id, sibling: sibling.id });
+  }
+
+  pub fn place_below(self: &Self, sibling: &Surface) -> std::io::Result<()> {
+    return self.conn.send(Message::PlaceBelow { id: self.id, sibling: sibling.id });
+  }
+
+  pub fn set_sync(self: &Self, sync: bool) -> std::io::Result<()> {
+    return self.conn.send(Message::SetSubsurfaceSync { id: self.id, sync });
+  }
+
+  // Destroys the subsurface and all of its children
+  pub fn destroy(self: Self) -> std::io::Result<()> {
+    if self.id == MAIN_SURFACE {
+      return Err(invalid_input("The main surface lives as long as the connection"));
+    }
+    return self.conn.send(Message::DestroySurface { id: self.id });
+  }
+}
+
+pub struct SurfaceBuilder<'a> {
+  client: &'a mut crate::Client,
+  parent: SurfaceId,
+  width: usize,
+  height: usize,
+  position: (i32, i32),
+  format: Option<PixelFormat>,
+  scale: u32,
+  sync: bool
+}
+
+impl<'a> SurfaceBuilder<'a> {
+  pub(crate) fn new(client: &'a mut crate::Client, parent: SurfaceId) -> Self {
+    SurfaceBuilder {
+      client,
+      parent,
+      width: 1,
+      height: 1,
+      position: (0, 0),
+      format: None,
+      scale: 1,
+      sync: true
+    }
+  }
+
+  pub fn size(mut self: Self, width: usize, height: usize) -> Self {
+    self.width = width;
+    self.height = height;
+    return self;
+  }
+
+  pub fn position(mut self: Self, x: i32, y: i32) -> Self {
+    self.position = (x, y);
+    return self;
+  }
+
+  pub fn format(mut self: Self, format: PixelFormat) -> Self {
+    self.format = Some(format);
+    return self;
+  }
+
+  pub fn buffer_scale(mut self: Self, scale: u32) -> Self {
+    self.scale = scale;
+    return self;
+  }
+
+  // Show commits immediately instead of with the parent's
+  pub fn desync(mut self: Self) -> Self {
+    self.sync = false;
+    return self;
+  }
+
+  pub fn build(self: Self) -> std::io::Result<Surface> {
+    let id = self.client.next_surface;
+    self.client.next_surface += 1;
+    let conn = self.client.conn.clone();
+    conn.send(Message::CreateSubsurface {
+      id,
+      parent: self.parent,
+      width: self.width,
+      height: self.height
+    })?;
+    let mut surface =
+      Surface::new(id, self.width, self.height, libprotocol::DEFAULT_SURFACE_FORMAT, conn);
+    if let Some(format) = self.format {
+      surface.set_format(format)?;
+    }
+    if self.scale != 1 {
+      surface.set_buffer_scale(self.scale)?;
+    }
+    if self.position != (0, 0) {
+      surface.set_position(self.position.0, self.position.1)?;
+    }
+    if !self.sync {
+      surface.set_sync(false)?;
+    }
+    return Ok(surface);
+  }
+}
@@ -0,0 +1,141 @@
+// Where composed frames go. A backend owns the swapchain frames are drawn
+// into and shows its front buffer on present. It also keeps the compositor's
+// clock. The emulator binary shows frames in a host window; the headless
+// backend keeps them in memory on a virtual clock that only moves when told
+// to, so runs in CI and tests can check composed pixels without a display or
+// GPU.
+
+use crate::render::Renderer;
+use crate::swapchain::{copy_rects, Swapchain, SWAPCHAIN_LENGTH};
+use crate::SharedState;
+use std::io::Result;
+use std::time::{Duration, Instant};
+
+pub trait Backend {
+  fn now(self: &Self) -> Instant;
+  fn swapchain(self: &mut Self) -> &mut Swapchain;
+  // Show the swapchain's front buffer, which differs from the frame shown
+  // before in its damage
+  fn present(self: &mut Self) -> Result<()>;
+}
+
+pub struct HeadlessBackend {
+  pub width: usize,
+  pub height: usize,
+  swapchain: Swapchain,
+  clock: Instant,
+  pub presented: u64
+}
+
+impl HeadlessBackend {
+  pub fn new(width: usize, height: usize) -> Self {
+    HeadlessBackend {
+      width,
+      height,
+      swapchain: Swapchain::new(width, height, SWAPCHAIN_LENGTH),
+      clock: Instant::now(),
+      presented: 0
+    }
+  }
+
+  pub fn advance(self: &mut Self, by: Duration) {
+    self.clock += by;
+  }
+
+  // A pixel of the frame last presented
+  pub fn pixel(self: &Self, x: usize, y: usize) -> [u8; 4] {
+    let ind = 4 * (y * self.width + x);
+    let front = self.swapchain.front_buffer();
+    return [front[ind], front[ind + 1], front[ind + 2], front[ind + 3]];
+  }
+}
+
+impl Backend for HeadlessBackend {
+  fn now(self: &Self) -> Instant {
+    return self.clock;
+  }
+
+  fn swapchain(self: &mut Self) -> &mut Swapchain {
+    return &mut self.swapchain;
+  }
+
+  fn present(self: &mut Self) -> Result<()> {
+    self.presented += 1;
+    return Ok(());
+  }
+}
+
+// Run time-driven work and draw the next frame into the swapchain, returning
+// whether anything changed and it should be presented
+pub fn draw_frame(
+  state: &SharedState, renderer: &mut Renderer, backend: &mut dyn Backend
+) -> bool {
+  let mut server = state.lock().unwrap();
+  server.tick(backend.now());
+  return renderer.render(&mut server, backend.swapchain());
+}
+
+// Present the drawn frame and make it available for capture. Composed output
+// is opaque, so the straight RGBA frame is also premultiplied.
+pub fn present_frame(state: &SharedState, backend: &mut dyn Backend) -> Result<()> {
+  backend.present()?;
+  let swapchain = backend.swapchain();
+  let (frame, damage) = (swapchain.front_buffer(), swapchain.damage());
+  let front = &mut state.lock().unwrap().front;
+  copy_rects(&mut front.data, frame, front.width, front.height, damage);
+  return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Message, PixelFormat, Rect, ServerState, Urgency, BACKGROUND, MAIN_SURFACE};
+  use std::sync::{mpsc, Arc, Mutex};
+
+  #[test]
+  fn test_headless() {
+    let (w, h) = (320, 240);
+    let state = Arc::new(Mutex::new(ServerState::new(w, h, Vec::new())));
+    let mut backend = HeadlessBackend::new(w, h);
+    let mut renderer = Renderer::new();
+    let (outbox, events) = mpsc::channel();
+    let client = {
+      let mut server = state.lock().unwrap();
+      let client = server.add_client(outbox);
+      server.configure_window(client, Rect::new(10, 20, 16, 16));
+      let surfaces = &mut server.clients.get_mut(&client).unwrap().surfaces;
+      let main = surfaces.get_mut(MAIN_SURFACE).unwrap();
+      main.format = PixelFormat::Rgba8888;
+      main.patch(0, 0, 16, 16, &[0x20, 0x40, 0x60, 0xff].repeat(256)).unwrap();
+      surfaces.commit(MAIN_SURFACE).unwrap();
+      client
+    };
+
+    assert!(draw_frame(&state, &mut renderer, &mut backend));
+    present_frame(&state, &mut backend).unwrap();
+    assert_eq!(backend.pixel(10, 20), [0x20, 0x40, 0x60, 0xff]);
+    assert_eq!(backend.pixel(26, 20), BACKGROUND);
+    assert_eq!(state.lock().unwrap().front.data[..4], BACKGROUND);
+    // nothing changed, so there is nothing to present
+    assert!(!draw_frame(&state, &mut renderer, &mut backend));
+    assert_eq!(backend.presented, 1);
+
+    // a notification shows until the virtual clock reaches its timeout
+    let now = backend.now();
+    let (summary, body) = ("Build".to_string(), "Done".to_string());
+    let mut server = state.lock().unwrap();
+    server.notifications.post(client, 1, summary, body, Urgency::Normal, Some(500), vec![], now);
+    drop(server);
+    assert!(draw_frame(&state, &mut renderer, &mut backend));
+    present_frame(&state, &mut backend).unwrap();
+    assert_ne!(backend.pixel(w - 20, 20), BACKGROUND);
+    backend.advance(Duration::from_millis(499));
+    assert!(!draw_frame(&state, &mut renderer, &mut backend));
+    assert!(!events.try_iter().any(|m| matches!(m, Message::NotificationClosedEvent { .. })));
+    backend.advance(Duration::from_millis(1));
+    assert!(draw_frame(&state, &mut renderer, &mut backend));
+    present_frame(&state, &mut backend).unwrap();
+    assert!(events.try_iter().any(|m| matches!(m, Message::NotificationClosedEvent { .. })));
+    assert_eq!(backend.pixel(w - 20, 20), BACKGROUND);
+  }
+}
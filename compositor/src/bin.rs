@@ -1,114 +1,411 @@
+// Explicit returns and typed self are house style
+#![allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
+
+mod recorder;
+
+use libcompositor::{
+  bind_unix_listener, copy_rects, run_frame, Backend, CloseReason, FrameScheduler,
+  HeadlessBackend, Keymap, Message, NotificationClick, OutputInfo, Permission, Renderer,
+  ServerState, SharedState, Swapchain, Transform, SWAPCHAIN_LENGTH
+};
 use pixels::{Error, Pixels, SurfaceTexture};
-use std::{mem, thread, time};
+use recorder::{RecordFormat, Recorder};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::{env, fs, io, thread, time};
 use winit::{
-  dpi::LogicalSize,
-  event::{DeviceEvent, ElementState, Event, KeyboardInput, ScanCode, WindowEvent},
+  dpi::PhysicalSize,
+  event::{DeviceEvent, ElementState, Event, MouseButton, TouchPhase, WindowEvent},
   event_loop::{ControlFlow, EventLoop}
 };
 const DISPLAY_WIDTH: usize = 800;
 const DISPLAY_HEIGHT: usize = 600;
-const DISPLAY_PIXELS: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
+
+// Frames shown in a window on the host through the GPU
+struct PixelsBackend {
+  swapchain: Swapchain,
+  pixels: Pixels
+}
+
+impl Backend for PixelsBackend {
+  fn now(self: &Self) -> time::Instant {
+    return time::Instant::now();
+  }
+
+  fn swapchain(self: &mut Self) -> &mut Swapchain {
+    return &mut self.swapchain;
+  }
+
+  // The texture upload keeps the last frame, so only what changed is copied
+  fn present(self: &mut Self) -> io::Result<()> {
+    let swapchain = &self.swapchain;
+    let (width, height) = (swapchain.width, swapchain.height);
+    let (frame, damage) = (swapchain.front_buffer(), swapchain.damage());
+    copy_rects(self.pixels.get_frame(), frame, width, height, damage);
+    return self.pixels.render().map_err(|e| io::Error::other(e.to_string()));
+  }
+}
 
 struct CompositorState {
-  front_buffer: Pixels,
-  back_buffer: Pixels,
-  parity: usize // DEBUG only
+  backend: PixelsBackend,
+  renderer: Renderer,
+  server: SharedState,
+  recorder: Option<Recorder>,
+  // Last cursor position in frame coordinates, if over the frame
+  cursor: Option<(usize, usize)>,
+  scheduler: FrameScheduler
 }
 
-impl CompositorState {
-  pub fn swap(self: &mut Self) {
-    mem::swap(&mut self.front_buffer, &mut self.back_buffer);
-    self.parity = 1 - self.parity;
+fn record(recorder: &mut Option<Recorder>, backend: &mut dyn Backend) {
+  if let Some(r) = recorder {
+    let now = backend.now();
+    if let Err(e) = r.record(backend.swapchain().front_buffer(), now) {
+      println!("Recording failed, stopping: {:?}", e);
+      *recorder = None;
+    }
   }
 }
 
+// The frame maps 1:1 onto host pixels, so on a HiDPI host the emulated output
+// is HiDPI too
 fn create_window(
-  title: &str, w: u32, h: u32, scale: f64, event_loop: &EventLoop<()>
+  title: &str, w: u32, h: u32, event_loop: &EventLoop<()>
 ) -> winit::window::Window {
   let window = winit::window::WindowBuilder::new()
     .with_title(title)
     .with_resizable(false)
-    .build(&event_loop)
+    .build(event_loop)
     .unwrap();
-  let size = LogicalSize::new((w as f64) * scale, (h as f64) * scale);
-  window.set_inner_size(size);
+  window.set_inner_size(PhysicalSize::new(w, h));
   return window;
 }
 
-fn compositor_step(state: &mut CompositorState) -> bool {
-  let frame = state.back_buffer.get_frame();
-  for pix in frame.chunks_exact_mut(4) {
-    let color: [u8; 4] = [0x99, 0x99, 0x99, 0xff];
-    pix.copy_from_slice(&color);
+// Dismiss or invoke an action on the notification under a click, returning
+// whether there was one
+fn click_notifications(server: &mut ServerState, (x, y): (usize, usize)) -> bool {
+  let hit = server.notifications.click(x as i32, y as i32, DISPLAY_WIDTH as i32);
+  if let Some((n, click)) = hit {
+    if let NotificationClick::Action(action) = click {
+      server.send(n.client, Message::NotificationActionEvent { id: n.id, action });
+    }
+    let reason = CloseReason::Dismissed;
+    server.send(n.client, Message::NotificationClosedEvent { id: n.id, reason });
+    return true;
+  }
+  return false;
+}
+
+// Permissions to grant clients, given as `--allow=<name>` arguments
+fn parse_grantable() -> Vec<Permission> {
+  let mut grantable = Vec::new();
+  for arg in env::args().skip(1) {
+    if let Some(name) = arg.strip_prefix("--allow=") {
+      match Permission::from_name(name) {
+        Some(p) => grantable.push(p),
+        None => println!("Unknown permission {}", name)
+      }
+    }
   }
+  return grantable;
+}
 
-  // DEBUG front vs back
-  for i in (DISPLAY_HEIGHT - 16)..(DISPLAY_HEIGHT - 4) {
-    let color: [u8; 4] = if state.parity == 0 {
-      [0xff, 0x00, 0x00, 0xff]
+// Recording is enabled by `--record=<path>`, optionally with
+// `--record-format=<png|ppm|y4m>` (default png)
+fn parse_recorder() -> Option<Recorder> {
+  let mut path = None;
+  let mut format = RecordFormat::Png;
+  for arg in env::args().skip(1) {
+    if let Some(p) = arg.strip_prefix("--record=") {
+      path = Some(p.to_string());
     }
-    else {
-      [0x00, 0x00, 0xff, 0xff]
-    };
-    for j in (DISPLAY_WIDTH - 16)..(DISPLAY_WIDTH - 4) {
-      let ind = 4 * (i * DISPLAY_WIDTH + j);
-      let pix = frame.get_mut(ind..ind + 4).unwrap();
-      pix.copy_from_slice(&color);
+    else if let Some(name) = arg.strip_prefix("--record-format=") {
+      match RecordFormat::from_name(name) {
+        Some(f) => format = f,
+        None => println!("Unknown recording format {}", name)
+      }
+    }
+  }
+  let path = path?;
+  match Recorder::new(Path::new(&path), format, DISPLAY_WIDTH, DISPLAY_HEIGHT) {
+    Ok(recorder) => {
+      return Some(recorder);
+    }
+    Err(e) => {
+      println!("Failed to start recording to {}: {:?}", path, e);
+      return None;
     }
   }
+}
 
-  // FORNOW
-  thread::sleep(time::Duration::from_millis(100));
-  return true;
+// The emulated output as the window on the host monitor shows it. Hosts
+// don't report physical sizes, so assume 96 dpi at the host's scale. The
+// output scale is the host's unless overridden. winit only lists the modes a
+// monitor supports, not the one in use, so the refresh rate is unknown.
+fn describe_output(window: &winit::window::Window, scale: Option<f64>) -> OutputInfo {
+  let monitor = window.current_monitor();
+  let name = monitor.as_ref().and_then(|m| m.name()).unwrap_or_else(|| "unknown".to_string());
+  let mm_per_pixel = 25.4 / (96.0 * window.scale_factor());
+  OutputInfo {
+    id: 0,
+    name: format!("emulator on {}", name),
+    width: DISPLAY_WIDTH,
+    height: DISPLAY_HEIGHT,
+    physical_width_mm: (DISPLAY_WIDTH as f64 * mm_per_pixel).round() as u32,
+    physical_height_mm: (DISPLAY_HEIGHT as f64 * mm_per_pixel).round() as u32,
+    scale: scale.unwrap_or_else(|| window.scale_factor()),
+    refresh_mhz: 0,
+    transform: Transform::Normal
+  }
+}
+
+// `--headless` runs without a window or GPU, composing into memory on a
+// virtual clock that moves a refresh at a time. Clients connect as usual and
+// `--record` captures what they would have shown.
+fn run_headless(server: SharedState) {
+  let scale = parse_scale().unwrap_or(1.0);
+  let mm_per_pixel = 25.4 / (96.0 * scale);
+  server.lock().unwrap().set_output(OutputInfo {
+    id: 0,
+    name: "headless".to_string(),
+    width: DISPLAY_WIDTH,
+    height: DISPLAY_HEIGHT,
+    physical_width_mm: (DISPLAY_WIDTH as f64 * mm_per_pixel).round() as u32,
+    physical_height_mm: (DISPLAY_HEIGHT as f64 * mm_per_pixel).round() as u32,
+    scale,
+    refresh_mhz: 0,
+    transform: Transform::Normal
+  });
+  let mut backend = HeadlessBackend::new(DISPLAY_WIDTH, DISPLAY_HEIGHT);
+  let mut renderer = Renderer::new();
+  let mut recorder = parse_recorder();
+  let mut scheduler = FrameScheduler::new(backend.now());
+  loop {
+    // Presenting in memory can't fail
+    if run_frame(&server, &mut renderer, &mut backend, &mut scheduler).unwrap() {
+      record(&mut recorder, &mut backend);
+    }
+    // Keep the virtual clock in step with clients, which run in real time
+    let wait = scheduler.deadline().saturating_duration_since(backend.now());
+    thread::sleep(wait);
+    backend.advance(wait);
+  }
+}
+
+// Keyboard layout from `--keymap=<path>` (see Keymap::parse), and key repeat
+// from `--repeat=<delay ms>,<rate per second>`
+fn configure_keyboard(server: &mut ServerState) {
+  for arg in env::args().skip(1) {
+    if let Some(path) = arg.strip_prefix("--keymap=") {
+      let name = Path::new(path).file_stem().map(|s| s.to_string_lossy().to_string());
+      let keymap = fs::read_to_string(path)
+        .and_then(|text| Keymap::parse(&name.unwrap_or_default(), &text));
+      match keymap {
+        Ok(keymap) => server.set_keymap(keymap),
+        Err(e) => println!("Failed to load keymap {}: {:?}", path, e)
+      }
+    }
+    else if let Some(repeat) = arg.strip_prefix("--repeat=") {
+      let parsed: Vec<Option<u32>> = repeat.split(',').map(|n| n.parse().ok()).collect();
+      match parsed[..] {
+        [Some(delay), Some(rate)] => {
+          server.keyboard.repeat_delay = time::Duration::from_millis(delay as u64);
+          server.keyboard.repeat_rate = rate;
+        }
+        _ => println!("Invalid key repeat {}", repeat)
+      }
+    }
+  }
+}
+
+// `--scale=<factor>` advertises an output scale other than the host's, e.g.
+// to try fractional scaling on a low density display
+fn parse_scale() -> Option<f64> {
+  for arg in env::args().skip(1) {
+    if let Some(factor) = arg.strip_prefix("--scale=") {
+      match factor.parse::<f64>() {
+        Ok(f) if f > 0.0 => return Some(f),
+        _ => println!("Invalid scale {}", factor)
+      }
+    }
+  }
+  return None;
 }
 
 fn main() -> Result<(), Error> {
-  let event_loop = EventLoop::new();
+  let mut server_state = ServerState::new(DISPLAY_WIDTH, DISPLAY_HEIGHT, parse_grantable());
+  // `--no-decorations` makes every client draw its own frame
+  server_state.server_decorations = !env::args().any(|a| a == "--no-decorations");
+  configure_keyboard(&mut server_state);
+  let server = Arc::new(Mutex::new(server_state));
+  let listener_state = server.clone();
+  thread::spawn(move || {
+    if let Err(e) = bind_unix_listener(listener_state) {
+      println!("Failed to listen: {:?}", e);
+    }
+  });
+
+  if env::args().any(|a| a == "--headless") {
+    run_headless(server);
+    return Ok(());
+  }
+
+  // Clients wake the loop with a user event when they change anything
+  let event_loop = EventLoop::with_user_event();
+  let proxy = event_loop.create_proxy();
+  server.lock().unwrap().set_waker(Box::new(move || {
+    // Fails only once the loop has exited
+    let _ = proxy.send_event(());
+  }));
   const WINDOW_WIDTH: u32 = DISPLAY_WIDTH as u32;
   const WINDOW_HEIGHT: u32 = DISPLAY_HEIGHT as u32;
-  let scale: f64 = 1.0;
-  let window = create_window("Compositor Demo", WINDOW_WIDTH, WINDOW_HEIGHT, scale, &event_loop);
+  let scale = parse_scale();
+  let window = create_window("Compositor Demo", WINDOW_WIDTH, WINDOW_HEIGHT, &event_loop);
   let window_size = window.inner_size();
+  server.lock().unwrap().set_output(describe_output(&window, scale));
 
   let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-  let front_buffer = Pixels::new(WINDOW_WIDTH, WINDOW_HEIGHT, surface_texture)?;
-  let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-  let back_buffer = Pixels::new(WINDOW_WIDTH, WINDOW_HEIGHT, surface_texture)?;
+  let pixels = Pixels::new(WINDOW_WIDTH, WINDOW_HEIGHT, surface_texture)?;
+  let swapchain = Swapchain::new(DISPLAY_WIDTH, DISPLAY_HEIGHT, SWAPCHAIN_LENGTH);
 
   let mut state = CompositorState {
-    front_buffer: front_buffer,
-    back_buffer: back_buffer,
-    parity: 0
+    backend: PixelsBackend { swapchain, pixels },
+    renderer: Renderer::new(),
+    server,
+    recorder: parse_recorder(),
+    cursor: None,
+    scheduler: FrameScheduler::new(time::Instant::now())
   };
 
   event_loop.run(move |event, _, control_flow| {
-    *control_flow = ControlFlow::Poll;
     match event {
       Event::RedrawRequested(_) => {
-        if state.front_buffer.render().map_err(|e| println!("err {}", e)).is_err() {
+        // The host needs the window contents again, e.g. after uncovering it
+        if let Err(e) = state.backend.pixels.render() {
+          println!("err {}", e);
           *control_flow = ControlFlow::Exit;
-          return;
         }
-        state.swap();
       }
       Event::WindowEvent {
         event: WindowEvent::CloseRequested,
         ..
       } => {
+        if let Some(recorder) = &state.recorder {
+          println!("Recorded {} frames", recorder.frame_count());
+        }
         *control_flow = ControlFlow::Exit;
         return;
       }
+      Event::WindowEvent {
+        event: WindowEvent::Moved(_),
+        ..
+      } => {
+        // Possibly onto another monitor
+        state.server.lock().unwrap().set_output(describe_output(&window, scale));
+      }
+      Event::WindowEvent {
+        event: WindowEvent::ScaleFactorChanged { new_inner_size, .. },
+        ..
+      } => {
+        // Keep mapping the frame 1:1 onto host pixels
+        *new_inner_size = PhysicalSize::new(WINDOW_WIDTH, WINDOW_HEIGHT);
+        state.server.lock().unwrap().set_output(describe_output(&window, scale));
+      }
+      Event::WindowEvent {
+        event: WindowEvent::Touch(touch),
+        ..
+      } => {
+        let pos = (touch.location.x as f32, touch.location.y as f32);
+        // Points that leave the frame keep reporting where they are
+        let (x, y) = match state.backend.pixels.window_pos_to_pixel(pos) {
+          Ok((x, y)) => (x as f64, y as f64),
+          Err((x, y)) => (x as f64, y as f64)
+        };
+        let mut server = state.server.lock().unwrap();
+        server.user_input(time::Instant::now());
+        match touch.phase {
+          TouchPhase::Started => server.touch_down(touch.id, x, y),
+          TouchPhase::Moved => server.touch_motion(touch.id, x, y),
+          TouchPhase::Ended => server.touch_up(touch.id),
+          TouchPhase::Cancelled => server.touch_cancel(touch.id)
+        }
+      }
+      Event::WindowEvent {
+        event: WindowEvent::CursorMoved { position, .. },
+        ..
+      } => {
+        let pos = (position.x as f32, position.y as f32);
+        state.cursor = state.backend.pixels.window_pos_to_pixel(pos).ok();
+        let mut server = state.server.lock().unwrap();
+        server.user_input(time::Instant::now());
+        if let Some((x, y)) = state.cursor {
+          server.pointer_motion(x as i32, y as i32);
+        }
+      }
+      Event::WindowEvent {
+        event:
+          WindowEvent::MouseInput {
+            state: ElementState::Pressed,
+            button: MouseButton::Left,
+            ..
+          },
+        ..
+      } => {
+        let mut server = state.server.lock().unwrap();
+        server.user_input(time::Instant::now());
+        if let Some(pos) = state.cursor {
+          if !click_notifications(&mut server, pos) {
+            server.pointer_press(pos.0 as i32, pos.1 as i32);
+          }
+        }
+      }
+      Event::WindowEvent {
+        event:
+          WindowEvent::MouseInput {
+            state: ElementState::Released,
+            button: MouseButton::Left,
+            ..
+          },
+        ..
+      } => {
+        state.server.lock().unwrap().pointer_release();
+      }
       Event::MainEventsCleared => {
-        let drawn = compositor_step(&mut state);
-        if drawn {
-          window.request_redraw();
+        if *control_flow == ControlFlow::Exit {
+          return;
+        }
+        let (renderer, backend) = (&mut state.renderer, &mut state.backend);
+        match run_frame(&state.server, renderer, backend, &mut state.scheduler) {
+          Ok(true) => record(&mut state.recorder, &mut state.backend),
+          Ok(false) => {}
+          Err(e) => {
+            println!("err {}", e);
+            *control_flow = ControlFlow::Exit;
+            return;
+          }
+        }
+        // Sleep until the next refresh while there is something to draw or
+        // answer, and otherwise until timed work is due. Input and client
+        // requests wake the loop sooner.
+        let deadline = state.scheduler.deadline();
+        let server = state.server.lock().unwrap();
+        *control_flow = if state.renderer.needs_frame(&server) {
+          ControlFlow::WaitUntil(deadline)
         }
+        else {
+          match server.next_tick() {
+            Some(tick) => ControlFlow::WaitUntil(tick.max(deadline)),
+            None => ControlFlow::Wait
+          }
+        };
       }
       Event::DeviceEvent {
         device_id: _,
         event: DeviceEvent::Key(input)
       } => {
-        // TODO: do something
+        let mut server = state.server.lock().unwrap();
+        let now = time::Instant::now();
+        server.user_input(now);
+        server.key_input(input.scancode, input.state == ElementState::Pressed, now);
       }
       _ => ()
     };
@@ -0,0 +1,104 @@
+// Reading back composed output or client surfaces for screenshot tools and
+// visual tests. Requires the ScreenCapture permission.
+
+use crate::subsurface::SurfaceTree;
+use crate::surface::copy_rect;
+use crate::{CaptureBuffer, CaptureSource};
+use libprotocol::{INTERNAL_BYTES_PER_PIXEL, MAX_MESSAGE_SIZE};
+
+// Premultiplied RGBA image, e.g. the last presented frame
+pub struct Frame {
+  pub width: usize,
+  pub height: usize,
+  pub data: Vec<u8>
+}
+
+impl Frame {
+  pub fn new(width: usize, height: usize) -> Self {
+    Frame { width, height, data: vec![0; INTERNAL_BYTES_PER_PIXEL * width * height] }
+  }
+}
+
+// Copy the source into the client's buffer, returning the buffer contents.
+// The buffer has to be the size of the source, with room for a row between
+// rows.
+pub fn capture(
+  source: CaptureSource, front: &Frame, surfaces: &SurfaceTree, buffer: CaptureBuffer
+) -> Result<Vec<u8>, String> {
+  let (width, height, internal) = match source {
+    CaptureSource::Output => (front.width, front.height, front.data.clone()),
+    CaptureSource::Region { x, y, dx, dy } => {
+      let right = x.checked_add(dx).filter(|r| *r <= front.width);
+      let bottom = y.checked_add(dy).filter(|b| *b <= front.height);
+      if right.is_none() || bottom.is_none() {
+        return Err("Capture region out of output bounds".to_string());
+      }
+      (dx, dy, copy_rect(&front.data, front.width, INTERNAL_BYTES_PER_PIXEL, x, y, dx, dy))
+    }
+    CaptureSource::Surface(id) => {
+      let surface = surfaces.get(id).map_err(|e| e.to_string())?;
+      (surface.width, surface.height, surface.data.clone())
+    }
+  };
+  if (buffer.width, buffer.height) != (width, height) {
+    let (bw, bh) = (buffer.width, buffer.height);
+    return Err(format!("Capture is {}x{}, the buffer {}x{}", width, height, bw, bh));
+  }
+  let row_len = width * buffer.format.bytes_per_pixel();
+  let size = buffer.stride.checked_mul(height).filter(|s| *s <= MAX_MESSAGE_SIZE);
+  if buffer.stride < row_len || size.is_none() {
+    return Err("Bad capture buffer stride".to_string());
+  }
+  let mut out = vec![0; size.unwrap()];
+  let converted = buffer.format.from_internal(&internal);
+  if row_len > 0 {
+    for (src, dst) in converted.chunks_exact(row_len).zip(out.chunks_mut(buffer.stride)) {
+      dst[..row_len].copy_from_slice(src);
+    }
+  }
+  return Ok(out);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use libprotocol::PixelFormat;
+
+  #[test]
+  fn test_capture() {
+    let mut front = Frame::new(4, 3);
+    for (i, pix) in front.data.chunks_exact_mut(4).enumerate() {
+      pix.copy_from_slice(&[i as u8, 0, 0, 0xff]);
+    }
+    let surfaces = SurfaceTree::new(2, 2, PixelFormat::Rgba8888);
+
+    let buffer = CaptureBuffer::packed(4, 3, PixelFormat::Rgba8888);
+    let data = capture(CaptureSource::Output, &front, &surfaces, buffer).unwrap();
+    assert_eq!(data, front.data);
+
+    // rows land `stride` bytes apart
+    let region = CaptureSource::Region { x: 1, y: 1, dx: 2, dy: 2 };
+    let buffer = CaptureBuffer { width: 2, height: 2, stride: 12, format: PixelFormat::Argb8888 };
+    let data = capture(region, &front, &surfaces, buffer).unwrap();
+    assert_eq!(data.len(), 24);
+    assert_eq!(data.chunks_exact(4).map(|p| p[2]).collect::<Vec<u8>>(), vec![5, 6, 0, 9, 10, 0]);
+
+    // buffers that don't fit are rejected before copying
+    let buffer = CaptureBuffer { width: 2, height: 2, stride: 4, format: PixelFormat::Argb8888 };
+    assert!(capture(region, &front, &surfaces, buffer).is_err());
+    let buffer = CaptureBuffer { width: 2, height: 2, stride: usize::MAX, ..buffer };
+    assert!(capture(region, &front, &surfaces, buffer).is_err());
+    let buffer = CaptureBuffer::packed(3, 2, PixelFormat::Argb8888);
+    assert!(capture(region, &front, &surfaces, buffer).is_err());
+
+    let buffer = CaptureBuffer::packed(2, 1, PixelFormat::Argb8888);
+    let region = CaptureSource::Region { x: 3, y: 0, dx: 2, dy: 1 };
+    assert!(capture(region, &front, &surfaces, buffer).is_err());
+    let region = CaptureSource::Region { x: usize::MAX, y: 0, dx: 2, dy: 1 };
+    assert!(capture(region, &front, &surfaces, buffer).is_err());
+    assert!(capture(CaptureSource::Surface(7), &front, &surfaces, buffer).is_err());
+    let buffer = CaptureBuffer::packed(2, 2, PixelFormat::Rgb565);
+    let data = capture(CaptureSource::Surface(0), &front, &surfaces, buffer).unwrap();
+    assert_eq!(data.len(), 8);
+  }
+}
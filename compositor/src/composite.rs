@@ -0,0 +1,253 @@
+// Compositing client surfaces into the output frame. Only the parts of the
+// output that changed since the last composite are redrawn: where surfaces
+// committed new contents, and wherever a surface appeared, moved, changed
+// places in the stacking order or went away. Server-side decorations are
+// drawn under each window's surfaces, in the same order.
+
+use crate::decoration::DecorationLayout;
+use crate::draw::Canvas;
+use crate::{ClientId, Rect, ServerState, SurfaceId};
+use std::collections::HashMap;
+use std::mem;
+
+pub const BACKGROUND: [u8; 4] = [0x99, 0x99, 0x99, 0xff];
+
+// Past this many rects the damage is redrawn as one bounding box
+const MAX_DAMAGE_RECTS: usize = 16;
+
+// A surface as drawn on the output
+type SceneEntry = (ClientId, SurfaceId, Rect);
+
+pub struct DamageTracker {
+  // Surfaces of the last composite, bottom to top
+  scene: Vec<SceneEntry>,
+  // Nothing has been drawn yet
+  fresh: bool
+}
+
+impl DamageTracker {
+  pub fn new() -> Self {
+    DamageTracker { scene: Vec::new(), fresh: true }
+  }
+}
+
+impl Default for DamageTracker {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// Source-over of the premultiplied `src`, `src_width` pixels wide with its
+// top left at `origin`, onto the opaque frame `dst`. Only pixels within
+// `clip` are touched, which has to lie within both.
+pub fn blend(
+  dst: &mut [u8], dst_width: usize, src: &[u8], src_width: usize, origin: (i32, i32), clip: Rect
+) {
+  for y in clip.y..(clip.y + clip.height) {
+    for x in clip.x..(clip.x + clip.width) {
+      let s = 4 * ((y - origin.1) as usize * src_width + (x - origin.0) as usize);
+      let d = 4 * (y as usize * dst_width + x as usize);
+      let alpha = src[s + 3] as u32;
+      if alpha == 0xff {
+        dst[d..d + 3].copy_from_slice(&src[s..s + 3]);
+      }
+      else if alpha > 0 {
+        for c in 0..3 {
+          let under = (dst[d + c] as u32 * (0xff - alpha) + 127) / 0xff;
+          dst[d + c] = (src[s + c] as u32 + under).min(0xff) as u8;
+        }
+      }
+      dst[d + 3] = 0xff;
+    }
+  }
+}
+
+impl ServerState {
+  // Surfaces of visible windows with their output rects, bottom to top
+  fn scene(self: &Self) -> Vec<SceneEntry> {
+    let mut scene = Vec::new();
+    for client in self.window_order() {
+      for (surface, rect) in self.surface_rects(client) {
+        scene.push((client, surface, rect));
+      }
+    }
+    return scene;
+  }
+
+  // Whether `take_output_damage` would return anything
+  pub fn has_output_damage(self: &Self) -> bool {
+    let scene = self.scene();
+    let damaged = |(client, surface, _): &SceneEntry| {
+      return self.clients[client].surfaces.get(*surface).unwrap().has_damage();
+    };
+    return self.damage.fresh || scene != self.damage.scene || scene.iter().any(damaged);
+  }
+
+  // Output rects to redraw since the last call, clipped to the output. The
+  // scene they were computed for is what `composite` draws.
+  pub fn take_output_damage(self: &mut Self) -> Vec<Rect> {
+    let output = Rect::new(0, 0, self.front.width as i32, self.front.height as i32);
+    let scene = self.scene();
+    let old = mem::replace(&mut self.damage.scene, scene.clone());
+    let mut damage = Vec::new();
+    if self.damage.fresh {
+      self.damage.fresh = false;
+      damage.push(output);
+    }
+
+    let key = |(c, s, _): &SceneEntry| (*c, *s);
+    for (i, entry) in scene.iter().enumerate() {
+      match old.iter().position(|o| key(o) == key(entry)) {
+        Some(j) if j == i && old[j].2 == entry.2 => {}
+        Some(j) => damage.extend(&[old[j].2, entry.2]),
+        None => damage.push(entry.2)
+      }
+    }
+    for entry in &old {
+      if !scene.iter().any(|e| key(e) == key(entry)) {
+        damage.push(entry.2);
+      }
+    }
+
+    for (client, id, rect) in &scene {
+      let surface = self.clients.get_mut(client).unwrap().surfaces.get_mut(*id).unwrap();
+      if let Some(d) = surface.take_damage() {
+        // Buffer to output pixels, rounded outwards. Resampling blurs across
+        // the edge by a pixel.
+        let fx = rect.width as f64 / surface.width as f64;
+        let fy = rect.height as f64 / surface.height as f64;
+        let margin = if (fx, fy) == (1.0, 1.0) { 0 } else { 1 };
+        let x0 = (d.x as f64 * fx).floor() as i32 - margin;
+        let y0 = (d.y as f64 * fy).floor() as i32 - margin;
+        let x1 = ((d.x + d.width) as f64 * fx).ceil() as i32 + margin;
+        let y1 = ((d.y + d.height) as f64 * fy).ceil() as i32 + margin;
+        damage.push(Rect::new(rect.x + x0, rect.y + y0, x1 - x0, y1 - y0));
+      }
+    }
+
+    let mut damage: Vec<Rect> = damage.iter().filter_map(|d| d.intersect(&output)).collect();
+    if damage.len() > MAX_DAMAGE_RECTS {
+      let bounds = damage.iter().fold(Rect::new(0, 0, 0, 0), |acc, d| acc.union(d));
+      damage = vec![bounds];
+    }
+    return damage;
+  }
+
+  // Redraw `damage` of an opaque RGBA frame the size of the output: the
+  // background, then the windows of the last `take_output_damage` in order,
+  // each its decorations and then its surfaces
+  pub fn composite(self: &mut Self, frame: &mut [u8], damage: &[Rect]) {
+    let (width, height) = (self.front.width, self.front.height);
+    let output_scale = self.output_scale();
+    let decorated: HashMap<ClientId, Rect> = self.decorated_windows().into_iter().collect();
+    for d in damage {
+      for y in d.y..(d.y + d.height) {
+        let row = 4 * (y as usize * width);
+        for x in d.x..(d.x + d.width) {
+          let ind = row + 4 * x as usize;
+          frame[ind..ind + 4].copy_from_slice(&BACKGROUND);
+        }
+      }
+      let mut last_client = None;
+      for (client, id, rect) in &self.damage.scene {
+        if last_client != Some(*client) {
+          last_client = Some(*client);
+          if let Some(content) = decorated.get(client) {
+            let mut canvas = Canvas::new(frame, width, height);
+            canvas.set_clip(*d);
+            DecorationLayout::new(*content).draw(&mut canvas, &self.clients[client].window.title);
+          }
+        }
+        let clip = match rect.intersect(d) {
+          Some(clip) => clip,
+          None => continue
+        };
+        let surface = match self.clients.get_mut(client).map(|c| c.surfaces.get_mut(*id)) {
+          Some(Ok(surface)) => surface,
+          _ => continue
+        };
+        let (src_width, src) = surface.scaled(output_scale);
+        blend(frame, width, src, src_width, (rect.x, rect.y), clip);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{DecorationMode, PixelFormat, MAIN_SURFACE};
+  use std::sync::mpsc;
+
+  fn pixel(frame: &[u8], width: usize, x: usize, y: usize) -> [u8; 4] {
+    let ind = 4 * (y * width + x);
+    return [frame[ind], frame[ind + 1], frame[ind + 2], frame[ind + 3]];
+  }
+
+  #[test]
+  fn test_composite() {
+    let (w, h) = (64, 48);
+    let mut state = ServerState::new(w, h, Vec::new());
+    let (outbox, _events) = mpsc::channel();
+    let client = state.add_client(outbox);
+    state.configure_window(client, Rect::new(-4, 4, 8, 8));
+    let mut frame = vec![0; 4 * w * h];
+
+    assert_eq!(state.take_output_damage()[0], Rect::new(0, 0, w as i32, h as i32));
+    state.composite(&mut frame, &[Rect::new(0, 0, w as i32, h as i32)]);
+    // transparent until the client draws
+    assert_eq!(pixel(&frame, w, 2, 6), BACKGROUND);
+    assert!(state.take_output_damage().is_empty());
+
+    // half transparent red over the left columns, straight alpha
+    let surfaces = &mut state.clients.get_mut(&client).unwrap().surfaces;
+    let main = surfaces.get_mut(MAIN_SURFACE).unwrap();
+    main.format = PixelFormat::Rgba8888;
+    main.patch(0, 0, 6, 8, &[0xff, 0, 0, 0x80].repeat(48)).unwrap();
+    surfaces.commit(MAIN_SURFACE).unwrap();
+    // clipped to the output
+    let damage = state.take_output_damage();
+    assert_eq!(damage, vec![Rect::new(0, 4, 2, 8)]);
+    state.composite(&mut frame, &damage);
+    assert_eq!(pixel(&frame, w, 1, 6), [0xcc, 0x4c, 0x4c, 0xff]);
+    assert_eq!(pixel(&frame, w, 2, 6), BACKGROUND);
+
+    // moving damages where the window was and where it is now
+    state.configure_window(client, Rect::new(20, 4, 8, 8));
+    let damage = state.take_output_damage();
+    assert_eq!(damage, vec![Rect::new(0, 4, 4, 8), Rect::new(20, 4, 8, 8)]);
+    state.composite(&mut frame, &damage);
+    assert_eq!(pixel(&frame, w, 1, 6), BACKGROUND);
+    assert_eq!(pixel(&frame, w, 25, 6), [0xcc, 0x4c, 0x4c, 0xff]);
+    assert_eq!(pixel(&frame, w, 26, 6), BACKGROUND);
+  }
+
+  #[test]
+  fn test_decorations_in_order() {
+    let (w, h) = (64, 64);
+    let mut state = ServerState::new(w, h, Vec::new());
+    let (outbox, _events) = mpsc::channel();
+    let a = state.add_client(outbox.clone());
+    let b = state.add_client(outbox);
+    for (client, rect) in [(a, Rect::new(10, 30, 20, 20)), (b, Rect::new(20, 40, 20, 20))] {
+      state.configure_window(client, rect);
+      let c = state.clients.get_mut(&client).unwrap();
+      c.window.decorations = DecorationMode::Server;
+      let main = c.surfaces.get_mut(MAIN_SURFACE).unwrap();
+      main.format = PixelFormat::Rgba8888;
+      main.patch(0, 0, 20, 20, &[0, 0, 0xff, 0xff].repeat(400)).unwrap();
+      c.surfaces.commit(MAIN_SURFACE).unwrap();
+    }
+    let mut frame = vec![0; 4 * w * h];
+    state.take_output_damage();
+    state.composite(&mut frame, &[Rect::new(0, 0, w as i32, h as i32)]);
+
+    // a's right border runs under b's content, and shows left of it
+    assert_eq!(pixel(&frame, w, 31, 45), [0, 0, 0xff, 0xff]);
+    assert_eq!(pixel(&frame, w, 31, 12), [0x33, 0x33, 0x3a, 0xff]);
+    // decorations only touch the damage
+    let mut frame = vec![0; 4 * w * h];
+    state.composite(&mut frame, &[Rect::new(0, 0, 8, 8)]);
+    assert_eq!(pixel(&frame, w, 7, 12), [0, 0, 0, 0]);
+  }
+}
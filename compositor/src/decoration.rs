@@ -0,0 +1,203 @@
+// Server-side window decorations: a title bar with close, maximize and
+// minimize buttons above the client's main surface, and a border around it
+// that can be dragged to resize. Clients opt in per connection; otherwise
+// they draw their own frames.
+
+use crate::draw::Canvas;
+use crate::geometry::{Rect, GLYPH_SIZE};
+use serde::{Deserialize, Serialize};
+
+pub const TITLE_HEIGHT: i32 = 20;
+pub const BORDER: i32 = 4;
+const BUTTON_SIZE: i32 = 14;
+const BUTTON_GAP: i32 = 4;
+// How far into the title bar the top edge still resizes
+const TOP_GRAB: i32 = 3;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ResizeEdge {
+  Top,
+  Bottom,
+  Left,
+  Right,
+  TopLeft,
+  TopRight,
+  BottomLeft,
+  BottomRight
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecorationHit {
+  Close,
+  Maximize,
+  Minimize,
+  // Drag to move
+  TitleBar,
+  Edge(ResizeEdge)
+}
+
+pub struct DecorationLayout {
+  // Everything including the border
+  pub frame: Rect,
+  pub title_bar: Rect,
+  pub close: Rect,
+  pub maximize: Rect,
+  pub minimize: Rect,
+  // Where the title text starts, and how many characters fit
+  pub title_pos: (i32, i32),
+  pub title_chars: usize
+}
+
+impl DecorationLayout {
+  // Decorations around a window whose content occupies `content`
+  pub fn new(content: Rect) -> Self {
+    let frame = Rect::new(
+      content.x - BORDER,
+      content.y - BORDER - TITLE_HEIGHT,
+      content.width + 2 * BORDER,
+      content.height + 2 * BORDER + TITLE_HEIGHT
+    );
+    let title_bar = Rect::new(content.x, frame.y + BORDER, content.width, TITLE_HEIGHT);
+    let button_y = title_bar.y + (TITLE_HEIGHT - BUTTON_SIZE) / 2;
+    let button = |i: i32| -> Rect {
+      let x = title_bar.x + title_bar.width - (i + 1) * (BUTTON_SIZE + BUTTON_GAP);
+      return Rect::new(x, button_y, BUTTON_SIZE, BUTTON_SIZE);
+    };
+    let (close, maximize, minimize) = (button(0), button(1), button(2));
+    let title_width = minimize.x - title_bar.x - 2 * BUTTON_GAP;
+    DecorationLayout {
+      frame,
+      title_bar,
+      close,
+      maximize,
+      minimize,
+      title_pos: (title_bar.x + BUTTON_GAP, title_bar.y + (TITLE_HEIGHT - GLYPH_SIZE) / 2),
+      title_chars: (title_width.max(0) / GLYPH_SIZE) as usize
+    }
+  }
+
+  // Size of the content area for a frame that fills `outer`
+  pub fn content_for_frame(outer: Rect) -> Rect {
+    return Rect::new(
+      outer.x + BORDER,
+      outer.y + BORDER + TITLE_HEIGHT,
+      outer.width - 2 * BORDER,
+      outer.height - 2 * BORDER - TITLE_HEIGHT
+    );
+  }
+
+  pub fn draw(self: &Self, canvas: &mut Canvas, title: &str) {
+    canvas.stroke_rect(self.frame, BORDER, [0x33, 0x33, 0x3a, 0xff]);
+    canvas.fill_rect(self.title_bar, [0x33, 0x33, 0x3a, 0xff]);
+    let title: String = title.chars().take(self.title_chars).collect();
+    let (tx, ty) = self.title_pos;
+    canvas.draw_text(tx, ty, &title, [0xee, 0xee, 0xee, 0xff]);
+    canvas.fill_rect(self.close, [0xcc, 0x44, 0x44, 0xff]);
+    canvas.fill_rect(self.maximize, [0x44, 0xaa, 0x55, 0xff]);
+    canvas.fill_rect(self.minimize, [0xcc, 0xaa, 0x33, 0xff]);
+  }
+
+  pub fn hit_test(self: &Self, x: i32, y: i32) -> Option<DecorationHit> {
+    if !self.frame.contains(x, y) {
+      return None;
+    }
+    for (rect, hit) in &[
+      (self.close, DecorationHit::Close),
+      (self.maximize, DecorationHit::Maximize),
+      (self.minimize, DecorationHit::Minimize)
+    ] {
+      if rect.contains(x, y) {
+        return Some(*hit);
+      }
+    }
+    let f = self.frame;
+    let top = y < f.y + BORDER + TOP_GRAB;
+    let bottom = y >= f.y + f.height - BORDER;
+    let left = x < f.x + BORDER;
+    let right = x >= f.x + f.width - BORDER;
+    let edge = match (top, bottom, left, right) {
+      (true, _, true, _) => Some(ResizeEdge::TopLeft),
+      (true, _, _, true) => Some(ResizeEdge::TopRight),
+      (_, true, true, _) => Some(ResizeEdge::BottomLeft),
+      (_, true, _, true) => Some(ResizeEdge::BottomRight),
+      (true, _, _, _) => Some(ResizeEdge::Top),
+      (_, true, _, _) => Some(ResizeEdge::Bottom),
+      (_, _, true, _) => Some(ResizeEdge::Left),
+      (_, _, _, true) => Some(ResizeEdge::Right),
+      _ => None
+    };
+    if let Some(edge) = edge {
+      return Some(DecorationHit::Edge(edge));
+    }
+    if self.title_bar.contains(x, y) {
+      return Some(DecorationHit::TitleBar);
+    }
+    return None;
+  }
+}
+
+// Content rect after dragging `edge` of `start` by (dx, dy), keeping at
+// least `min` of width and height
+pub fn resize_rect(start: Rect, edge: ResizeEdge, dx: i32, dy: i32, min: i32) -> Rect {
+  let (mut x0, mut y0) = (start.x, start.y);
+  let (mut x1, mut y1) = (start.x + start.width, start.y + start.height);
+  let (left, right, top, bottom) = match edge {
+    ResizeEdge::Top => (false, false, true, false),
+    ResizeEdge::Bottom => (false, false, false, true),
+    ResizeEdge::Left => (true, false, false, false),
+    ResizeEdge::Right => (false, true, false, false),
+    ResizeEdge::TopLeft => (true, false, true, false),
+    ResizeEdge::TopRight => (false, true, true, false),
+    ResizeEdge::BottomLeft => (true, false, false, true),
+    ResizeEdge::BottomRight => (false, true, false, true)
+  };
+  if left {
+    x0 = (x0 + dx).min(x1 - min);
+  }
+  if right {
+    x1 = (x1 + dx).max(x0 + min);
+  }
+  if top {
+    y0 = (y0 + dy).min(y1 - min);
+  }
+  if bottom {
+    y1 = (y1 + dy).max(y0 + min);
+  }
+  return Rect::new(x0, y0, x1 - x0, y1 - y0);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_hit_test() {
+    let content = Rect::new(100, 100, 200, 150);
+    let layout = DecorationLayout::new(content);
+    assert_eq!(layout.frame, Rect::new(96, 76, 208, 178));
+    assert_eq!(DecorationLayout::content_for_frame(layout.frame), content);
+
+    let c = layout.close;
+    assert_eq!(layout.hit_test(c.x + 1, c.y + 1), Some(DecorationHit::Close));
+    let m = layout.minimize;
+    assert_eq!(layout.hit_test(m.x + 1, m.y + 1), Some(DecorationHit::Minimize));
+    assert!(layout.close.x > layout.maximize.x && layout.maximize.x > layout.minimize.x);
+    assert_eq!(layout.hit_test(150, 90), Some(DecorationHit::TitleBar));
+    assert_eq!(layout.hit_test(97, 77), Some(DecorationHit::Edge(ResizeEdge::TopLeft)));
+    assert_eq!(layout.hit_test(302, 200), Some(DecorationHit::Edge(ResizeEdge::Right)));
+    assert_eq!(layout.hit_test(150, 252), Some(DecorationHit::Edge(ResizeEdge::Bottom)));
+    // content and outside are not decorations
+    assert_eq!(layout.hit_test(150, 150), None);
+    assert_eq!(layout.hit_test(10, 10), None);
+  }
+
+  #[test]
+  fn test_resize_rect() {
+    let start = Rect::new(100, 100, 200, 150);
+    let resized = resize_rect(start, ResizeEdge::BottomRight, 10, 20, 50);
+    assert_eq!(resized, Rect::new(100, 100, 210, 170));
+    assert_eq!(resize_rect(start, ResizeEdge::Left, -10, 99, 50), Rect::new(90, 100, 210, 150));
+    // clamped to the minimum size, keeping the opposite edge fixed
+    assert_eq!(resize_rect(start, ResizeEdge::Top, 0, 500, 50), Rect::new(100, 200, 200, 50));
+  }
+}
@@ -0,0 +1,245 @@
+// Requests from one client, applied to the server state. The client's lock on
+// the state is held for the length of each request.
+
+use crate::{
+  capture, CaptureBuffer, CaptureSource, ClientId, ClientState, CloseReason, Compression,
+  DecorationMode, Keysym, Message, Permission, PixelFormat, Rect, ServerState, Shortcut, SurfaceId,
+  Urgency, MAIN_SURFACE, MAX_BUFFER_SCALE
+};
+use libprotocol::{ServerHandler, DEFAULT_SURFACE_FORMAT, SUPPORTED_FORMATS};
+use std::io::{Error, ErrorKind, Result};
+use std::time::{Duration, Instant};
+
+pub struct ClientRequests<'a> {
+  pub state: &'a mut ServerState,
+  pub id: ClientId
+}
+
+impl<'a> ClientRequests<'a> {
+  fn client(self: &mut Self) -> &mut ClientState {
+    return self.state.clients.get_mut(&self.id).unwrap();
+  }
+}
+
+impl<'a> ServerHandler for ClientRequests<'a> {
+  fn request_permission(self: &mut Self, permission: Permission) -> Result<()> {
+    let granted = self.state.grantable.contains(&permission);
+    let client = self.client();
+    if granted && !client.permissions.contains(&permission) {
+      client.permissions.push(permission);
+    }
+    client.send(Message::PermissionEvent { permission, granted });
+    return Ok(());
+  }
+
+  fn capture_request(
+    self: &mut Self, serial: u32, source: CaptureSource, buffer: CaptureBuffer
+  ) -> Result<()> {
+    let client = &self.state.clients[&self.id];
+    let result = if !client.permissions.contains(&Permission::ScreenCapture) {
+      Err("Screen capture not permitted".to_string())
+    }
+    else {
+      capture::capture(source, &self.state.front, &client.surfaces, buffer)
+    };
+    client.send(match result {
+      Ok(data) => Message::CaptureDoneEvent { serial, data },
+      Err(reason) => Message::CaptureFailedEvent { serial, reason }
+    });
+    return Ok(());
+  }
+
+  fn notify(
+    self: &mut Self, id: u32, summary: String, body: String, urgency: Urgency,
+    timeout_ms: Option<u32>, actions: Vec<String>
+  ) -> Result<()> {
+    self.state.notifications.post(
+      self.id,
+      id,
+      summary,
+      body,
+      urgency,
+      timeout_ms,
+      actions,
+      Instant::now()
+    );
+    return Ok(());
+  }
+
+  fn close_notification(self: &mut Self, id: u32) -> Result<()> {
+    if self.state.notifications.remove(self.id, id).is_some() {
+      let reason = CloseReason::Closed;
+      self.state.send(self.id, Message::NotificationClosedEvent { id, reason });
+    }
+    return Ok(());
+  }
+
+  fn set_title(self: &mut Self, title: String) -> Result<()> {
+    self.client().window.title = title;
+    return Ok(());
+  }
+
+  fn set_decoration_mode(self: &mut Self, mode: DecorationMode) -> Result<()> {
+    let mode = if self.state.server_decorations { mode } else { DecorationMode::Client };
+    let client = self.client();
+    client.window.decorations = mode;
+    client.send(Message::DecorationModeEvent { mode });
+    self.state.retile();
+    return Ok(());
+  }
+
+  fn set_window_state(self: &mut Self, maximized: bool, minimized: bool) -> Result<()> {
+    self.state.set_maximized(self.id, maximized);
+    self.state.set_minimized(self.id, minimized);
+    return Ok(());
+  }
+
+  fn enable_text_input(self: &mut Self) -> Result<()> {
+    self.client().text_input.enabled = true;
+    self.state.update_text_input();
+    return Ok(());
+  }
+
+  fn disable_text_input(self: &mut Self) -> Result<()> {
+    self.client().text_input.enabled = false;
+    self.state.update_text_input();
+    return Ok(());
+  }
+
+  fn set_text_input_cursor(self: &mut Self, cursor: Rect) -> Result<()> {
+    self.state.set_text_input_cursor(self.id, cursor);
+    return Ok(());
+  }
+
+  fn register_hotkey(self: &mut Self, id: u32, shortcut: Shortcut) -> Result<()> {
+    let result = if !self.client().permissions.contains(&Permission::GlobalShortcuts) {
+      Err("Global shortcuts not permitted".to_string())
+    }
+    else {
+      self.state.hotkeys.register(self.id, id, shortcut)
+    };
+    self.state.send(self.id, match result {
+      Ok(()) => Message::HotkeyRegisteredEvent { id },
+      Err(reason) => Message::HotkeyFailedEvent { id, reason }
+    });
+    return Ok(());
+  }
+
+  fn unregister_hotkey(self: &mut Self, id: u32) -> Result<()> {
+    self.state.hotkeys.unregister(self.id, id);
+    return Ok(());
+  }
+
+  fn register_input_method(self: &mut Self) -> Result<()> {
+    let permitted = self.client().permissions.contains(&Permission::InputMethod);
+    let granted = permitted && self.state.register_input_method(self.id);
+    self.state.send(self.id, Message::InputMethodRegisteredEvent { granted });
+    return Ok(());
+  }
+
+  fn preedit(self: &mut Self, text: String, cursor: Option<usize>) -> Result<()> {
+    self.state.route_from_input_method(self.id, Message::PreeditEvent { text, cursor });
+    return Ok(());
+  }
+
+  fn commit_string(self: &mut Self, text: String) -> Result<()> {
+    self.state.route_from_input_method(self.id, Message::CommitStringEvent { text });
+    return Ok(());
+  }
+
+  fn forward_key(self: &mut Self, scancode: u32, keysym: Keysym, pressed: bool) -> Result<()> {
+    let key = Message::KeyEvent { scancode, keysym, pressed, repeat: false };
+    self.state.route_from_input_method(self.id, key);
+    return Ok(());
+  }
+
+  fn subscribe_idle(self: &mut Self, timeout_ms: u32) -> Result<()> {
+    self.state.idle.subscribe(self.id, Duration::from_millis(timeout_ms as u64));
+    return Ok(());
+  }
+
+  fn unsubscribe_idle(self: &mut Self) -> Result<()> {
+    self.state.idle.unsubscribe(self.id);
+    return Ok(());
+  }
+
+  fn create_idle_inhibitor(self: &mut Self, surface: SurfaceId) -> Result<()> {
+    self.client().surfaces.get(surface)?;
+    self.state.idle.add_inhibitor(self.id, surface);
+    return Ok(());
+  }
+
+  fn destroy_idle_inhibitor(self: &mut Self, surface: SurfaceId) -> Result<()> {
+    self.state.idle.remove_inhibitor(self.id, surface);
+    return Ok(());
+  }
+
+  fn set_buffer_scale(self: &mut Self, surface: SurfaceId, scale: u32) -> Result<()> {
+    if scale == 0 || scale > MAX_BUFFER_SCALE {
+      return Err(Error::new(ErrorKind::InvalidInput, "Buffer scale out of range"));
+    }
+    let rect = self.state.window_rect(self.id);
+    self.client().surfaces.get_mut(surface)?.scale = scale;
+    if let (MAIN_SURFACE, Some(rect)) = (surface, rect) {
+      self.state.configure_window(self.id, rect);
+    }
+    return Ok(());
+  }
+
+  fn set_format(self: &mut Self, surface: SurfaceId, format: PixelFormat) -> Result<()> {
+    if !SUPPORTED_FORMATS.contains(&format) {
+      return Err(Error::new(ErrorKind::Unsupported, "Unsupported pixel format"));
+    }
+    self.client().surfaces.get_mut(surface)?.format = format;
+    return Ok(());
+  }
+
+  fn create_subsurface(
+    self: &mut Self, id: SurfaceId, parent: SurfaceId, width: usize, height: usize
+  ) -> Result<()> {
+    let surfaces = &mut self.client().surfaces;
+    return surfaces.create_subsurface(id, parent, width, height, DEFAULT_SURFACE_FORMAT);
+  }
+
+  fn set_subsurface_position(self: &mut Self, id: SurfaceId, x: i32, y: i32) -> Result<()> {
+    return self.client().surfaces.set_position(id, x, y);
+  }
+
+  fn place_above(self: &mut Self, id: SurfaceId, sibling: SurfaceId) -> Result<()> {
+    return self.client().surfaces.place_above(id, sibling);
+  }
+
+  fn place_below(self: &mut Self, id: SurfaceId, sibling: SurfaceId) -> Result<()> {
+    return self.client().surfaces.place_below(id, sibling);
+  }
+
+  fn set_subsurface_sync(self: &mut Self, id: SurfaceId, sync: bool) -> Result<()> {
+    return self.client().surfaces.set_sync(id, sync);
+  }
+
+  fn destroy_surface(self: &mut Self, id: SurfaceId) -> Result<()> {
+    return self.client().surfaces.destroy(id);
+  }
+
+  fn commit(self: &mut Self, surface: SurfaceId) -> Result<()> {
+    return self.client().surfaces.commit(surface);
+  }
+
+  fn request_frame(self: &mut Self, surface: SurfaceId) -> Result<()> {
+    self.client().surfaces.get(surface)?;
+    self.state.request_frame(self.id, surface);
+    return Ok(());
+  }
+
+  fn damage_upload(
+    self: &mut Self, surface: SurfaceId, x: usize, y: usize, dx: usize, dy: usize,
+    compression: Compression, data: Vec<u8>
+  ) -> Result<()> {
+    let surface = self.client().surfaces.get_mut(surface)?;
+    // Checked before decoding, which allocates the size up front
+    let size = surface.upload_size(x, y, dx, dy)?;
+    let bpp = surface.format.bytes_per_pixel();
+    let pixels = libprotocol::decode(&data, compression, bpp, size)?;
+    return surface.patch(x, y, dx, dy, &pixels);
+  }
+}
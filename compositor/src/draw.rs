@@ -0,0 +1,94 @@
+// Primitives for compositor-drawn UI on an RGBA frame. Everything is clipped
+// to the frame, and to a smaller clip rect if one is set.
+
+use font8x8::legacy::BASIC_LEGACY;
+use crate::geometry::{Rect, GLYPH_SIZE};
+
+pub struct Canvas<'a> {
+  pub frame: &'a mut [u8],
+  pub width: usize,
+  pub height: usize,
+  clip: Rect
+}
+
+impl<'a> Canvas<'a> {
+  pub fn new(frame: &'a mut [u8], width: usize, height: usize) -> Self {
+    assert_eq!(frame.len(), 4 * width * height);
+    Canvas { frame, width, height, clip: Rect::new(0, 0, width as i32, height as i32) }
+  }
+
+  // Only draw within `clip` from now on
+  pub fn set_clip(self: &mut Self, clip: Rect) {
+    let bounds = Rect::new(0, 0, self.width as i32, self.height as i32);
+    self.clip = clip.intersect(&bounds).unwrap_or(Rect::new(0, 0, 0, 0));
+  }
+
+  fn put(self: &mut Self, x: i32, y: i32, color: [u8; 4]) {
+    if !self.clip.contains(x, y) {
+      return;
+    }
+    let ind = 4 * (y as usize * self.width + x as usize);
+    self.frame[ind..ind + 4].copy_from_slice(&color);
+  }
+
+  pub fn fill_rect(self: &mut Self, rect: Rect, color: [u8; 4]) {
+    let clip = self.clip;
+    let x0 = rect.x.max(clip.x);
+    let x1 = (rect.x + rect.width).min(clip.x + clip.width);
+    let y0 = rect.y.max(clip.y);
+    let y1 = (rect.y + rect.height).min(clip.y + clip.height);
+    for y in y0..y1 {
+      for x in x0..x1 {
+        self.put(x, y, color);
+      }
+    }
+  }
+
+  pub fn stroke_rect(self: &mut Self, rect: Rect, thickness: i32, color: [u8; 4]) {
+    let Rect { x, y, width, height } = rect;
+    self.fill_rect(Rect::new(x, y, width, thickness), color);
+    self.fill_rect(Rect::new(x, y + height - thickness, width, thickness), color);
+    self.fill_rect(Rect::new(x, y, thickness, height), color);
+    self.fill_rect(Rect::new(x + width - thickness, y, thickness, height), color);
+  }
+
+  // Draw a line of text with its top left corner at (x, y). Characters
+  // outside ASCII are drawn as '?'.
+  pub fn draw_text(self: &mut Self, x: i32, y: i32, text: &str, color: [u8; 4]) {
+    for (i, c) in text.chars().enumerate() {
+      let c = if c.is_ascii() { c } else { '?' };
+      let glyph = BASIC_LEGACY[c as usize];
+      let gx = x + i as i32 * GLYPH_SIZE;
+      for (row, bits) in glyph.iter().enumerate() {
+        for col in 0..GLYPH_SIZE {
+          if bits & (1 << col) != 0 {
+            self.put(gx + col, y + row as i32, color);
+          }
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_clipping() {
+    let mut frame = vec![0u8; 4 * 4 * 4];
+    let mut canvas = Canvas::new(&mut frame, 4, 4);
+    canvas.fill_rect(Rect::new(-2, 2, 4, 10), [1, 2, 3, 4]);
+    canvas.draw_text(3, 3, "A", [9, 9, 9, 9]);
+    let filled = frame.chunks_exact(4).filter(|p| p[0] != 0).count();
+    assert_eq!(filled, 4);
+    assert_eq!(frame[4 * 8..4 * 9], [1, 2, 3, 4]);
+
+    let mut canvas = Canvas::new(&mut frame, 4, 4);
+    canvas.set_clip(Rect::new(1, 0, 1, 1));
+    canvas.fill_rect(Rect::new(0, 0, 4, 4), [5, 5, 5, 5]);
+    let filled = frame.chunks_exact(4).filter(|p| p[0] == 5).count();
+    assert_eq!(filled, 1);
+    assert_eq!(frame[4..8], [5, 5, 5, 5]);
+  }
+}
@@ -0,0 +1,43 @@
+// Layout helpers for compositor-drawn UI. Rect itself is part of the
+// protocol.
+
+pub use libprotocol::Rect;
+
+// Compositor-drawn text uses an 8x8 bitmap font
+pub const GLYPH_SIZE: i32 = 8;
+
+// Fit text into lines of at most `max_chars`, breaking at spaces where
+// possible
+pub fn wrap_text(text: &str, max_chars: usize) -> Vec<String> {
+  let mut lines = Vec::new();
+  let mut line = String::new();
+  for word in text.split_whitespace() {
+    let mut word: Vec<char> = word.chars().collect();
+    if !line.is_empty() && line.chars().count() + 1 + word.len() > max_chars {
+      lines.push(std::mem::take(&mut line));
+    }
+    while word.len() > max_chars {
+      lines.push(word.drain(..max_chars).collect());
+    }
+    if !line.is_empty() {
+      line.push(' ');
+    }
+    line.extend(word);
+  }
+  if !line.is_empty() {
+    lines.push(line);
+  }
+  return lines;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_wrap_text() {
+    assert_eq!(wrap_text("White to move  and win", 10), vec!["White to", "move and", "win"]);
+    assert_eq!(wrap_text("abcdefghijkl", 5), vec!["abcde", "fghij", "kl"]);
+    assert!(wrap_text("   ", 5).is_empty());
+  }
+}
@@ -0,0 +1,235 @@
+// Compositor-wide shortcuts. The compositor has a few bindings of its own;
+// clients granted the GlobalShortcuts permission can register more, e.g. a
+// screenshot tool or media keys. Keys that trigger a shortcut, including
+// their repeats and release, never reach the focused client. A client's
+// shortcuts go away when it disconnects.
+
+use crate::{
+  ClientId, Keysym, Message, ServerState, Shortcut, KEY_RETURN, KEY_TAB, WORKSPACE_COUNT
+};
+
+fn logo_shortcut(keysym: Keysym) -> Shortcut {
+  Shortcut { ctrl: false, alt: false, shift: false, logo: true, keysym }
+}
+
+fn logo_shift_shortcut(keysym: Keysym) -> Shortcut {
+  Shortcut { shift: true, ..logo_shortcut(keysym) }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompositorAction {
+  FocusNextWindow,
+  CloseWindow,
+  NextLayout,
+  ToggleFloating,
+  // Tiles before or after the focused one
+  FocusTile(i32),
+  SwapTile(i32),
+  SwapWithMaster,
+  // Steps to widen the master tile by
+  ResizeMaster(i32),
+  SwitchWorkspace(u32),
+  // Move the focused window
+  MoveToWorkspace(u32)
+}
+
+// What a held key triggered
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Trigger {
+  Compositor,
+  Client(ClientId, u32)
+}
+
+pub struct HotkeyRegistry {
+  pub compositor: Vec<(Shortcut, CompositorAction)>,
+  // Owner, client-chosen id, shortcut
+  clients: Vec<(ClientId, u32, Shortcut)>,
+  // Scancodes whose press triggered a shortcut
+  held: Vec<(u32, Trigger)>
+}
+
+impl HotkeyRegistry {
+  pub fn new() -> Self {
+    let mut registry = HotkeyRegistry {
+      compositor: vec![
+        (logo_shortcut(KEY_TAB), CompositorAction::FocusNextWindow),
+        (logo_shortcut('q' as Keysym), CompositorAction::CloseWindow),
+        (logo_shortcut(' ' as Keysym), CompositorAction::NextLayout),
+        (logo_shortcut('f' as Keysym), CompositorAction::ToggleFloating),
+        (logo_shortcut('j' as Keysym), CompositorAction::FocusTile(1)),
+        (logo_shortcut('k' as Keysym), CompositorAction::FocusTile(-1)),
+        (logo_shift_shortcut('j' as Keysym), CompositorAction::SwapTile(1)),
+        (logo_shift_shortcut('k' as Keysym), CompositorAction::SwapTile(-1)),
+        (logo_shortcut(KEY_RETURN), CompositorAction::SwapWithMaster),
+        (logo_shortcut('h' as Keysym), CompositorAction::ResizeMaster(-1)),
+        (logo_shortcut('l' as Keysym), CompositorAction::ResizeMaster(1))
+      ],
+      clients: Vec::new(),
+      held: Vec::new()
+    };
+    // Logo with the digits from 1 switches workspace, adding Shift takes the
+    // focused window along
+    for i in 0..WORKSPACE_COUNT {
+      let digit = std::char::from_digit(i + 1, 10).unwrap() as Keysym;
+      registry.compositor.push((logo_shortcut(digit), CompositorAction::SwitchWorkspace(i)));
+      let shortcut = logo_shift_shortcut(digit);
+      registry.compositor.push((shortcut, CompositorAction::MoveToWorkspace(i)));
+    }
+    return registry;
+  }
+
+  pub fn register(
+    self: &mut Self, client: ClientId, id: u32, shortcut: Shortcut
+  ) -> Result<(), String> {
+    if self.compositor.iter().any(|(s, _)| *s == shortcut) {
+      return Err("Shortcut is reserved by the compositor".to_string());
+    }
+    let taken = self.clients.iter().find(|(c, i, s)| *s == shortcut && (*c, *i) != (client, id));
+    if taken.is_some() {
+      return Err("Shortcut is registered by another client".to_string());
+    }
+    self.unregister(client, id);
+    self.clients.push((client, id, shortcut));
+    return Ok(());
+  }
+
+  pub fn unregister(self: &mut Self, client: ClientId, id: u32) {
+    self.clients.retain(|(c, i, _)| (*c, *i) != (client, id));
+  }
+
+  pub fn remove_client(self: &mut Self, client: ClientId) {
+    self.clients.retain(|(c, _, _)| *c != client);
+    self.held.retain(|(_, t)| !matches!(t, Trigger::Client(c, _) if *c == client));
+  }
+
+  pub fn is_held(self: &Self, scancode: u32) -> bool {
+    return self.held.iter().any(|(code, _)| *code == scancode);
+  }
+}
+
+impl Default for HotkeyRegistry {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ServerState {
+  fn run_compositor_action(self: &mut Self, action: CompositorAction) {
+    match action {
+      // Minimized windows on current workspaces take their turn too, which is
+      // how they are brought back
+      CompositorAction::FocusNextWindow => {
+        let mut order = self.wm.stack.clone();
+        order.retain(|c| {
+          let window = &self.clients[c].window;
+          return window.workspace == self.current_workspace(window.output);
+        });
+        let next = match self.keyboard_focus.and_then(|f| order.iter().position(|c| *c == f)) {
+          Some(i) => order.get((i + 1) % order.len()),
+          None => order.first()
+        };
+        if let Some(next) = next.cloned() {
+          self.set_minimized(next, false);
+          self.wm.raise(next);
+          self.set_keyboard_focus(Some(next));
+        }
+      }
+      CompositorAction::CloseWindow => {
+        if let Some(focus) = self.keyboard_focus {
+          self.send(focus, Message::CloseRequestEvent);
+        }
+      }
+      CompositorAction::NextLayout => self.set_layout(self.wm.layout.next()),
+      CompositorAction::ToggleFloating => {
+        if let Some(focus) = self.keyboard_focus {
+          self.toggle_floating(focus);
+        }
+      }
+      CompositorAction::FocusTile(offset) => self.focus_tile(offset),
+      CompositorAction::SwapTile(offset) => self.swap_tile(Some(offset)),
+      CompositorAction::SwapWithMaster => self.swap_tile(None),
+      CompositorAction::ResizeMaster(steps) => self.adjust_master_ratio(steps),
+      CompositorAction::SwitchWorkspace(index) => self.switch_workspace(index),
+      CompositorAction::MoveToWorkspace(index) => {
+        if let Some(focus) = self.keyboard_focus {
+          self.move_to_workspace(focus, index);
+        }
+      }
+    }
+  }
+
+  // Run any shortcut for a key change, returning whether it was consumed
+  pub fn hotkey_input(self: &mut Self, scancode: u32, pressed: bool) -> bool {
+    let registry = &mut self.hotkeys;
+    if !pressed {
+      let ind = match registry.held.iter().position(|(code, _)| *code == scancode) {
+        Some(ind) => ind,
+        None => return false
+      };
+      if let (_, Trigger::Client(client, id)) = registry.held.remove(ind) {
+        self.send(client, Message::HotkeyEvent { id, pressed: false });
+      }
+      return true;
+    }
+    if registry.is_held(scancode) {
+      return true;
+    }
+    let shortcut = Shortcut::new(self.keyboard.modifiers, self.keyboard.plain_keysym(scancode));
+    if let Some((_, action)) = registry.compositor.iter().find(|(s, _)| *s == shortcut) {
+      let action = *action;
+      registry.held.push((scancode, Trigger::Compositor));
+      self.run_compositor_action(action);
+      return true;
+    }
+    if let Some((client, id, _)) = registry.clients.iter().find(|(_, _, s)| *s == shortcut) {
+      let (client, id) = (*client, *id);
+      registry.held.push((scancode, Trigger::Client(client, id)));
+      self.send(client, Message::HotkeyEvent { id, pressed: true });
+      return true;
+    }
+    return false;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::mpsc;
+  use std::time::Instant;
+
+  #[test]
+  fn test_hotkeys() {
+    let mut state = ServerState::new(800, 600, Vec::new());
+    let (outbox, events) = mpsc::channel();
+    let client = state.add_client(outbox);
+    let print = Shortcut {
+      ctrl: true,
+      alt: false,
+      shift: true,
+      logo: false,
+      keysym: 'p' as Keysym
+    };
+    assert!(state.hotkeys.register(client, 1, logo_shortcut(KEY_TAB)).is_err());
+    assert!(state.hotkeys.register(client, 1, print).is_ok());
+    assert!(state.hotkeys.register(client + 1, 7, print).is_err());
+
+    let now = Instant::now();
+    state.key_input(29, true, now);
+    state.key_input(42, true, now);
+    state.key_input(25, true, now);
+    state.key_input(25, false, now);
+    let got: Vec<(u32, bool)> = events
+      .try_iter()
+      .filter_map(|m| match m {
+        Message::HotkeyEvent { id, pressed } => Some((id, pressed)),
+        // the shortcut key never reaches the client as a key
+        Message::KeyEvent { scancode: 25, .. } => Some((99, true)),
+        _ => None
+      })
+      .collect();
+    assert_eq!(got, vec![(1, true), (1, false)]);
+
+    state.remove_client(client);
+    assert!(state.hotkeys.register(client + 1, 7, print).is_ok());
+  }
+}
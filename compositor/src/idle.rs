@@ -0,0 +1,131 @@
+// Idle detection. Clients subscribe with their own timeout and are told when
+// the user has been away from the input devices that long, and again when
+// input resumes. Clients can also keep the session from going idle while one
+// of their surfaces is visible, e.g. a game with a running clock.
+
+use crate::{ClientId, Message, ServerState, SurfaceId};
+use std::time::{Duration, Instant};
+
+struct IdleWatch {
+  client: ClientId,
+  timeout: Duration,
+  idle: bool
+}
+
+pub struct IdleTracker {
+  last_input: Instant,
+  watches: Vec<IdleWatch>,
+  inhibitors: Vec<(ClientId, SurfaceId)>
+}
+
+impl IdleTracker {
+  pub fn new(now: Instant) -> Self {
+    IdleTracker { last_input: now, watches: Vec::new(), inhibitors: Vec::new() }
+  }
+
+  // Replaces any earlier subscription of the client
+  pub fn subscribe(self: &mut Self, client: ClientId, timeout: Duration) {
+    self.unsubscribe(client);
+    self.watches.push(IdleWatch { client, timeout, idle: false });
+  }
+
+  pub fn unsubscribe(self: &mut Self, client: ClientId) {
+    self.watches.retain(|w| w.client != client);
+  }
+
+  pub fn add_inhibitor(self: &mut Self, client: ClientId, surface: SurfaceId) {
+    if !self.inhibitors.contains(&(client, surface)) {
+      self.inhibitors.push((client, surface));
+    }
+  }
+
+  pub fn remove_inhibitor(self: &mut Self, client: ClientId, surface: SurfaceId) {
+    self.inhibitors.retain(|i| *i != (client, surface));
+  }
+
+  pub fn remove_client(self: &mut Self, client: ClientId) {
+    self.unsubscribe(client);
+    self.inhibitors.retain(|(c, _)| *c != client);
+  }
+
+  // User input at `now`, returning the subscribers that were idle
+  pub fn input(self: &mut Self, now: Instant) -> Vec<ClientId> {
+    self.last_input = now;
+    let mut resumed = Vec::new();
+    for w in self.watches.iter_mut().filter(|w| w.idle) {
+      w.idle = false;
+      resumed.push(w.client);
+    }
+    return resumed;
+  }
+
+  // Returns the subscribers that just went idle. While `inhibited`, time
+  // without input does not count.
+  pub fn update(self: &mut Self, now: Instant, inhibited: bool) -> Vec<ClientId> {
+    if inhibited {
+      self.last_input = now;
+    }
+    let away = now.saturating_duration_since(self.last_input);
+    let mut idled = Vec::new();
+    for w in self.watches.iter_mut().filter(|w| !w.idle && away >= w.timeout) {
+      w.idle = true;
+      idled.push(w.client);
+    }
+    return idled;
+  }
+
+  // When the next subscriber goes idle, unless there is input first
+  pub fn next_timeout(self: &Self) -> Option<Instant> {
+    let waiting = self.watches.iter().filter(|w| !w.idle);
+    return waiting.map(|w| self.last_input + w.timeout).min();
+  }
+}
+
+impl ServerState {
+  // Whether any inhibitor's surface is currently shown
+  fn idle_inhibited(self: &Self) -> bool {
+    return self.idle.inhibitors.iter().any(|(client, surface)| match self.clients.get(client) {
+      Some(c) => !c.window.minimized && c.surfaces.get(*surface).is_ok(),
+      None => false
+    });
+  }
+
+  pub fn user_input(self: &mut Self, now: Instant) {
+    for client in self.idle.input(now) {
+      self.send(client, Message::ResumedEvent);
+    }
+  }
+
+  // Tell subscribers who went idle by `now`
+  pub fn update_idle(self: &mut Self, now: Instant) {
+    let inhibited = self.idle_inhibited();
+    for client in self.idle.update(now, inhibited) {
+      self.send(client, Message::IdledEvent);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_idle_tracker() {
+    let t0 = Instant::now();
+    let secs = Duration::from_secs;
+    let mut tracker = IdleTracker::new(t0);
+    tracker.subscribe(1, secs(10));
+    tracker.subscribe(2, secs(60));
+    assert!(tracker.update(t0 + secs(5), false).is_empty());
+    assert_eq!(tracker.update(t0 + secs(10), false), vec![1]);
+    // only reported once
+    assert!(tracker.update(t0 + secs(20), false).is_empty());
+    assert_eq!(tracker.input(t0 + secs(30)), vec![1]);
+    assert!(tracker.input(t0 + secs(31)).is_empty());
+
+    // inhibited time does not count towards the timeout
+    assert!(tracker.update(t0 + secs(100), true).is_empty());
+    assert!(tracker.update(t0 + secs(105), false).is_empty());
+    assert_eq!(tracker.update(t0 + secs(110), false), vec![1]);
+  }
+}
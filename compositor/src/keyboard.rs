@@ -0,0 +1,252 @@
+// Keyboard handling: translating scancodes to keysyms through a layout,
+// tracking modifiers, and repeating held keys on the server side so clients
+// don't need timers. Key events go to the client with keyboard focus.
+
+use crate::{ClientId, Keymap, Keysym, Message, Modifiers, ServerState};
+use libprotocol::{
+  keysym_to_char, KEY_ALT_L, KEY_ALT_R, KEY_CAPS_LOCK, KEY_CONTROL_L, KEY_CONTROL_R, KEY_SHIFT_L,
+  KEY_SHIFT_R, KEY_SUPER_L, MODIFIER_KEYS
+};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEvent {
+  pub scancode: u32,
+  pub keysym: Keysym,
+  pub pressed: bool,
+  // Generated by key repeat rather than the keyboard
+  pub repeat: bool
+}
+
+pub struct Keyboard {
+  pub keymap: Keymap,
+  pub modifiers: Modifiers,
+  // Delay before a held key repeats, and repeats per second (0 disables)
+  pub repeat_delay: Duration,
+  pub repeat_rate: u32,
+  pressed: Vec<u32>,
+  // Key being repeated and when it next fires
+  repeating: Option<(u32, Instant)>
+}
+
+impl Keyboard {
+  pub fn new(keymap: Keymap) -> Self {
+    Keyboard {
+      keymap,
+      modifiers: Modifiers::default(),
+      repeat_delay: Duration::from_millis(600),
+      repeat_rate: 25,
+      pressed: Vec::new(),
+      repeating: None
+    }
+  }
+
+  // Keysym ignoring shift and caps lock, e.g. for matching shortcuts
+  pub fn plain_keysym(self: &Self, scancode: u32) -> Keysym {
+    return self.keymap.keys.get(&scancode).map(|(plain, _)| *plain).unwrap_or(0);
+  }
+
+  fn keysym(self: &Self, scancode: u32) -> Keysym {
+    let (plain, shifted) = match self.keymap.keys.get(&scancode) {
+      Some(syms) => *syms,
+      None => return 0
+    };
+    let is_letter = keysym_to_char(plain).map(|c| c.is_alphabetic()).unwrap_or(false);
+    let shift = self.modifiers.shift != (self.modifiers.caps_lock && is_letter);
+    return if shift { shifted } else { plain };
+  }
+
+  fn update_modifiers(self: &mut Self, sym: Keysym, pressed: bool) -> bool {
+    let held = |keys: &[Keysym]| {
+      let syms = self.pressed.iter().filter_map(|code| self.keymap.keys.get(code));
+      return syms.map(|(plain, _)| plain).any(|sym| keys.contains(sym));
+    };
+    let mods = Modifiers {
+      shift: held(&[KEY_SHIFT_L, KEY_SHIFT_R]),
+      ctrl: held(&[KEY_CONTROL_L, KEY_CONTROL_R]),
+      alt: held(&[KEY_ALT_L, KEY_ALT_R]),
+      logo: held(&[KEY_SUPER_L]),
+      caps_lock: self.modifiers.caps_lock != (pressed && sym == KEY_CAPS_LOCK)
+    };
+    let changed = mods != self.modifiers;
+    self.modifiers = mods;
+    return changed;
+  }
+
+  // A key changed state at `now`. Returns the translated event, and whether
+  // the modifiers changed.
+  pub fn key(self: &mut Self, scancode: u32, pressed: bool, now: Instant) -> (KeyEvent, bool) {
+    let sym = self.keysym(scancode);
+    let was_pressed = self.pressed.contains(&scancode);
+    self.pressed.retain(|c| *c != scancode);
+    if pressed {
+      self.pressed.push(scancode);
+    }
+    // Hosts send their own repeats as presses without a release
+    let changed = if was_pressed == pressed { false } else { self.update_modifiers(sym, pressed) };
+    if pressed && !MODIFIER_KEYS.contains(&sym) && self.repeat_rate > 0 {
+      self.repeating = Some((scancode, now + self.repeat_delay));
+    }
+    else if !pressed && self.repeating.map(|(c, _)| c == scancode).unwrap_or(false) {
+      self.repeating = None;
+    }
+    let event = KeyEvent { scancode, keysym: sym, pressed, repeat: false };
+    return (event, changed);
+  }
+
+  // Repeats of the held key due by `now`
+  pub fn repeat(self: &mut Self, now: Instant) -> Vec<KeyEvent> {
+    let mut out = Vec::new();
+    let interval = Duration::from_secs(1) / self.repeat_rate.max(1);
+    if let Some((scancode, next)) = self.repeating.as_mut() {
+      while *next <= now {
+        out.push(KeyEvent { scancode: *scancode, keysym: 0, pressed: true, repeat: true });
+        *next += interval;
+      }
+    }
+    for e in out.iter_mut() {
+      e.keysym = self.keysym(e.scancode);
+    }
+    return out;
+  }
+
+  // When the held key next repeats
+  pub fn next_repeat(self: &Self) -> Option<Instant> {
+    return self.repeating.map(|(_, next)| next);
+  }
+
+  // Stop repeating, e.g. when focus moves. Held keys stay held so modifiers
+  // remain right.
+  pub fn stop_repeat(self: &mut Self) {
+    self.repeating = None;
+  }
+}
+
+impl ServerState {
+  pub fn set_keyboard_focus(self: &mut Self, focus: Option<ClientId>) {
+    if focus == self.keyboard_focus {
+      return;
+    }
+    self.keyboard.stop_repeat();
+    if let Some(old) = self.keyboard_focus {
+      self.send(old, Message::KeyboardLeaveEvent);
+    }
+    self.keyboard_focus = focus;
+    if let Some(new) = focus {
+      self.send(new, Message::KeyboardEnterEvent { modifiers: self.keyboard.modifiers });
+      if let Some(c) = self.clients.get(&new) {
+        self.wm.active_output = c.window.output;
+      }
+    }
+    self.update_text_input();
+  }
+
+  pub fn set_keymap(self: &mut Self, keymap: Keymap) {
+    self.keyboard.keymap = keymap;
+    for c in self.clients.values() {
+      c.send(Message::KeymapEvent(self.keyboard.keymap.clone()));
+    }
+  }
+
+  // The active input method takes keys before the focused client
+  fn key_recipient(self: &Self) -> Option<ClientId> {
+    if self.text_input_active.is_some() {
+      return self.input_method;
+    }
+    return self.keyboard_focus;
+  }
+
+  fn send_key(self: &Self, event: KeyEvent) {
+    if let Some(recipient) = self.key_recipient() {
+      self.send(recipient, Message::KeyEvent {
+        scancode: event.scancode,
+        keysym: event.keysym,
+        pressed: event.pressed,
+        repeat: event.repeat
+      });
+    }
+  }
+
+  pub fn key_input(self: &mut Self, scancode: u32, pressed: bool, now: Instant) {
+    let (event, modifiers_changed) = self.keyboard.key(scancode, pressed, now);
+    if !self.hotkey_input(scancode, pressed) {
+      self.send_key(event);
+    }
+    if let (true, Some(recipient)) = (modifiers_changed, self.key_recipient()) {
+      self.send(recipient, Message::ModifiersEvent(self.keyboard.modifiers));
+    }
+  }
+
+  pub fn repeat_keys(self: &mut Self, now: Instant) {
+    for event in self.keyboard.repeat(now) {
+      if !self.hotkeys.is_held(event.scancode) {
+        self.send_key(event);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_translation() {
+    let mut kb = Keyboard::new(Keymap::us());
+    let now = Instant::now();
+    assert_eq!(kb.key(30, true, now).0.keysym, 'a' as Keysym);
+    kb.key(30, false, now);
+    assert!(kb.key(42, true, now).1);
+    assert!(kb.modifiers.shift);
+    assert_eq!(kb.key(2, true, now).0.keysym, '!' as Keysym);
+    kb.key(2, false, now);
+    kb.key(42, false, now);
+    // caps lock only shifts letters
+    kb.key(58, true, now);
+    kb.key(58, false, now);
+    assert!(kb.modifiers.caps_lock);
+    assert_eq!(kb.key(30, true, now).0.keysym, 'A' as Keysym);
+    assert_eq!(kb.key(2, true, now).0.keysym, '1' as Keysym);
+  }
+
+  #[test]
+  fn test_repeat() {
+    let mut kb = Keyboard::new(Keymap::us());
+    kb.repeat_delay = Duration::from_millis(500);
+    kb.repeat_rate = 10;
+    let t0 = Instant::now();
+    kb.key(30, true, t0);
+    assert!(kb.repeat(t0 + Duration::from_millis(499)).is_empty());
+    assert_eq!(kb.repeat(t0 + Duration::from_millis(700)).len(), 3);
+    // modifiers don't repeat or stop the held key
+    kb.key(42, true, t0 + Duration::from_millis(750));
+    let repeats = kb.repeat(t0 + Duration::from_millis(800));
+    let repeat = KeyEvent { scancode: 30, keysym: 'A' as Keysym, pressed: true, repeat: true };
+    assert_eq!(repeats, vec![repeat]);
+    kb.key(30, false, t0 + Duration::from_millis(850));
+    assert!(kb.repeat(t0 + Duration::from_secs(5)).is_empty());
+  }
+
+  #[test]
+  fn test_modifiers_across_focus() {
+    let mut state = ServerState::new(800, 600, Vec::new());
+    let (a_outbox, _a_events) = std::sync::mpsc::channel();
+    let (b_outbox, b_events) = std::sync::mpsc::channel();
+    let a = state.add_client(a_outbox);
+    let b = state.add_client(b_outbox);
+    state.set_keyboard_focus(Some(a));
+    let now = Instant::now();
+    state.key_input(42, true, now);
+
+    // shift is still held when focus moves, and its release still counts
+    state.set_keyboard_focus(Some(b));
+    assert!(state.keyboard.modifiers.shift);
+    assert!(b_events.try_iter().any(|m| matches!(
+      m,
+      Message::KeyboardEnterEvent { modifiers } if modifiers.shift
+    )));
+    state.key_input(42, false, now);
+    assert!(!state.keyboard.modifiers.shift);
+    assert!(b_events.try_iter().any(|m| matches!(m, Message::ModifiersEvent(m) if !m.shift)));
+  }
+}
@@ -1,78 +1,276 @@
-// Library for clients to talk to libcompositor (and vice versa). Currently
-// implemented using unix sockets in this emulator stage. IPC could be replaced.
+// The server half of the compositor: per-client state and the threads that
+// serve clients over the protocol in libprotocol. Clients use libclient.
 
-use serde::{Deserialize, Serialize};
-use std::io::{Error, ErrorKind, Read, Write};
+// Explicit returns and typed self are house style
+#![allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
+
+mod backend;
+mod capture;
+mod composite;
+mod decoration;
+mod dispatch;
+mod draw;
+mod geometry;
+mod hotkey;
+mod idle;
+mod keyboard;
+mod notification;
+mod output;
+mod render;
+mod scale;
+mod scheduler;
+mod subsurface;
+mod swapchain;
+mod surface;
+mod text_input;
+mod tiling;
+mod touch;
+mod window;
+mod wm;
+mod workspace;
+
+pub use libprotocol::{
+  keysym_to_char, CaptureBuffer, CaptureSource, CloseReason, Compression, DecorationMode,
+  Handshake, Keymap, Keysym, Message, Modifiers, OutputId, OutputInfo, PixelFormat, Permission,
+  Rect, Shortcut, SurfaceId, TouchId, Transform, Urgency, DEFAULT_SOCK_PATH, KEY_RETURN, KEY_TAB,
+  MAIN_SURFACE
+};
+
+pub use backend::{draw_frame, present_frame, Backend, HeadlessBackend};
+pub use capture::Frame;
+pub use composite::{blend, DamageTracker, BACKGROUND};
+pub use decoration::{
+  resize_rect, DecorationHit, DecorationLayout, ResizeEdge, BORDER, TITLE_HEIGHT
+};
+pub use dispatch::ClientRequests;
+pub use geometry::GLYPH_SIZE;
+pub use hotkey::{CompositorAction, HotkeyRegistry};
+pub use idle::IdleTracker;
+pub use keyboard::{KeyEvent, Keyboard};
+pub use notification::{NotificationCenter, NotificationClick, NotificationLayout};
+pub use render::Renderer;
+pub use scheduler::{run_frame, FrameScheduler, FrameStats};
+pub use subsurface::SurfaceTree;
+pub use surface::{Surface, MAX_BUFFER_SCALE, MAX_SURFACE_SIZE};
+pub use swapchain::{copy_rects, Swapchain, SWAPCHAIN_LENGTH};
+pub use text_input::TextInput;
+pub use tiling::{tile, Layout};
+pub use touch::TouchTracker;
+pub use window::WindowState;
+pub use wm::{Drag, WindowManager, MIN_WINDOW_SIZE};
+pub use workspace::WORKSPACE_COUNT;
+
+use libprotocol::{
+  check_hello, dispatch_to_server, negotiate_codec, negotiate_compression, recv_message,
+  send_message, Codec, DEFAULT_SURFACE_FORMAT, SUPPORTED_CODECS, SUPPORTED_COMPRESSION
+};
+use std::collections::HashMap;
+use std::io::BufReader;
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time::Instant;
 
-const DEFAULT_SOCK_PATH: &str = "/tmp/gfcomp_sock";
-
-#[derive(Serialize, Deserialize)]
-enum Message {
-  // FORNOW: just a dummy -- could be extended to some sort of versioning in the future
-  Hello,
-  // Server -> Client
-  BufferCreatedEvent(),
-  ResizeEvent {
-    width: usize,
-    height: usize,
-    is_main: bool
-  },
-  Ping,
-  // Client -> Server
-  DamageReport {
-    x: usize,
-    y: usize,
-    dx: usize,
-    dy: usize
-  },
-  Pong
+// FORNOW: every client gets one surface of this size
+const DEFAULT_SURFACE_WIDTH: usize = 400;
+const DEFAULT_SURFACE_HEIGHT: usize = 400;
+
+pub type ClientId = u32;
+
+pub struct ClientState {
+  pub surfaces: SurfaceTree,
+  pub window: WindowState,
+  pub permissions: Vec<Permission>,
+  pub text_input: TextInput,
+  // Drained by the client's writer thread
+  outbox: mpsc::Sender<Message>
 }
 
-fn check_hello(msg: Message) {
-  match msg {
-    Message::Hello => {
-      println!("Got Hello");
+impl ClientState {
+  pub fn send(self: &Self, msg: Message) {
+    // A closed outbox means the client is going away, which its server thread
+    // notices on the next read
+    let _ = self.outbox.send(msg);
+  }
+}
+
+// State shared between the client threads and the compositor loop
+pub struct ServerState {
+  pub clients: HashMap<ClientId, ClientState>,
+  // Last presented frame
+  pub front: Frame,
+  // Permissions granted to any client that asks
+  pub grantable: Vec<Permission>,
+  pub notifications: NotificationCenter,
+  pub idle: IdleTracker,
+  pub outputs: Vec<OutputInfo>,
+  pub keyboard: Keyboard,
+  pub keyboard_focus: Option<ClientId>,
+  pub input_method: Option<ClientId>,
+  pub touch: TouchTracker,
+  pub hotkeys: HotkeyRegistry,
+  pub wm: WindowManager,
+  // What was last composited, to work out what to redraw
+  pub damage: DamageTracker,
+  // Surfaces to send a FrameDoneEvent for after the next frame
+  pub frame_callbacks: Vec<(ClientId, SurfaceId)>,
+  // Client the input method is serving
+  text_input_active: Option<ClientId>,
+  // Whether clients may have the compositor draw their frames
+  pub server_decorations: bool,
+  // Called after clients change anything, so a compositor loop waiting for
+  // changes can run a frame
+  waker: Option<Box<dyn Fn() + Send>>,
+  next_client: ClientId
+}
+
+pub type SharedState = Arc<Mutex<ServerState>>;
+
+impl ServerState {
+  pub fn new(width: usize, height: usize, grantable: Vec<Permission>) -> Self {
+    ServerState {
+      clients: HashMap::new(),
+      front: Frame::new(width, height),
+      grantable,
+      notifications: NotificationCenter::new(),
+      idle: IdleTracker::new(Instant::now()),
+      outputs: Vec::new(),
+      keyboard: Keyboard::new(Keymap::us()),
+      keyboard_focus: None,
+      input_method: None,
+      touch: TouchTracker::new(),
+      hotkeys: HotkeyRegistry::new(),
+      wm: WindowManager::new(),
+      damage: DamageTracker::new(),
+      frame_callbacks: Vec::new(),
+      text_input_active: None,
+      server_decorations: true,
+      waker: None,
+      next_client: 0
     }
-    _ => {
-      panic!("Interlocutor is speaking an unexpected protocol");
+  }
+
+  // Scale of the output clients are shown on
+  pub fn output_scale(self: &Self) -> f64 {
+    return self.outputs.first().map(|o| o.scale).unwrap_or(1.0);
+  }
+
+  pub fn set_waker(self: &mut Self, waker: Box<dyn Fn() + Send>) {
+    self.waker = Some(waker);
+  }
+
+  fn wake(self: &Self) {
+    if let Some(waker) = &self.waker {
+      waker();
     }
   }
-}
 
-fn send_message(msg: Message, stream: &mut UnixStream) -> std::io::Result<()> {
-  let encoded: Vec<u8> = bincode::serialize(&msg).unwrap();
-  let l: usize = encoded.len();
-  let l_encoded: Vec<u8> = bincode::serialize(&l).unwrap();
-  assert_eq!(l_encoded.len(), 4);
-  stream.write_all(&l_encoded[..])?;
-  stream.write_all(&encoded[..])?;
-  return Ok(());
+  pub fn send(self: &Self, client: ClientId, msg: Message) {
+    if let Some(c) = self.clients.get(&client) {
+      c.send(msg);
+    }
+  }
+
+  fn remove_client(self: &mut Self, id: ClientId) {
+    self.clients.remove(&id);
+    self.notifications.remove_client(id);
+    self.idle.remove_client(id);
+    self.touch.remove_client(id);
+    self.hotkeys.remove_client(id);
+    self.wm.remove(id);
+    self.frame_callbacks.retain(|(c, _)| *c != id);
+    self.retile();
+    if self.input_method == Some(id) {
+      self.input_method = None;
+    }
+    if self.keyboard_focus == Some(id) {
+      self.set_keyboard_focus(None);
+    }
+    self.update_text_input();
+  }
+
+  fn add_client(self: &mut Self, outbox: mpsc::Sender<Message>) -> ClientId {
+    let id = self.next_client;
+    self.next_client += 1;
+    self.clients.insert(id, ClientState {
+      surfaces: SurfaceTree::new(
+        DEFAULT_SURFACE_WIDTH,
+        DEFAULT_SURFACE_HEIGHT,
+        DEFAULT_SURFACE_FORMAT
+      ),
+      window: WindowState::new(0, 0),
+      permissions: Vec::new(),
+      text_input: TextInput::new(),
+      outbox
+    });
+    self.manage_window(id);
+    return id;
+  }
 }
-fn recv_message(stream: &mut UnixStream) -> std::io::Result<Message> {
-  let mut l_encoded: Vec<u8> = Vec::with_capacity(4);
-  stream.read(&mut l_encoded[..]);
-  let l: usize = bincode::deserialize(&l_encoded[..]).unwrap();
-  let mut encoded: Vec<u8> = Vec::with_capacity(l);
-  stream.read(&mut encoded[..]);
-  let msg: Message = bincode::deserialize(&encoded[..]).unwrap();
-  return Ok(msg);
+
+fn server_thread(mut stream: UnixStream, state: SharedState) -> std::io::Result<()> {
+  send_message(Message::Hello(Handshake::supported()), &mut stream)?;
+  let client = check_hello(recv_message(&mut stream)?)?;
+  let codec = negotiate_codec(SUPPORTED_CODECS, &client.codecs);
+  println!(
+    "Client uploads with {:?} and speaks {:?}",
+    negotiate_compression(SUPPORTED_COMPRESSION, &client.compression),
+    codec
+  );
+
+  let (outbox, outgoing) = mpsc::channel();
+  let mut stream_writer = stream.try_clone()?;
+  thread::spawn(move || -> std::io::Result<()> {
+    let codec = codec.codec();
+    for m in outgoing {
+      codec.write(&m, &mut stream_writer)?;
+    }
+    return Ok(());
+  });
+  let mut locked = state.lock().unwrap();
+  let id = locked.add_client(outbox.clone());
+  for output in &locked.outputs {
+    outbox.send(Message::OutputEvent(output.clone())).unwrap();
+    outbox.send(locked.workspace_event(output.id)).unwrap();
+  }
+  outbox.send(Message::KeymapEvent(locked.keyboard.keymap.clone())).unwrap();
+  // New windows get the keyboard
+  locked.set_keyboard_focus(Some(id));
+  // Placement may already have resized the window, e.g. into a tile
+  let main = locked.clients[&id].surfaces.get(MAIN_SURFACE).unwrap();
+  let (width, height) = (main.width, main.height);
+  locked.wake();
+  drop(locked);
+  outbox.send(Message::ResizeEvent { width, height, is_main: true }).unwrap();
+  drop(outbox);
+
+  let result = serve_client(&mut BufReader::new(stream), codec.codec(), &state, id);
+  let mut locked = state.lock().unwrap();
+  locked.remove_client(id);
+  locked.wake();
+  return result;
 }
 
-fn server_thread(mut stream: UnixStream) -> std::io::Result<()> {
-  send_message(Message::Hello, &mut stream)?;
-  check_hello(recv_message(&mut stream)?);
-  return Ok(());
+fn serve_client(
+  stream: &mut BufReader<UnixStream>, codec: Box<dyn Codec>, state: &SharedState, id: ClientId
+) -> std::io::Result<()> {
+  loop {
+    let m = codec.read(stream)?;
+    let mut locked = state.lock().unwrap();
+    dispatch_to_server(&mut ClientRequests { state: &mut locked, id }, m)?;
+    locked.wake();
+  }
 }
 
-fn bind_unix_listener() -> std::io::Result<()> {
+pub fn bind_unix_listener(state: SharedState) -> std::io::Result<()> {
+  // Clear out a socket left behind by a previous run
+  let _ = std::fs::remove_file(DEFAULT_SOCK_PATH);
   let listener = UnixListener::bind(DEFAULT_SOCK_PATH)?;
   for stream in listener.incoming() {
     match stream {
       Ok(stream) => {
-        thread::spawn(|| server_thread(stream));
+        let state = state.clone();
+        thread::spawn(move || server_thread(stream, state));
       }
       Err(_) => {
         break;
@@ -82,35 +280,50 @@ fn bind_unix_listener() -> std::io::Result<()> {
   return Ok(());
 }
 
-fn client_thread(
-  mut stream: UnixStream, msg_queue: mpsc::Receiver<Message>
-) -> std::io::Result<()> {
-  check_hello(recv_message(&mut stream)?);
-  send_message(Message::Hello, &mut stream)?;
-  let (in_msg_sender, in_msg_queue) = mpsc::channel();
-  let mut stream_reader = stream.try_clone().unwrap();
-  thread::spawn(move || -> std::io::Result<()> {
-    loop {
-      let m = recv_message(&mut stream_reader)?;
-      in_msg_sender.send(m).unwrap();
-    }
-  });
-  loop {
-    if let Ok(m) = msg_queue.try_recv() {
-      send_message(m, &mut stream)?;
-    }
-    if let Ok(_m) = in_msg_queue.try_recv() {}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::ErrorKind;
+
+  #[test]
+  fn test_bad_request_disconnects_client() {
+    let state = Arc::new(Mutex::new(ServerState::new(800, 600, Vec::new())));
+    let (outbox, _events) = mpsc::channel();
+    let other = state.lock().unwrap().add_client(outbox);
+    let (server_end, mut client_end) = UnixStream::pair().unwrap();
+    let server_state = state.clone();
+    let server = thread::spawn(move || server_thread(server_end, server_state));
+    check_hello(recv_message(&mut client_end).unwrap()).unwrap();
+    send_message(Message::Hello(Handshake::supported()), &mut client_end).unwrap();
+
+    // a size that can't be allocated fails the request, not the server
+    let codec = negotiate_codec(SUPPORTED_CODECS, SUPPORTED_CODECS).codec();
+    let (width, height) = (usize::MAX, usize::MAX);
+    let huge = Message::CreateSubsurface { id: 1, parent: MAIN_SURFACE, width, height };
+    codec.write(&huge, &mut client_end).unwrap();
+    assert_eq!(server.join().unwrap().unwrap_err().kind(), ErrorKind::InvalidInput);
+    let locked = state.lock().unwrap();
+    assert_eq!(locked.clients.keys().cloned().collect::<Vec<ClientId>>(), vec![other]);
   }
-}
 
-fn connect_to_server(msg_queue: mpsc::Receiver<Message>) -> () {
-  match UnixStream::connect(DEFAULT_SOCK_PATH) {
-    Ok(sock) => {
-      thread::spawn(move || client_thread(sock, msg_queue));
-    }
-    Err(e) => {
-      println!("Failed to connect: {:?}", e);
-      return;
+  #[test]
+  fn test_buffer_scale_bounds() {
+    let mut state = ServerState::new(800, 600, Vec::new());
+    let (outbox, _events) = mpsc::channel();
+    let id = state.add_client(outbox);
+    let mut requests = ClientRequests { state: &mut state, id };
+    for scale in [0, MAX_BUFFER_SCALE + 1, u32::MAX] {
+      let m = Message::SetBufferScale { surface: MAIN_SURFACE, scale };
+      let err = dispatch_to_server(&mut requests, m).unwrap_err();
+      assert_eq!(err.kind(), ErrorKind::InvalidInput);
     }
-  };
+    let m = Message::SetBufferScale { surface: MAIN_SURFACE, scale: MAX_BUFFER_SCALE };
+    dispatch_to_server(&mut requests, m).unwrap();
+    let main = state.clients[&id].surfaces.get(MAIN_SURFACE).unwrap();
+    assert_eq!((main.scale, main.width, main.height), (MAX_BUFFER_SCALE, 3200, 3200));
+    // wide windows get a buffer no wider than a client could ask for
+    state.configure_window(id, Rect::new(0, 0, 2000, 100));
+    let main = state.clients[&id].surfaces.get(MAIN_SURFACE).unwrap();
+    assert_eq!((main.width, main.height), (MAX_SURFACE_SIZE, 800));
+  }
 }
@@ -0,0 +1,219 @@
+// Desktop notifications posted by clients. The compositor shows them stacked
+// in the top right corner of the output, newest first, until they expire,
+// the user clicks them away or invokes an action, or the client closes them.
+
+use crate::geometry::{wrap_text, Rect, GLYPH_SIZE};
+use crate::{ClientId, Urgency};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(5000);
+const MAX_VISIBLE: usize = 5;
+pub const NOTIFICATION_WIDTH: i32 = 240;
+const MARGIN: i32 = 8;
+const PADDING: i32 = 6;
+const LINE_HEIGHT: i32 = GLYPH_SIZE + 3;
+const BUTTON_HEIGHT: i32 = GLYPH_SIZE + 6;
+const MAX_BODY_LINES: usize = 4;
+
+pub struct Notification {
+  pub client: ClientId,
+  // Chosen by the client; posting again with the same id replaces it
+  pub id: u32,
+  pub summary: String,
+  pub body: String,
+  pub urgency: Urgency,
+  pub actions: Vec<String>,
+  expires: Option<Instant>
+}
+
+// Where each part of a visible notification goes on screen
+pub struct NotificationLayout {
+  pub client: ClientId,
+  pub id: u32,
+  pub urgency: Urgency,
+  pub rect: Rect,
+  // Text lines with the position of their first glyph
+  pub lines: Vec<(i32, i32, String)>,
+  pub buttons: Vec<(Rect, String)>
+}
+
+pub enum NotificationClick {
+  Action(String),
+  Dismiss
+}
+
+pub struct NotificationCenter {
+  // Oldest first
+  notifications: Vec<Notification>
+}
+
+impl NotificationCenter {
+  pub fn new() -> Self {
+    NotificationCenter { notifications: Vec::new() }
+  }
+
+  // `timeout_ms` of None picks the default for the urgency, Some(0) never
+  // expires
+  #[allow(clippy::too_many_arguments)]
+  pub fn post(
+    self: &mut Self, client: ClientId, id: u32, summary: String, body: String, urgency: Urgency,
+    timeout_ms: Option<u32>, actions: Vec<String>, now: Instant
+  ) {
+    let expires = match (timeout_ms, urgency) {
+      (Some(0), _) | (None, Urgency::Critical) => None,
+      (Some(ms), _) => Some(now + Duration::from_millis(ms as u64)),
+      (None, _) => Some(now + DEFAULT_TIMEOUT)
+    };
+    self.remove(client, id);
+    self.notifications.push(Notification {
+      client,
+      id,
+      summary,
+      body,
+      urgency,
+      actions,
+      expires
+    });
+  }
+
+  pub fn remove(self: &mut Self, client: ClientId, id: u32) -> Option<Notification> {
+    let ind = self.notifications.iter().position(|n| n.client == client && n.id == id)?;
+    return Some(self.notifications.remove(ind));
+  }
+
+  pub fn remove_client(self: &mut Self, client: ClientId) {
+    self.notifications.retain(|n| n.client != client);
+  }
+
+  // Drop expired notifications, returning them so their clients can be told
+  pub fn expire(self: &mut Self, now: Instant) -> Vec<Notification> {
+    let (expired, kept) = self
+      .notifications
+      .drain(..)
+      .partition(|n| n.expires.map(|t| t <= now).unwrap_or(false));
+    self.notifications = kept;
+    return expired;
+  }
+
+  // When the next notification expires
+  pub fn next_expiry(self: &Self) -> Option<Instant> {
+    return self.notifications.iter().filter_map(|n| n.expires).min();
+  }
+
+  pub fn layout(self: &Self, output_width: i32) -> Vec<NotificationLayout> {
+    let chars_per_line = ((NOTIFICATION_WIDTH - 2 * PADDING) / GLYPH_SIZE) as usize;
+    let x = output_width - MARGIN - NOTIFICATION_WIDTH;
+    let mut y = MARGIN;
+    let mut out = Vec::new();
+    for n in self.notifications.iter().rev().take(MAX_VISIBLE) {
+      let mut lines = Vec::new();
+      let mut line_y = y + PADDING;
+      let summary = wrap_text(&n.summary, chars_per_line);
+      let body = wrap_text(&n.body, chars_per_line);
+      for text in summary.into_iter().take(1).chain(body.into_iter().take(MAX_BODY_LINES)) {
+        lines.push((x + PADDING, line_y, text));
+        line_y += LINE_HEIGHT;
+      }
+      let mut buttons = Vec::new();
+      let mut button_x = x + PADDING;
+      for action in &n.actions {
+        let width = GLYPH_SIZE * action.chars().count() as i32 + 2 * PADDING;
+        buttons.push((Rect::new(button_x, line_y, width, BUTTON_HEIGHT), action.clone()));
+        button_x += width + PADDING;
+      }
+      if !buttons.is_empty() {
+        line_y += BUTTON_HEIGHT + PADDING / 2;
+      }
+      let rect = Rect::new(x, y, NOTIFICATION_WIDTH, line_y - y + PADDING / 2);
+      y += rect.height + MARGIN;
+      out.push(NotificationLayout {
+        client: n.client,
+        id: n.id,
+        urgency: n.urgency,
+        rect,
+        lines,
+        buttons
+      });
+    }
+    return out;
+  }
+
+  // Work out what a click at (x, y) hits, removing the notification if it
+  // hit one
+  pub fn click(
+    self: &mut Self, x: i32, y: i32, output_width: i32
+  ) -> Option<(Notification, NotificationClick)> {
+    for l in self.layout(output_width) {
+      if !l.rect.contains(x, y) {
+        continue;
+      }
+      let click = match l.buttons.iter().find(|(r, _)| r.contains(x, y)) {
+        Some((_, action)) => NotificationClick::Action(action.clone()),
+        None => NotificationClick::Dismiss
+      };
+      return Some((self.remove(l.client, l.id).unwrap(), click));
+    }
+    return None;
+  }
+}
+
+impl Default for NotificationCenter {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn post(center: &mut NotificationCenter, id: u32, urgency: Urgency, now: Instant) {
+    center.post(
+      0,
+      id,
+      format!("Summary {}", id),
+      "Body".to_string(),
+      urgency,
+      None,
+      vec!["Resign".to_string()],
+      now
+    );
+  }
+
+  #[test]
+  fn test_expiry() {
+    let mut center = NotificationCenter::new();
+    let now = Instant::now();
+    post(&mut center, 1, Urgency::Normal, now);
+    post(&mut center, 2, Urgency::Critical, now);
+    assert!(center.expire(now).is_empty());
+    let expired = center.expire(now + DEFAULT_TIMEOUT);
+    assert_eq!(expired.iter().map(|n| n.id).collect::<Vec<u32>>(), vec![1]);
+    // critical ones wait for the user
+    assert!(center.expire(now + 100 * DEFAULT_TIMEOUT).is_empty());
+  }
+
+  #[test]
+  fn test_layout_and_click() {
+    let mut center = NotificationCenter::new();
+    let now = Instant::now();
+    post(&mut center, 1, Urgency::Normal, now);
+    post(&mut center, 2, Urgency::Normal, now);
+    let layout = center.layout(800);
+    // newest on top, stacked downwards
+    assert_eq!(layout[0].id, 2);
+    assert!(layout[1].rect.y >= layout[0].rect.y + layout[0].rect.height);
+    assert_eq!(layout[0].lines[0].2, "Summary 2");
+
+    let (button, _) = layout[1].buttons[0];
+    match center.click(button.x + 1, button.y + 1, 800) {
+      Some((n, NotificationClick::Action(action))) => {
+        assert_eq!((n.id, action.as_str()), (1, "Resign"));
+      }
+      _ => panic!("Expected an action click")
+    }
+    let rect = center.layout(800)[0].rect;
+    assert!(matches!(center.click(rect.x, rect.y, 800), Some((_, NotificationClick::Dismiss))));
+    assert!(center.click(rect.x, rect.y, 800).is_none());
+  }
+}
@@ -0,0 +1,57 @@
+// Outputs the compositor presents on, advertised to every client at connect
+// and again whenever something about an output changes. Windows on an output
+// that goes away move to the first one left.
+
+use crate::{ClientId, Message, OutputId, OutputInfo, ServerState};
+
+impl ServerState {
+  // Add or update an output, telling every client
+  pub fn set_output(self: &mut Self, info: OutputInfo) {
+    let added = match self.outputs.iter_mut().find(|o| o.id == info.id) {
+      Some(o) if *o == info => return,
+      Some(o) => {
+        *o = info.clone();
+        false
+      }
+      None => {
+        self.outputs.push(info.clone());
+        true
+      }
+    };
+    // Windows placed before there were any outputs go on the first one
+    if added && self.outputs.len() == 1 && info.id != self.wm.active_output {
+      self.wm.active_output = info.id;
+      let clients: Vec<ClientId> = self.clients.keys().cloned().collect();
+      for client in clients {
+        self.move_to_output(client, info.id);
+      }
+    }
+    for c in self.clients.values() {
+      c.send(Message::OutputEvent(info.clone()));
+      if added {
+        c.send(self.workspace_event(info.id));
+      }
+    }
+  }
+
+  pub fn remove_output(self: &mut Self, id: OutputId) {
+    let count = self.outputs.len();
+    self.outputs.retain(|o| o.id != id);
+    if self.outputs.len() != count {
+      for c in self.clients.values() {
+        c.send(Message::OutputRemovedEvent { id });
+      }
+      self.wm.workspaces.remove(&id);
+      if let Some(first) = self.outputs.first().map(|o| o.id) {
+        if self.wm.active_output == id {
+          self.wm.active_output = first;
+        }
+        let moved: Vec<ClientId> =
+          self.clients.iter().filter(|(_, c)| c.window.output == id).map(|(c, _)| *c).collect();
+        for client in moved {
+          self.move_to_output(client, first);
+        }
+      }
+    }
+  }
+}
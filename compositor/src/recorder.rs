@@ -0,0 +1,191 @@
+// Optional recording of presented frames, for bug reports and regression
+// demos. Frames identical to the last recorded one are skipped, so an idle
+// display costs nothing. Every recorded frame keeps its presentation time,
+// in microseconds since recording started.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+// Nominal rate in the video header; actual timing is in the frame timestamps
+const NOMINAL_FPS: u32 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordFormat {
+  // Numbered frame_NNNNNN.png files plus timestamps.txt in a directory
+  Png,
+  // As Png, but binary PPM images
+  Ppm,
+  // A single uncompressed YUV4MPEG2 (4:4:4) video, with each timestamp
+  // stored as an `Xts=` frame parameter
+  Y4m
+}
+
+impl RecordFormat {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "png" => Some(RecordFormat::Png),
+      "ppm" => Some(RecordFormat::Ppm),
+      "y4m" => Some(RecordFormat::Y4m),
+      _ => None
+    }
+  }
+}
+
+pub struct Recorder {
+  format: RecordFormat,
+  path: PathBuf,
+  width: usize,
+  height: usize,
+  start: Instant,
+  frame_count: usize,
+  last_frame: Vec<u8>,
+  // Video stream or timestamps.txt, depending on the format
+  out: BufWriter<File>
+}
+
+fn rgba_to_yuv444(frame: &[u8], out: &mut Vec<u8>) {
+  let n = frame.len() / 4;
+  let mut ys = Vec::with_capacity(n);
+  let mut us = Vec::with_capacity(n);
+  let mut vs = Vec::with_capacity(n);
+  for pix in frame.chunks_exact(4) {
+    let (r, g, b) = (pix[0] as i32, pix[1] as i32, pix[2] as i32);
+    ys.push((((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8);
+    us.push((((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8);
+    vs.push((((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8);
+  }
+  out.extend_from_slice(&ys);
+  out.extend_from_slice(&us);
+  out.extend_from_slice(&vs);
+}
+
+impl Recorder {
+  // `path` is the output directory for image sequences or the file for
+  // video
+  pub fn new(
+    path: &Path, format: RecordFormat, width: usize, height: usize
+  ) -> std::io::Result<Self> {
+    let mut out = match format {
+      RecordFormat::Png | RecordFormat::Ppm => {
+        fs::create_dir_all(path)?;
+        BufWriter::new(File::create(path.join("timestamps.txt"))?)
+      }
+      RecordFormat::Y4m => BufWriter::new(File::create(path)?)
+    };
+    if format == RecordFormat::Y4m {
+      writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, NOMINAL_FPS)?;
+    }
+    return Ok(Recorder {
+      format,
+      path: path.to_path_buf(),
+      width,
+      height,
+      start: Instant::now(),
+      frame_count: 0,
+      last_frame: Vec::new(),
+      out
+    });
+  }
+
+  pub fn frame_count(self: &Self) -> usize {
+    return self.frame_count;
+  }
+
+  // Record an RGBA frame presented at `time`, unless nothing changed since
+  // the last recorded frame. Returns whether the frame was written.
+  pub fn record(self: &mut Self, frame: &[u8], time: Instant) -> std::io::Result<bool> {
+    if frame.len() != 4 * self.width * self.height {
+      return Err(Error::new(ErrorKind::InvalidInput, "Frame does not match recording size"));
+    }
+    if frame == &self.last_frame[..] {
+      return Ok(false);
+    }
+    let timestamp = time.saturating_duration_since(self.start).as_micros();
+    match self.format {
+      RecordFormat::Png | RecordFormat::Ppm => {
+        let ext = if self.format == RecordFormat::Png { "png" } else { "ppm" };
+        let name = format!("frame_{:06}.{}", self.frame_count, ext);
+        self.write_image(&self.path.join(&name), frame)?;
+        writeln!(self.out, "{} {}", name, timestamp)?;
+      }
+      RecordFormat::Y4m => {
+        let mut planes = Vec::with_capacity(3 * self.width * self.height);
+        rgba_to_yuv444(frame, &mut planes);
+        writeln!(self.out, "FRAME Xts={}", timestamp)?;
+        self.out.write_all(&planes)?;
+      }
+    }
+    self.out.flush()?;
+    self.last_frame = frame.to_vec();
+    self.frame_count += 1;
+    return Ok(true);
+  }
+
+  fn write_image(self: &Self, path: &Path, frame: &[u8]) -> std::io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    if self.format == RecordFormat::Ppm {
+      write!(w, "P6\n{} {}\n255\n", self.width, self.height)?;
+      for pix in frame.chunks_exact(4) {
+        w.write_all(&pix[..3])?;
+      }
+      return w.flush();
+    }
+    let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(Error::other)?;
+    writer.write_image_data(frame).map_err(Error::other)?;
+    return Ok(());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::time::Duration;
+
+  fn test_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("gfcomp_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    return dir;
+  }
+
+  #[test]
+  fn test_ppm_sequence() {
+    let dir = test_dir("ppm");
+    let mut rec = Recorder::new(&dir, RecordFormat::Ppm, 2, 1).unwrap();
+    let t0 = Instant::now();
+    let frame = [1, 2, 3, 0xff, 4, 5, 6, 0xff];
+    assert!(rec.record(&frame, t0).unwrap());
+    // undamaged frames are skipped
+    assert!(!rec.record(&frame, t0 + Duration::from_millis(10)).unwrap());
+    assert!(rec.record(&[0; 8], t0 + Duration::from_millis(20)).unwrap());
+    assert_eq!(rec.frame_count(), 2);
+    assert!(rec.record(&[0; 4], t0).is_err());
+
+    let ppm = fs::read(dir.join("frame_000000.ppm")).unwrap();
+    assert_eq!(ppm, b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06".to_vec());
+    let timestamps = fs::read_to_string(dir.join("timestamps.txt")).unwrap();
+    assert_eq!(timestamps.lines().count(), 2);
+    assert!(timestamps.starts_with("frame_000000.ppm "));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_y4m() {
+    let dir = test_dir("y4m");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("out.y4m");
+    let mut rec = Recorder::new(&path, RecordFormat::Y4m, 1, 1).unwrap();
+    rec.record(&[0xff, 0xff, 0xff, 0xff], Instant::now()).unwrap();
+    let data = fs::read(&path).unwrap();
+    let header = b"YUV4MPEG2 W1 H1 F30:1 Ip A1:1 C444\nFRAME Xts=";
+    assert!(data.starts_with(header));
+    // white is full scale luma with neutral chroma
+    assert_eq!(data[data.len() - 3..], [235, 128, 128]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
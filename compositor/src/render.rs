@@ -0,0 +1,119 @@
+// Drawing output frames: client surfaces and their decorations composited
+// where they changed, with the rest of the compositor's own UI (the resize
+// outline, notifications) on top. Frames go into a swapchain, which keeps
+// what didn't change.
+
+use crate::decoration::DecorationLayout;
+use crate::draw::Canvas;
+use crate::swapchain::Swapchain;
+use crate::{CloseReason, Message, Rect, ServerState, Urgency};
+use std::time::Instant;
+
+pub struct Renderer {
+  // Compositor-drawn UI of the last frame, by the rect it covers and the text
+  // in it
+  overlays: Vec<(Rect, String)>
+}
+
+impl Renderer {
+  pub fn new() -> Self {
+    Renderer { overlays: Vec::new() }
+  }
+
+  // Draw the next frame into the swapchain where it changed and swap it in,
+  // returning whether anything changed. Unchanged frames aren't drawn and
+  // there is nothing new to present.
+  pub fn render(self: &mut Self, server: &mut ServerState, swapchain: &mut Swapchain) -> bool {
+    let (width, height) = (server.front.width, server.front.height);
+    let mut damage = server.take_output_damage();
+    let overlays = overlays(server);
+    if overlays != self.overlays {
+      damage.extend(overlays.iter().chain(&self.overlays).map(|(rect, _)| *rect));
+    }
+    if damage.is_empty() {
+      return false;
+    }
+    self.overlays = overlays;
+
+    let frame = swapchain.back_buffer();
+    server.composite(frame, &damage);
+    let mut canvas = Canvas::new(frame, width, height);
+    if let Some(outline) = server.resize_outline() {
+      canvas.stroke_rect(outline, 1, [0xff, 0xff, 0xff, 0xff]);
+    }
+    render_notifications(&mut canvas, server);
+    swapchain.swap(damage);
+    return true;
+  }
+
+  // Whether the next frame has anything to do: something to repaint, or
+  // frame callbacks to answer
+  pub fn needs_frame(self: &Self, server: &ServerState) -> bool {
+    return server.has_output_damage()
+      || overlays(server) != self.overlays
+      || server.frame_callbacks_due();
+  }
+}
+
+impl Default for Renderer {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ServerState {
+  // Time-driven work due by `now`: expiring notifications, idle timeouts and
+  // key repeat
+  pub fn tick(self: &mut Self, now: Instant) {
+    for n in self.notifications.expire(now) {
+      let reason = CloseReason::Expired;
+      self.send(n.client, Message::NotificationClosedEvent { id: n.id, reason });
+    }
+    self.update_idle(now);
+    self.repeat_keys(now);
+  }
+
+  // When `tick` next has something to do, if ever without other changes
+  pub fn next_tick(self: &Self) -> Option<Instant> {
+    let times = [
+      self.notifications.next_expiry(),
+      self.idle.next_timeout(),
+      self.keyboard.next_repeat()
+    ];
+    return times.iter().flatten().min().cloned();
+  }
+}
+
+fn overlays(server: &ServerState) -> Vec<(Rect, String)> {
+  let mut out = Vec::new();
+  for (id, rect) in server.decorated_windows() {
+    out.push((DecorationLayout::new(rect).frame, server.clients[&id].window.title.clone()));
+  }
+  if let Some(outline) = server.resize_outline() {
+    out.push((outline, String::new()));
+  }
+  for l in server.notifications.layout(server.front.width as i32) {
+    let text: Vec<&str> = l.lines.iter().map(|(_, _, t)| t.as_str()).collect();
+    out.push((l.rect, text.join("\n")));
+  }
+  return out;
+}
+
+fn render_notifications(canvas: &mut Canvas, server: &ServerState) {
+  for l in server.notifications.layout(canvas.width as i32) {
+    let border: [u8; 4] = match l.urgency {
+      Urgency::Low => [0x77, 0x77, 0x77, 0xff],
+      Urgency::Normal => [0x44, 0x66, 0x99, 0xff],
+      Urgency::Critical => [0xcc, 0x22, 0x22, 0xff]
+    };
+    canvas.fill_rect(l.rect, [0x22, 0x22, 0x28, 0xff]);
+    canvas.stroke_rect(l.rect, 2, border);
+    for (x, y, text) in &l.lines {
+      canvas.draw_text(*x, *y, text, [0xee, 0xee, 0xee, 0xff]);
+    }
+    for (rect, action) in &l.buttons {
+      canvas.fill_rect(*rect, [0x44, 0x44, 0x4c, 0xff]);
+      canvas.draw_text(rect.x + 6, rect.y + 3, action, [0xff, 0xff, 0xff, 0xff]);
+    }
+  }
+}
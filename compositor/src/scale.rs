@@ -0,0 +1,129 @@
+// Resampling client buffers to the output's pixel density. Integer ratios
+// replicate pixels so text stays sharp. Shrinking to less than half averages
+// every source pixel a destination pixel covers, since sampling would skip
+// some; anything else is filtered bilinearly. Both are correct on
+// premultiplied pixels.
+
+use libprotocol::INTERNAL_BYTES_PER_PIXEL;
+
+// Size in output pixels of a buffer drawn at `buffer_scale` on an output of
+// `output_scale`
+pub fn scaled_size(
+  width: usize, height: usize, buffer_scale: u32, output_scale: f64
+) -> (usize, usize) {
+  let factor = output_scale / buffer_scale as f64;
+  return ((width as f64 * factor).round() as usize, (height as f64 * factor).round() as usize);
+}
+
+// For each of `dst_len` pixels spanning `src_len` ones, the source pixels it
+// covers and by how much, adding up to 1
+fn coverage(src_len: usize, dst_len: usize) -> Vec<Vec<(usize, f64)>> {
+  let ratio = src_len as f64 / dst_len as f64;
+  let mut out = Vec::new();
+  for d in 0..dst_len {
+    let (start, end) = (d as f64 * ratio, (d + 1) as f64 * ratio);
+    let mut weights = Vec::new();
+    for s in (start as usize)..(end.ceil() as usize).min(src_len) {
+      let covered = (end.min(s as f64 + 1.0) - start.max(s as f64)) / ratio;
+      weights.push((s, covered));
+    }
+    out.push(weights);
+  }
+  return out;
+}
+
+// Average the source pixels each destination pixel covers
+fn area_average(
+  src: &[u8], src_width: usize, src_height: usize, dst_width: usize, dst_height: usize
+) -> Vec<u8> {
+  let bpp = INTERNAL_BYTES_PER_PIXEL;
+  let (columns, rows) = (coverage(src_width, dst_width), coverage(src_height, dst_height));
+  let mut out = vec![0u8; bpp * dst_width * dst_height];
+  for (y, row) in rows.iter().enumerate() {
+    for (x, column) in columns.iter().enumerate() {
+      let ind = bpp * (y * dst_width + x);
+      for c in 0..bpp {
+        let mut sum = 0.0;
+        for (sy, wy) in row {
+          for (sx, wx) in column {
+            sum += src[bpp * (sy * src_width + sx) + c] as f64 * wy * wx;
+          }
+        }
+        out[ind + c] = sum.round().min(255.0) as u8;
+      }
+    }
+  }
+  return out;
+}
+
+// Resample a premultiplied RGBA image to (dst_width, dst_height)
+pub fn resample(
+  src: &[u8], src_width: usize, src_height: usize, dst_width: usize, dst_height: usize
+) -> Vec<u8> {
+  let bpp = INTERNAL_BYTES_PER_PIXEL;
+  if (src_width, src_height) == (dst_width, dst_height) {
+    return src.to_vec();
+  }
+  let mut out = vec![0u8; bpp * dst_width * dst_height];
+  if src_width == 0 || src_height == 0 {
+    return out;
+  }
+  if 2 * dst_width < src_width || 2 * dst_height < src_height {
+    return area_average(src, src_width, src_height, dst_width, dst_height);
+  }
+  let integer_up = dst_width.is_multiple_of(src_width)
+    && dst_height.is_multiple_of(src_height)
+    && dst_width / src_width == dst_height / src_height;
+  for y in 0..dst_height {
+    for x in 0..dst_width {
+      let ind = bpp * (y * dst_width + x);
+      if integer_up {
+        let (sx, sy) = (x * src_width / dst_width, y * src_height / dst_height);
+        let src_ind = bpp * (sy * src_width + sx);
+        out[ind..ind + bpp].copy_from_slice(&src[src_ind..src_ind + bpp]);
+        continue;
+      }
+      // Sample at the center of the destination pixel
+      let fx = ((x as f64 + 0.5) * src_width as f64 / dst_width as f64 - 0.5).max(0.0);
+      let fy = ((y as f64 + 0.5) * src_height as f64 / dst_height as f64 - 0.5).max(0.0);
+      let (x0, y0) = ((fx as usize).min(src_width - 1), (fy as usize).min(src_height - 1));
+      let (x1, y1) = ((x0 + 1).min(src_width - 1), (y0 + 1).min(src_height - 1));
+      let (tx, ty) = (fx - x0 as f64, fy - y0 as f64);
+      for c in 0..bpp {
+        let at = |sx: usize, sy: usize| src[bpp * (sy * src_width + sx) + c] as f64;
+        let top = at(x0, y0) * (1.0 - tx) + at(x1, y0) * tx;
+        let bottom = at(x0, y1) * (1.0 - tx) + at(x1, y1) * tx;
+        out[ind + c] = (top * (1.0 - ty) + bottom * ty).round() as u8;
+      }
+    }
+  }
+  return out;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_resample() {
+    assert_eq!(scaled_size(100, 60, 2, 1.5), (75, 45));
+    let src = [10, 0, 0, 255, 30, 0, 0, 255];
+    // integer upscaling replicates pixels
+    let up = resample(&src, 2, 1, 4, 2);
+    let reds: Vec<u8> = up.chunks_exact(4).map(|p| p[0]).collect();
+    assert_eq!(reds, vec![10, 10, 30, 30, 10, 10, 30, 30]);
+    // downscaling by two averages neighbours
+    assert_eq!(resample(&src, 2, 1, 1, 1), vec![20, 0, 0, 255]);
+    // fractional scaling blends, keeping the edges
+    let reds: Vec<u8> = resample(&src, 2, 1, 3, 1).chunks_exact(4).map(|p| p[0]).collect();
+    assert_eq!(reds, vec![10, 20, 30]);
+    // shrinking further averages every pixel instead of skipping some
+    let src: Vec<u8> = [0, 40, 80, 120, 160, 200].iter().flat_map(|r| [*r, 0, 0, 255]).collect();
+    let reds: Vec<u8> = resample(&src, 6, 1, 2, 1).chunks_exact(4).map(|p| p[0]).collect();
+    assert_eq!(reds, vec![40, 160]);
+    // uneven ratios weigh pixels by how much of them is covered
+    let reds: Vec<u8> = resample(&src[..20], 5, 1, 2, 1).chunks_exact(4).map(|p| p[0]).collect();
+    assert_eq!(reds, vec![32, 128]);
+    assert_eq!(resample(&src, 6, 1, 2, 1)[3], 255);
+  }
+}
@@ -0,0 +1,277 @@
+// Frame scheduling. Frames start at the output's refresh rate, but a frame
+// only repaints and presents when something on screen changed; otherwise it
+// just answers frame callbacks. Clients that ask for a callback before each
+// draw therefore draw at most once per refresh, and not at all while hidden.
+// With nothing to repaint or answer the compositor loop sleeps until a
+// client or the user changes something, or timed work is due.
+// How long repainting takes is summed up every few seconds.
+
+use crate::backend::{draw_frame, present_frame, Backend};
+use crate::render::Renderer;
+use crate::{ClientId, Message, ServerState, SharedState, SurfaceId};
+use std::io::Result;
+use std::time::{Duration, Instant};
+
+// For outputs that don't say
+const DEFAULT_REFRESH_MHZ: u32 = 60_000;
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+  // Frames presented
+  pub frames: u32,
+  // Frames that took longer than a refresh interval
+  pub missed: u32,
+  pub total: Duration,
+  pub worst: Duration
+}
+
+pub struct FrameScheduler {
+  interval: Duration,
+  // When the next frame is due
+  deadline: Instant,
+  // Start of frame callback timestamps
+  epoch: Instant,
+  pub stats: FrameStats,
+  last_report: Instant
+}
+
+impl FrameScheduler {
+  pub fn new(now: Instant) -> Self {
+    let mut scheduler = FrameScheduler {
+      interval: Duration::default(),
+      deadline: now,
+      epoch: now,
+      stats: FrameStats::default(),
+      last_report: now
+    };
+    scheduler.set_refresh_mhz(0);
+    return scheduler;
+  }
+
+  // Refresh rate of the output in millihertz, 0 if unknown
+  pub fn set_refresh_mhz(self: &mut Self, refresh_mhz: u32) {
+    let refresh_mhz = if refresh_mhz == 0 { DEFAULT_REFRESH_MHZ } else { refresh_mhz };
+    self.interval = Duration::from_nanos(1_000_000_000_000 / refresh_mhz as u64);
+  }
+
+  pub fn interval(self: &Self) -> Duration {
+    return self.interval;
+  }
+
+  pub fn deadline(self: &Self) -> Instant {
+    return self.deadline;
+  }
+
+  // Whether a frame is due at `now`. If so the deadline moves to the next
+  // refresh after `now`, skipping any that were missed.
+  pub fn frame_due(self: &mut Self, now: Instant) -> bool {
+    if now < self.deadline {
+      return false;
+    }
+    let behind = now.duration_since(self.deadline).as_nanos() / self.interval.as_nanos();
+    self.deadline += self.interval * (behind as u32 + 1);
+    return true;
+  }
+
+  pub fn timestamp_ms(self: &Self, time: Instant) -> u32 {
+    return time.saturating_duration_since(self.epoch).as_millis() as u32;
+  }
+
+  // A presented frame that took `took` to draw and present
+  pub fn record_frame(self: &mut Self, took: Duration) {
+    let stats = &mut self.stats;
+    stats.frames += 1;
+    stats.total += took;
+    stats.worst = stats.worst.max(took);
+    if took > self.interval {
+      stats.missed += 1;
+    }
+  }
+
+  // Stats since the last report, once they cover the report interval
+  pub fn report(self: &mut Self, now: Instant) -> Option<FrameStats> {
+    if now.saturating_duration_since(self.last_report) < REPORT_INTERVAL {
+      return None;
+    }
+    self.last_report = now;
+    return Some(std::mem::take(&mut self.stats));
+  }
+}
+
+impl ServerState {
+  pub fn request_frame(self: &mut Self, client: ClientId, surface: SurfaceId) {
+    if !self.frame_callbacks.contains(&(client, surface)) {
+      self.frame_callbacks.push((client, surface));
+    }
+  }
+
+  // Whether the next frame answers any callbacks
+  pub fn frame_callbacks_due(self: &Self) -> bool {
+    let shown = self.window_order();
+    return self.frame_callbacks.iter().any(|(client, _)| shown.contains(client));
+  }
+
+  // Answer callbacks of surfaces that are shown. The rest wait until they
+  // are, and those of destroyed surfaces are dropped.
+  pub fn frame_done(self: &mut Self, time_ms: u32) {
+    let shown = self.window_order();
+    let mut waiting = Vec::new();
+    for (client, surface) in std::mem::take(&mut self.frame_callbacks) {
+      match self.clients.get(&client).map(|c| c.surfaces.get(surface).is_ok()) {
+        Some(true) if shown.contains(&client) => {
+          self.send(client, Message::FrameDoneEvent { surface, time_ms });
+        }
+        Some(true) => waiting.push((client, surface)),
+        _ => {}
+      }
+    }
+    self.frame_callbacks = waiting;
+  }
+}
+
+// Run a frame if one is due: repaint and present if anything changed, then
+// answer frame callbacks. Returns whether a frame was presented.
+pub fn run_frame(
+  state: &SharedState, renderer: &mut Renderer, backend: &mut dyn Backend,
+  scheduler: &mut FrameScheduler
+) -> Result<bool> {
+  let now = backend.now();
+  let refresh_mhz = state.lock().unwrap().outputs.first().map_or(0, |o| o.refresh_mhz);
+  scheduler.set_refresh_mhz(refresh_mhz);
+  if !scheduler.frame_due(now) {
+    return Ok(false);
+  }
+  let started = Instant::now();
+  let drawn = draw_frame(state, renderer, backend);
+  if drawn {
+    present_frame(state, backend)?;
+    scheduler.record_frame(started.elapsed());
+  }
+  state.lock().unwrap().frame_done(scheduler.timestamp_ms(now));
+  if let Some(stats) = scheduler.report(now) {
+    if stats.frames > 0 {
+      println!(
+        "{} frames, {:.2} ms average, {:.2} ms worst, {} missed",
+        stats.frames,
+        stats.total.as_secs_f64() * 1000.0 / stats.frames as f64,
+        stats.worst.as_secs_f64() * 1000.0,
+        stats.missed
+      );
+    }
+  }
+  return Ok(drawn);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{HeadlessBackend, MAIN_SURFACE};
+  use std::sync::{mpsc, Arc, Mutex};
+
+  #[test]
+  fn test_frame_due() {
+    let start = Instant::now();
+    let mut scheduler = FrameScheduler::new(start);
+    scheduler.set_refresh_mhz(50_000);
+    assert_eq!(scheduler.interval(), Duration::from_millis(20));
+    assert!(scheduler.frame_due(start));
+    assert!(!scheduler.frame_due(start + Duration::from_millis(19)));
+    assert!(scheduler.frame_due(start + Duration::from_millis(20)));
+    // late frames skip the refreshes they missed
+    assert!(scheduler.frame_due(start + Duration::from_millis(75)));
+    assert_eq!(scheduler.deadline(), start + Duration::from_millis(80));
+
+    scheduler.record_frame(Duration::from_millis(5));
+    scheduler.record_frame(Duration::from_millis(30));
+    assert!(scheduler.report(start + Duration::from_secs(1)).is_none());
+    let stats = scheduler.report(start + Duration::from_secs(5)).unwrap();
+    assert_eq!((stats.frames, stats.missed), (2, 1));
+    assert_eq!(stats.worst, Duration::from_millis(30));
+    assert_eq!(scheduler.stats, FrameStats::default());
+  }
+
+  #[test]
+  fn test_frame_callbacks() {
+    let (w, h) = (64, 48);
+    let state = Arc::new(Mutex::new(ServerState::new(w, h, Vec::new())));
+    let mut backend = HeadlessBackend::new(w, h);
+    let mut renderer = Renderer::new();
+    let mut scheduler = FrameScheduler::new(backend.now());
+    let (outbox, events) = mpsc::channel();
+    let client = state.lock().unwrap().add_client(outbox);
+    let frames = |events: &mpsc::Receiver<Message>| {
+      return events.try_iter().filter(|m| matches!(m, Message::FrameDoneEvent { .. })).count();
+    };
+
+    // the first frame draws everything
+    assert!(run_frame(&state, &mut renderer, &mut backend, &mut scheduler).unwrap());
+    // a callback is answered at the next refresh, without repainting
+    state.lock().unwrap().request_frame(client, MAIN_SURFACE);
+    assert!(!run_frame(&state, &mut renderer, &mut backend, &mut scheduler).unwrap());
+    assert_eq!(frames(&events), 0);
+    backend.advance(scheduler.interval());
+    assert!(!run_frame(&state, &mut renderer, &mut backend, &mut scheduler).unwrap());
+    assert_eq!(frames(&events), 1);
+    assert_eq!(backend.presented, 1);
+
+    // hidden surfaces wait
+    state.lock().unwrap().set_minimized(client, true);
+    state.lock().unwrap().request_frame(client, MAIN_SURFACE);
+    backend.advance(scheduler.interval());
+    run_frame(&state, &mut renderer, &mut backend, &mut scheduler).unwrap();
+    assert_eq!(frames(&events), 0);
+    state.lock().unwrap().set_minimized(client, false);
+    backend.advance(scheduler.interval());
+    run_frame(&state, &mut renderer, &mut backend, &mut scheduler).unwrap();
+    assert_eq!(frames(&events), 1);
+  }
+
+  // Run the frame at the next refresh, then say whether another is needed
+  fn next_frame(
+    state: &SharedState, renderer: &mut Renderer, backend: &mut HeadlessBackend,
+    scheduler: &mut FrameScheduler
+  ) -> bool {
+    backend.advance(scheduler.interval());
+    run_frame(state, renderer, backend, scheduler).unwrap();
+    return renderer.needs_frame(&state.lock().unwrap());
+  }
+
+  #[test]
+  fn test_needs_frame() {
+    let (w, h) = (64, 48);
+    let state = Arc::new(Mutex::new(ServerState::new(w, h, Vec::new())));
+    let mut backend = HeadlessBackend::new(w, h);
+    let mut renderer = Renderer::new();
+    let mut scheduler = FrameScheduler::new(backend.now());
+    let (outbox, _events) = mpsc::channel();
+    let client = state.lock().unwrap().add_client(outbox);
+    assert!(renderer.needs_frame(&state.lock().unwrap()));
+    // nothing left to do, so the loop can sleep
+    assert!(!next_frame(&state, &mut renderer, &mut backend, &mut scheduler));
+    assert_eq!(state.lock().unwrap().next_tick(), None);
+
+    state.lock().unwrap().request_frame(client, MAIN_SURFACE);
+    assert!(renderer.needs_frame(&state.lock().unwrap()));
+    assert!(!next_frame(&state, &mut renderer, &mut backend, &mut scheduler));
+
+    // a committed upload
+    {
+      let mut server = state.lock().unwrap();
+      let surfaces = &mut server.clients.get_mut(&client).unwrap().surfaces;
+      surfaces.get_mut(MAIN_SURFACE).unwrap().patch(0, 0, 1, 1, &[0xff; 4]).unwrap();
+      surfaces.commit(MAIN_SURFACE).unwrap();
+      assert!(renderer.needs_frame(&server));
+    }
+    assert!(!next_frame(&state, &mut renderer, &mut backend, &mut scheduler));
+
+    // a notification is drawn, then wakes the loop when it expires
+    let now = backend.now();
+    let mut server = state.lock().unwrap();
+    let (summary, body) = (String::new(), String::new());
+    let urgency = crate::Urgency::Normal;
+    server.notifications.post(client, 1, summary, body, urgency, Some(500), Vec::new(), now);
+    assert!(renderer.needs_frame(&server));
+    assert_eq!(server.next_tick(), Some(now + Duration::from_millis(500)));
+  }
+}
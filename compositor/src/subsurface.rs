@@ -0,0 +1,330 @@
+// All surfaces belonging to one client, arranged as a tree under its main
+// surface. Subsurfaces sit at an offset from their parent and are stacked
+// relative to their siblings and the parent itself. A synchronized
+// subsurface caches its commits until the parent commits, so parent and
+// child content always appear together; a desynchronized one shows its
+// commits immediately.
+
+use crate::surface::{surface_bytes, Surface};
+use crate::{PixelFormat, SurfaceId, MAIN_SURFACE};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+struct Subsurface {
+  parent: SurfaceId,
+  x: i32,
+  y: i32,
+  pending_position: Option<(i32, i32)>,
+  sync: bool,
+  // Set by a commit while synchronized, whose state then waits on the parent
+  cached: bool
+}
+
+struct Node {
+  surface: Surface,
+  role: Option<Subsurface>,
+  // Children and this surface itself, bottom to top
+  stack: Vec<SurfaceId>,
+  pending_stack: Option<Vec<SurfaceId>>
+}
+
+pub struct SurfaceTree {
+  nodes: HashMap<SurfaceId, Node>
+}
+
+fn no_surface(id: SurfaceId) -> Error {
+  return Error::new(ErrorKind::InvalidInput, format!("No surface {}", id));
+}
+
+impl SurfaceTree {
+  pub fn new(width: usize, height: usize, format: PixelFormat) -> Self {
+    let mut nodes = HashMap::new();
+    nodes.insert(MAIN_SURFACE, Node {
+      surface: Surface::new(width, height, format),
+      role: None,
+      stack: vec![MAIN_SURFACE],
+      pending_stack: None
+    });
+    return SurfaceTree { nodes };
+  }
+
+  pub fn get(self: &Self, id: SurfaceId) -> std::io::Result<&Surface> {
+    return self.nodes.get(&id).map(|n| &n.surface).ok_or_else(|| no_surface(id));
+  }
+
+  pub fn get_mut(self: &mut Self, id: SurfaceId) -> std::io::Result<&mut Surface> {
+    return self.nodes.get_mut(&id).map(|n| &mut n.surface).ok_or_else(|| no_surface(id));
+  }
+
+  fn node_mut(self: &mut Self, id: SurfaceId) -> std::io::Result<&mut Node> {
+    return self.nodes.get_mut(&id).ok_or_else(|| no_surface(id));
+  }
+
+  fn subsurface_mut(self: &mut Self, id: SurfaceId) -> std::io::Result<&mut Subsurface> {
+    return self
+      .node_mut(id)?
+      .role
+      .as_mut()
+      .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Surface is not a subsurface"));
+  }
+
+  // New subsurfaces start synchronized at the parent's origin, on top of
+  // their siblings
+  pub fn create_subsurface(
+    self: &mut Self, id: SurfaceId, parent: SurfaceId, width: usize, height: usize,
+    format: PixelFormat
+  ) -> std::io::Result<()> {
+    if self.nodes.contains_key(&id) {
+      return Err(Error::new(ErrorKind::AlreadyExists, "Surface id already in use"));
+    }
+    surface_bytes(width, height)?;
+    let parent_node = self.node_mut(parent)?;
+    let mut stack = parent_node.pending_stack.take().unwrap_or_else(|| parent_node.stack.clone());
+    stack.push(id);
+    parent_node.pending_stack = Some(stack);
+    self.nodes.insert(id, Node {
+      surface: Surface::new(width, height, format),
+      role: Some(Subsurface {
+        parent,
+        x: 0,
+        y: 0,
+        pending_position: None,
+        sync: true,
+        cached: false
+      }),
+      stack: vec![id],
+      pending_stack: None
+    });
+    return Ok(());
+  }
+
+  // Takes effect on the next parent commit
+  pub fn set_position(self: &mut Self, id: SurfaceId, x: i32, y: i32) -> std::io::Result<()> {
+    self.subsurface_mut(id)?.pending_position = Some((x, y));
+    return Ok(());
+  }
+
+  pub fn set_sync(self: &mut Self, id: SurfaceId, sync: bool) -> std::io::Result<()> {
+    self.subsurface_mut(id)?.sync = sync;
+    // Leaving sync mode shows whatever was waiting on the parent
+    if !sync && !self.is_synchronized(id) {
+      self.apply_cached(id)?;
+    }
+    return Ok(());
+  }
+
+  // Restack `id` directly above (or below) `sibling`, which must share its
+  // parent or be the parent. Takes effect on the next parent commit.
+  fn restack(
+    self: &mut Self, id: SurfaceId, sibling: SurfaceId, above: bool
+  ) -> std::io::Result<()> {
+    let parent = self.subsurface_mut(id)?.parent;
+    let parent_node = self.node_mut(parent)?;
+    let mut stack = parent_node.pending_stack.take().unwrap_or_else(|| parent_node.stack.clone());
+    if id == sibling || !stack.contains(&sibling) {
+      parent_node.pending_stack = Some(stack);
+      return Err(Error::new(ErrorKind::InvalidInput, "Not a sibling surface"));
+    }
+    stack.retain(|s| *s != id);
+    let ind = stack.iter().position(|s| *s == sibling).unwrap();
+    stack.insert(if above { ind + 1 } else { ind }, id);
+    parent_node.pending_stack = Some(stack);
+    return Ok(());
+  }
+
+  pub fn place_above(self: &mut Self, id: SurfaceId, sibling: SurfaceId) -> std::io::Result<()> {
+    return self.restack(id, sibling, true);
+  }
+
+  pub fn place_below(self: &mut Self, id: SurfaceId, sibling: SurfaceId) -> std::io::Result<()> {
+    return self.restack(id, sibling, false);
+  }
+
+  // Synchronized if this subsurface or any ancestor subsurface is in sync
+  // mode
+  fn is_synchronized(self: &Self, id: SurfaceId) -> bool {
+    let mut cur = id;
+    while let Some(role) = self.nodes.get(&cur).and_then(|n| n.role.as_ref()) {
+      if role.sync {
+        return true;
+      }
+      cur = role.parent;
+    }
+    return false;
+  }
+
+  pub fn commit(self: &mut Self, id: SurfaceId) -> std::io::Result<()> {
+    let synchronized = self.is_synchronized(id);
+    let node = self.node_mut(id)?;
+    if synchronized {
+      node.role.as_mut().unwrap().cached = true;
+      node.surface.cache_pending();
+      return Ok(());
+    }
+    if let Some(pending) = node.surface.take_pending() {
+      node.surface.attach(pending);
+    }
+    self.apply_children(id)?;
+    return Ok(());
+  }
+
+  // Apply state the children of `id` were holding for its commit: their
+  // position and stacking, and the cached contents of synchronized ones
+  fn apply_children(self: &mut Self, id: SurfaceId) -> std::io::Result<()> {
+    let node = self.node_mut(id)?;
+    if let Some(stack) = node.pending_stack.take() {
+      node.stack = stack;
+    }
+    let children: Vec<SurfaceId> = node.stack.iter().cloned().filter(|c| *c != id).collect();
+    for child in children {
+      let role = self.subsurface_mut(child)?;
+      if let Some((x, y)) = role.pending_position.take() {
+        role.x = x;
+        role.y = y;
+      }
+      if self.is_synchronized(child) {
+        self.apply_cached(child)?;
+      }
+    }
+    return Ok(());
+  }
+
+  fn apply_cached(self: &mut Self, id: SurfaceId) -> std::io::Result<()> {
+    let node = self.node_mut(id)?;
+    let role = node.role.as_mut().unwrap();
+    if !role.cached {
+      return Ok(());
+    }
+    role.cached = false;
+    if let Some(pending) = node.surface.take_cached() {
+      node.surface.attach(pending);
+    }
+    return self.apply_children(id);
+  }
+
+  // Destroys the surface together with all of its descendants
+  pub fn destroy(self: &mut Self, id: SurfaceId) -> std::io::Result<()> {
+    let parent = self.subsurface_mut(id)?.parent;
+    let parent_node = self.node_mut(parent)?;
+    parent_node.stack.retain(|s| *s != id);
+    if let Some(stack) = parent_node.pending_stack.as_mut() {
+      stack.retain(|s| *s != id);
+    }
+    let mut doomed = vec![id];
+    while let Some(cur) = doomed.pop() {
+      if let Some(node) = self.nodes.remove(&cur) {
+        doomed.extend(node.stack.iter().filter(|s| **s != cur));
+        doomed.extend(node.pending_stack.iter().flatten().filter(|s| **s != cur));
+      }
+    }
+    return Ok(());
+  }
+
+  // Every surface with its offset from the main surface, bottom to top
+  pub fn render_order(self: &Self) -> Vec<(SurfaceId, i32, i32)> {
+    let mut out = Vec::new();
+    self.push_render_order(MAIN_SURFACE, 0, 0, &mut out);
+    return out;
+  }
+
+  fn push_render_order(
+    self: &Self, id: SurfaceId, x: i32, y: i32, out: &mut Vec<(SurfaceId, i32, i32)>
+  ) {
+    let node = &self.nodes[&id];
+    for s in &node.stack {
+      if *s == id {
+        out.push((id, x, y));
+        continue;
+      }
+      let role = self.nodes[s].role.as_ref().unwrap();
+      self.push_render_order(*s, x + role.x, y + role.y, out);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Rect;
+
+  fn upload(tree: &mut SurfaceTree, id: SurfaceId, value: u8) {
+    let surface = tree.get_mut(id).unwrap();
+    let pixels = vec![value; 4 * surface.width * surface.height];
+    let (w, h) = (surface.width, surface.height);
+    surface.patch(0, 0, w, h, &pixels).unwrap();
+  }
+
+  #[test]
+  fn test_stacking() {
+    let mut tree = SurfaceTree::new(4, 4, PixelFormat::Rgba8888);
+    tree.create_subsurface(1, MAIN_SURFACE, 2, 2, PixelFormat::Rgba8888).unwrap();
+    tree.create_subsurface(2, MAIN_SURFACE, 2, 2, PixelFormat::Rgba8888).unwrap();
+    tree.create_subsurface(3, 1, 1, 1, PixelFormat::Rgba8888).unwrap();
+    tree.set_position(1, 1, 2).unwrap();
+    tree.set_position(3, 1, 0).unwrap();
+    tree.place_below(2, MAIN_SURFACE).unwrap();
+    // nothing changes until the parents commit
+    assert_eq!(tree.render_order(), vec![(MAIN_SURFACE, 0, 0)]);
+    tree.commit(MAIN_SURFACE).unwrap();
+    assert_eq!(tree.render_order(), vec![(2, 0, 0), (MAIN_SURFACE, 0, 0), (1, 1, 2)]);
+    tree.commit(1).unwrap();
+    tree.commit(MAIN_SURFACE).unwrap();
+    assert_eq!(tree.render_order(), vec![
+      (2, 0, 0),
+      (MAIN_SURFACE, 0, 0),
+      (1, 1, 2),
+      (3, 2, 2)
+    ]);
+    assert!(tree.place_above(3, 2).is_err());
+    tree.destroy(1).unwrap();
+    assert!(tree.get(3).is_err());
+    assert_eq!(tree.render_order(), vec![(2, 0, 0), (MAIN_SURFACE, 0, 0)]);
+  }
+
+  #[test]
+  fn test_sync_modes() {
+    let mut tree = SurfaceTree::new(4, 4, PixelFormat::Xrgb8888);
+    tree.create_subsurface(1, MAIN_SURFACE, 2, 2, PixelFormat::Xrgb8888).unwrap();
+    tree.commit(MAIN_SURFACE).unwrap();
+
+    // synchronized commits wait on the parent
+    upload(&mut tree, 1, 0xff);
+    tree.commit(1).unwrap();
+    assert_eq!(tree.get(1).unwrap().data[0], 0);
+    tree.commit(MAIN_SURFACE).unwrap();
+    assert_eq!(tree.get(1).unwrap().data[0], 0xff);
+
+    // desynchronized commits show immediately
+    tree.set_sync(1, false).unwrap();
+    upload(&mut tree, 1, 0x80);
+    tree.commit(1).unwrap();
+    assert_eq!(tree.get(1).unwrap().data[0], 0x80);
+
+    // switching to desync flushes a cached commit
+    tree.set_sync(1, true).unwrap();
+    upload(&mut tree, 1, 0x40);
+    tree.commit(1).unwrap();
+    assert_eq!(tree.get(1).unwrap().data[0], 0x80);
+    tree.set_sync(1, false).unwrap();
+    assert_eq!(tree.get(1).unwrap().data[0], 0x40);
+  }
+
+  #[test]
+  fn test_partial_sync_commits() {
+    let mut tree = SurfaceTree::new(4, 4, PixelFormat::Rgba8888);
+    tree.create_subsurface(1, MAIN_SURFACE, 2, 1, PixelFormat::Rgba8888).unwrap();
+    tree.commit(MAIN_SURFACE).unwrap();
+    tree.get_mut(1).unwrap().take_damage();
+
+    // two commits of one pixel each, before the parent commits once
+    tree.get_mut(1).unwrap().patch(0, 0, 1, 1, &[0xff; 4]).unwrap();
+    tree.commit(1).unwrap();
+    tree.get_mut(1).unwrap().patch(1, 0, 1, 1, &[0x80, 0x80, 0x80, 0xff]).unwrap();
+    tree.commit(1).unwrap();
+    assert_eq!(tree.get(1).unwrap().data, vec![0; 8]);
+    tree.commit(MAIN_SURFACE).unwrap();
+    let surface = tree.get_mut(1).unwrap();
+    assert_eq!(surface.data, [0xff, 0xff, 0xff, 0xff, 0x80, 0x80, 0x80, 0xff]);
+    assert_eq!(surface.take_damage(), Some(Rect::new(0, 0, 2, 1)));
+  }
+}
@@ -0,0 +1,241 @@
+// Server-side copy of a client surface, kept up to date from damage uploads
+// when the client cannot share its buffer with us directly. Uploads land in a
+// pending buffer which only becomes visible once the client commits.
+
+use crate::scale::{resample, scaled_size};
+use libprotocol::{PixelFormat, Rect, INTERNAL_BYTES_PER_PIXEL};
+use std::io::{Error, ErrorKind};
+
+// Largest width or height clients may give a surface, in buffer pixels
+pub const MAX_SURFACE_SIZE: usize = 8192;
+// Largest buffer scale clients may render at
+pub const MAX_BUFFER_SCALE: u32 = 8;
+
+// Contents uploaded since the last commit, with the bounding box of the
+// uploaded rects in buffer pixels
+pub struct Pending {
+  pub data: Vec<u8>,
+  pub damage: Rect
+}
+
+pub struct Surface {
+  pub width: usize,
+  pub height: usize,
+  // Format the client uploads in
+  pub format: PixelFormat,
+  // Buffer pixels per logical pixel the client renders at
+  pub scale: u32,
+  // Premultiplied RGBA, converted from `format` on upload
+  pub data: Vec<u8>,
+  pending: Option<Pending>,
+  // Contents committed while the surface was synchronized to its parent,
+  // waiting on the parent's commit
+  cached: Option<Pending>,
+  // Committed changes not yet composited, in buffer pixels
+  damage: Option<Rect>,
+  // `data` resampled to the output size it was last composited at, until the
+  // contents or size change
+  scaled: Option<(usize, usize, Vec<u8>)>
+}
+
+impl Surface {
+  pub fn new(width: usize, height: usize, format: PixelFormat) -> Self {
+    Surface {
+      width,
+      height,
+      format,
+      scale: 1,
+      data: vec![0; INTERNAL_BYTES_PER_PIXEL * width * height],
+      pending: None,
+      cached: None,
+      damage: Some(Rect::new(0, 0, width as i32, height as i32)),
+      scaled: None
+    }
+  }
+
+  // Bytes of surface format pixels an upload of the rect at (x, y) of size
+  // (dx, dy) takes, if the rect is inside the surface. The rect comes from the
+  // client, so it may not even fit in a usize.
+  pub fn upload_size(
+    self: &Self, x: usize, y: usize, dx: usize, dy: usize
+  ) -> std::io::Result<usize> {
+    let right = x.checked_add(dx).filter(|r| *r <= self.width);
+    let bottom = y.checked_add(dy).filter(|b| *b <= self.height);
+    if right.is_none() || bottom.is_none() {
+      return Err(Error::new(ErrorKind::InvalidInput, "Damage rect out of surface bounds"));
+    }
+    let size = self.format.bytes_per_pixel().checked_mul(dx).and_then(|s| s.checked_mul(dy));
+    return size.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Damage rect too large"));
+  }
+
+  // Overwrite the rect at (x, y) of size (dx, dy) with tightly packed rows of
+  // pixels in the surface format
+  pub fn patch(
+    self: &mut Self, x: usize, y: usize, dx: usize, dy: usize, pixels: &[u8]
+  ) -> std::io::Result<()> {
+    if pixels.len() != self.upload_size(x, y, dx, dy)? {
+      return Err(Error::new(ErrorKind::InvalidData, "Damage data does not match rect size"));
+    }
+    let converted = self.format.to_internal(pixels);
+    let row_len = INTERNAL_BYTES_PER_PIXEL * dx;
+    let width = self.width;
+    if self.pending.is_none() {
+      // Uploads build on what the last commit left, even if it isn't visible
+      self.pending = Some(match &self.cached {
+        Some(cached) => Pending { data: cached.data.clone(), damage: cached.damage },
+        None => Pending { data: self.data.clone(), damage: Rect::new(0, 0, 0, 0) }
+      });
+    }
+    let pending = self.pending.as_mut().unwrap();
+    for (i, row) in converted.chunks_exact(row_len).enumerate() {
+      let ind = INTERNAL_BYTES_PER_PIXEL * ((y + i) * width + x);
+      pending.data[ind..ind + row_len].copy_from_slice(row);
+    }
+    pending.damage = pending.damage.union(&Rect::new(x as i32, y as i32, dx as i32, dy as i32));
+    return Ok(());
+  }
+
+  // Reallocate at a new size, e.g. after the compositor resizes the window.
+  // The client has to upload fresh contents.
+  pub fn resize(self: &mut Self, width: usize, height: usize) {
+    self.width = width;
+    self.height = height;
+    self.data = vec![0; INTERNAL_BYTES_PER_PIXEL * width * height];
+    self.pending = None;
+    self.cached = None;
+    self.damage = Some(Rect::new(0, 0, width as i32, height as i32));
+    self.scaled = None;
+  }
+
+  // Size in pixels on an output of the given scale
+  pub fn output_size(self: &Self, output_scale: f64) -> (usize, usize) {
+    return scaled_size(self.width, self.height, self.scale, output_scale);
+  }
+
+  // Committed contents as shown on an output of the given scale, with their
+  // width. Contents shown at buffer size are used as they are; resampled ones
+  // are kept for the next call.
+  pub fn scaled(self: &mut Self, output_scale: f64) -> (usize, &[u8]) {
+    let (width, height) = self.output_size(output_scale);
+    if (width, height) == (self.width, self.height) {
+      return (width, &self.data);
+    }
+    if !matches!(&self.scaled, Some((w, h, _)) if (*w, *h) == (width, height)) {
+      let data = resample(&self.data, self.width, self.height, width, height);
+      self.scaled = Some((width, height, data));
+    }
+    return (width, &self.scaled.as_ref().unwrap().2);
+  }
+
+  // Contents uploaded since the last commit, if any
+  pub fn take_pending(self: &mut Self) -> Option<Pending> {
+    return self.pending.take();
+  }
+
+  // Hold on to uploads for a later commit of the parent. Since they were
+  // made on top of contents cached before, they replace those.
+  pub fn cache_pending(self: &mut Self) {
+    if let Some(pending) = self.pending.take() {
+      self.cached = Some(pending);
+    }
+  }
+
+  pub fn take_cached(self: &mut Self) -> Option<Pending> {
+    return self.cached.take();
+  }
+
+  // Make contents taken from `take_pending` or `take_cached` visible
+  pub fn attach(self: &mut Self, pending: Pending) {
+    self.data = pending.data;
+    self.scaled = None;
+    self.damage = Some(match self.damage {
+      Some(d) => d.union(&pending.damage),
+      None => pending.damage
+    });
+  }
+
+  pub fn has_damage(self: &Self) -> bool {
+    return self.damage.is_some();
+  }
+
+  // What changed since the last call, in buffer pixels
+  pub fn take_damage(self: &mut Self) -> Option<Rect> {
+    return self.damage.take();
+  }
+}
+
+// Bytes of internal pixels a surface of the given size needs. The size comes
+// from the client, so it is rejected unless it is one clients may ask for.
+pub fn surface_bytes(width: usize, height: usize) -> std::io::Result<usize> {
+  if width == 0 || height == 0 || width > MAX_SURFACE_SIZE || height > MAX_SURFACE_SIZE {
+    return Err(Error::new(ErrorKind::InvalidInput, "Surface size out of range"));
+  }
+  let bytes = INTERNAL_BYTES_PER_PIXEL.checked_mul(width).and_then(|b| b.checked_mul(height));
+  return bytes.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Surface too large"));
+}
+
+// Pull the rect at (x, y) of size (dx, dy) out of a frame of the given width
+// as tightly packed rows
+pub fn copy_rect(
+  frame: &[u8], width: usize, bpp: usize, x: usize, y: usize, dx: usize, dy: usize
+) -> Vec<u8> {
+  let row_len = bpp * dx;
+  let mut out = Vec::with_capacity(row_len * dy);
+  for i in y..(y + dy) {
+    let ind = bpp * (i * width + x);
+    out.extend_from_slice(&frame[ind..ind + row_len]);
+  }
+  return out;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_patch() {
+    let (w, h) = (8, 6);
+    let frame: Vec<u8> = (0..(4 * w * h)).map(|i| i as u8).collect();
+    let mut surface = Surface::new(w, h, PixelFormat::Xrgb8888);
+    let rect = copy_rect(&frame, w, 4, 2, 1, 3, 4);
+    assert_eq!(rect.len(), 4 * 3 * 4);
+    surface.patch(2, 1, 3, 4, &rect).unwrap();
+    // nothing visible before commit
+    assert_eq!(surface.data, vec![0; 4 * w * h]);
+    surface.take_damage();
+    let pending = surface.take_pending().unwrap();
+    surface.attach(pending);
+    assert!(surface.take_pending().is_none());
+    assert_eq!(surface.take_damage(), Some(Rect::new(2, 1, 3, 4)));
+    assert_eq!(surface.take_damage(), None);
+    assert_eq!(
+      copy_rect(&surface.data, w, 4, 2, 1, 3, 4),
+      PixelFormat::Xrgb8888.to_internal(&rect)
+    );
+    // outside the damage stays untouched
+    assert_eq!(copy_rect(&surface.data, w, 4, 0, 0, w, 1), vec![0; 4 * w]);
+    assert!(surface.patch(6, 0, 3, 1, &rect[..12]).is_err());
+    assert!(surface.patch(0, 0, 3, 1, &rect).is_err());
+    // sizes that overflow are rejected rather than wrapping
+    assert!(surface.patch(usize::MAX, 0, 1, 1, &rect[..4]).is_err());
+    assert!(surface.patch(1, 1, usize::MAX, usize::MAX, &rect).is_err());
+  }
+
+  #[test]
+  fn test_scaled() {
+    let mut surface = Surface::new(4, 2, PixelFormat::Xrgb8888);
+    // shown at buffer size, the contents aren't copied
+    let (width, data) = surface.scaled(1.0);
+    assert_eq!((width, data.as_ptr()), (4, surface.data.as_ptr()));
+    let (width, data) = surface.scaled(2.0);
+    let resampled = data.as_ptr();
+    assert_eq!(width, 8);
+    assert_eq!(surface.scaled(2.0).1.as_ptr(), resampled);
+    // until the next commit
+    surface.patch(0, 0, 1, 1, &[0xff; 4]).unwrap();
+    let pending = surface.take_pending().unwrap();
+    surface.attach(pending);
+    assert!(surface.scaled.is_none());
+    assert_eq!(surface.scaled(2.0).1[..4], [0xff; 4]);
+  }
+}
@@ -0,0 +1,163 @@
+// CPU-side framebuffers that frames are drawn into in turn. A buffer keeps
+// the frame it was last drawn with, so its age (how many frames ago that
+// was) and the damage of the frames since tell which parts of it are stale.
+// Before the next frame is drawn into it only those parts are copied over
+// from the newest frame, leaving the renderer to repaint what changed in
+// this one. A single presenter then shows the front buffer.
+
+use crate::Rect;
+use std::collections::VecDeque;
+
+pub const SWAPCHAIN_LENGTH: usize = 2;
+
+pub struct Swapchain {
+  pub width: usize,
+  pub height: usize,
+  buffers: Vec<Vec<u8>>,
+  // Frame each buffer was last drawn with, None if it never was
+  drawn: Vec<Option<u64>>,
+  // Frames drawn so far
+  frame: u64,
+  back: usize,
+  // Where each recent frame differs from the one before, newest last
+  history: VecDeque<Vec<Rect>>,
+  // Whether the back buffer was brought up to date since the last swap
+  synced: bool
+}
+
+impl Swapchain {
+  pub fn new(width: usize, height: usize, count: usize) -> Self {
+    assert!(count >= 2, "a swapchain needs at least two buffers");
+    Swapchain {
+      width,
+      height,
+      buffers: vec![vec![0; 4 * width * height]; count],
+      drawn: vec![None; count],
+      frame: 0,
+      back: 0,
+      history: VecDeque::new(),
+      synced: false
+    }
+  }
+
+  fn front(self: &Self) -> usize {
+    return (self.back + self.buffers.len() - 1) % self.buffers.len();
+  }
+
+  // Frames since the back buffer was drawn, counting the front one, or 0 if
+  // it never was
+  pub fn age(self: &Self) -> u64 {
+    return self.drawn[self.back].map_or(0, |d| self.frame - d + 1);
+  }
+
+  // Where the back buffer differs from the front one, None if it may differ
+  // anywhere
+  pub fn stale(self: &Self) -> Option<Vec<Rect>> {
+    let age = self.age() as usize;
+    if age == 0 || age - 1 > self.history.len() {
+      return None;
+    }
+    return Some(self.history.iter().rev().take(age - 1).flatten().cloned().collect());
+  }
+
+  // The buffer to draw the next frame into, up to date with the front one
+  pub fn back_buffer(self: &mut Self) -> &mut [u8] {
+    let (front, back) = (self.front(), self.back);
+    if !self.synced {
+      self.synced = true;
+      let stale = self.stale();
+      let (src, dst) = if front < back {
+        let (head, tail) = self.buffers.split_at_mut(back);
+        (&head[front], &mut tail[0])
+      }
+      else {
+        let (head, tail) = self.buffers.split_at_mut(front);
+        (&tail[0], &mut head[back])
+      };
+      match stale {
+        Some(stale) => copy_rects(dst, src, self.width, self.height, &stale),
+        None => dst.copy_from_slice(src)
+      }
+    }
+    return &mut self.buffers[back];
+  }
+
+  // The frame last drawn
+  pub fn front_buffer(self: &Self) -> &[u8] {
+    return &self.buffers[self.front()];
+  }
+
+  // Where the front buffer differs from the frame before it
+  pub fn damage(self: &Self) -> &[Rect] {
+    return self.history.back().map_or(&[], |d| d);
+  }
+
+  // The back buffer was drawn, differing from the front one in `damage`, and
+  // becomes the front one. Every swapped frame should be presented.
+  pub fn swap(self: &mut Self, damage: Vec<Rect>) {
+    self.frame += 1;
+    self.drawn[self.back] = Some(self.frame);
+    self.history.push_back(damage);
+    // The oldest buffer is as many frames old as there are buffers
+    if self.history.len() >= self.buffers.len() {
+      self.history.pop_front();
+    }
+    self.back = (self.back + 1) % self.buffers.len();
+    self.synced = false;
+  }
+}
+
+// Copy `rects` of one RGBA frame of `width` by `height` into another
+pub fn copy_rects(dst: &mut [u8], src: &[u8], width: usize, height: usize, rects: &[Rect]) {
+  let frame = Rect::new(0, 0, width as i32, height as i32);
+  for r in rects.iter().filter_map(|r| r.intersect(&frame)) {
+    for y in r.y..r.y + r.height {
+      let start = 4 * (y as usize * width + r.x as usize);
+      let end = start + 4 * r.width as usize;
+      dst[start..end].copy_from_slice(&src[start..end]);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_age() {
+    let full = Rect::new(0, 0, 8, 8);
+    let (a, b) = (Rect::new(0, 0, 2, 2), Rect::new(4, 4, 2, 2));
+    let mut swapchain = Swapchain::new(8, 8, 3);
+    assert_eq!((swapchain.age(), swapchain.stale()), (0, None));
+    swapchain.swap(vec![full]);
+    swapchain.swap(vec![a]);
+    assert_eq!((swapchain.age(), swapchain.stale()), (0, None));
+    // the first buffer missed the two frames since
+    swapchain.swap(vec![b]);
+    assert_eq!(swapchain.age(), 3);
+    assert_eq!(swapchain.stale(), Some(vec![b, a]));
+    assert_eq!(swapchain.damage(), &[b]);
+    swapchain.swap(vec![]);
+    assert_eq!(swapchain.stale(), Some(vec![b]));
+  }
+
+  #[test]
+  fn test_copy_stale() {
+    let mut swapchain = Swapchain::new(4, 4, 2);
+    swapchain.back_buffer().fill(1);
+    swapchain.swap(vec![Rect::new(0, 0, 4, 4)]);
+    // never drawn, so it gets the whole frame
+    let frame = swapchain.back_buffer();
+    assert!(frame.iter().all(|b| *b == 1));
+    frame[..4].fill(2);
+    swapchain.swap(vec![Rect::new(0, 0, 1, 1)]);
+
+    // only the pixel that changed is copied
+    swapchain.buffers[0][4] = 9;
+    let frame = swapchain.back_buffer();
+    assert_eq!(frame[..8], [2, 2, 2, 2, 9, 1, 1, 1]);
+    frame[4] = 1;
+    let front = swapchain.front_buffer().to_vec();
+    assert_eq!(swapchain.back_buffer(), &front[..]);
+  }
+}
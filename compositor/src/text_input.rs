@@ -0,0 +1,188 @@
+// Text input through an input method. A client enables text input while it
+// has a text field focused and reports where its text cursor is; a separate
+// input method client (e.g. for CJK entry) is activated while the focused
+// client has text input enabled. The input method gets the keyboard while
+// active, sends back preedit and commit strings, and forwards keys it
+// doesn't consume.
+
+use crate::geometry::Rect;
+use crate::{ClientId, Message, ServerState, MAIN_SURFACE};
+
+pub struct TextInput {
+  pub enabled: bool,
+  // Text cursor in main surface buffer pixels
+  pub cursor: Rect
+}
+
+impl TextInput {
+  pub fn new() -> Self {
+    TextInput { enabled: false, cursor: Rect::new(0, 0, 0, 0) }
+  }
+}
+
+impl Default for TextInput {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ServerState {
+  // Client whose text input the input method currently serves
+  pub fn text_input_target(self: &Self) -> Option<ClientId> {
+    self.input_method?;
+    let focus = self.keyboard_focus?;
+    return match self.clients.get(&focus) {
+      Some(c) if c.text_input.enabled && Some(focus) != self.input_method => Some(focus),
+      _ => None
+    };
+  }
+
+  // Text cursor of the target in output coordinates
+  fn text_input_cursor(self: &Self, client: ClientId) -> Option<Rect> {
+    let c = self.clients.get(&client)?;
+    let main = c.surfaces.get(MAIN_SURFACE).ok()?;
+    let factor = self.output_scale() / main.scale as f64;
+    let r = c.text_input.cursor;
+    let scaled = |v: i32| (v as f64 * factor).round() as i32;
+    return Some(Rect::new(
+      c.window.x.saturating_add(scaled(r.x)),
+      c.window.y.saturating_add(scaled(r.y)),
+      scaled(r.width),
+      scaled(r.height)
+    ));
+  }
+
+  // Activate or deactivate the input method after anything that may change
+  // the target
+  pub fn update_text_input(self: &mut Self) {
+    let target = self.text_input_target();
+    let ime = match self.input_method {
+      Some(ime) => ime,
+      None => {
+        self.text_input_active = None;
+        return;
+      }
+    };
+    if target != self.text_input_active {
+      if self.text_input_active.is_some() {
+        self.send(ime, Message::InputMethodDeactivateEvent);
+      }
+      if let Some(cursor) = target.and_then(|t| self.text_input_cursor(t)) {
+        self.send(ime, Message::InputMethodActivateEvent { cursor });
+      }
+      self.text_input_active = target;
+    }
+  }
+
+  // The cursor comes from the client, so it is clamped to the main surface
+  pub fn set_text_input_cursor(self: &mut Self, client: ClientId, cursor: Rect) {
+    if let Some(c) = self.clients.get_mut(&client) {
+      let main = c.surfaces.get(MAIN_SURFACE).unwrap();
+      let (width, height) = (main.width as i32, main.height as i32);
+      let (x, y) = (cursor.x.clamp(0, width), cursor.y.clamp(0, height));
+      let size = (cursor.width.clamp(0, width - x), cursor.height.clamp(0, height - y));
+      c.text_input.cursor = Rect::new(x, y, size.0, size.1);
+    }
+    if let (Some(ime), Some(cursor)) = (self.input_method, self.text_input_cursor(client)) {
+      if self.text_input_active == Some(client) {
+        self.send(ime, Message::InputMethodCursorEvent { cursor });
+      }
+    }
+  }
+
+  // Only one input method at a time; returns whether `client` got the role
+  pub fn register_input_method(self: &mut Self, client: ClientId) -> bool {
+    if self.input_method.map(|ime| ime != client).unwrap_or(false) {
+      return false;
+    }
+    self.input_method = Some(client);
+    self.update_text_input();
+    return true;
+  }
+
+  // Route a message from the input method to the text input it serves
+  pub fn route_from_input_method(self: &Self, client: ClientId, msg: Message) {
+    if self.input_method != Some(client) {
+      return;
+    }
+    if let Some(target) = self.text_input_active {
+      self.send(target, msg);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{ClientRequests, Permission};
+  use libprotocol::dispatch_to_server;
+  use std::sync::mpsc;
+
+  #[test]
+  fn test_routing() {
+    let mut state = ServerState::new(800, 600, Vec::new());
+    let (app_outbox, app_events) = mpsc::channel();
+    let (ime_outbox, ime_events) = mpsc::channel();
+    let app = state.add_client(app_outbox);
+    let ime = state.add_client(ime_outbox);
+    assert!(state.register_input_method(ime));
+    assert!(!state.register_input_method(app));
+
+    state.set_keyboard_focus(Some(app));
+    state.clients.get_mut(&app).unwrap().text_input.enabled = true;
+    state.update_text_input();
+    assert_eq!(state.text_input_target(), Some(app));
+    let activated = ime_events.try_iter().find_map(|m| match m {
+      Message::InputMethodActivateEvent { cursor } => Some(cursor),
+      _ => None
+    });
+    let window = &state.clients[&app].window;
+    assert_eq!(activated, Some(Rect::new(window.x, window.y, 0, 0)));
+
+    state.route_from_input_method(ime, Message::CommitStringEvent { text: "日本".to_string() });
+    // only the input method may send text
+    state.route_from_input_method(app, Message::CommitStringEvent { text: "x".to_string() });
+    let commits: Vec<String> = app_events
+      .try_iter()
+      .filter_map(|m| match m {
+        Message::CommitStringEvent { text } => Some(text),
+        _ => None
+      })
+      .collect();
+    assert_eq!(commits, vec!["日本".to_string()]);
+
+    // a cursor far outside the surface is moved to its edge
+    state.set_text_input_cursor(app, Rect::new(i32::MAX, i32::MIN, i32::MAX, i32::MAX));
+    let moved = ime_events.try_iter().find_map(|m| match m {
+      Message::InputMethodCursorEvent { cursor } => Some(cursor),
+      _ => None
+    });
+    let window = &state.clients[&app].window;
+    let main = state.clients[&app].surfaces.get(MAIN_SURFACE).unwrap();
+    let edge = Rect::new(window.x + main.width as i32, window.y, 0, main.height as i32);
+    assert_eq!(moved, Some(edge));
+
+    state.set_keyboard_focus(None);
+    assert!(ime_events.try_iter().any(|m| matches!(m, Message::InputMethodDeactivateEvent)));
+  }
+
+  #[test]
+  fn test_input_method_permission() {
+    let mut state = ServerState::new(800, 600, Vec::new());
+    let (outbox, events) = mpsc::channel();
+    let ime = state.add_client(outbox);
+    let register = |state: &mut ServerState| {
+      let requests = &mut ClientRequests { state, id: ime };
+      dispatch_to_server(requests, Message::RegisterInputMethod).unwrap();
+      return events.try_iter().find_map(|m| match m {
+        Message::InputMethodRegisteredEvent { granted } => Some(granted),
+        _ => None
+      });
+    };
+    assert_eq!(register(&mut state), Some(false));
+    assert_eq!(state.input_method, None);
+    state.clients.get_mut(&ime).unwrap().permissions.push(Permission::InputMethod);
+    assert_eq!(register(&mut state), Some(true));
+    assert_eq!(state.input_method, Some(ime));
+  }
+}
@@ -0,0 +1,234 @@
+// Tiling layouts. Outside the stacking layout, shown windows that aren't
+// floating or maximized share the output between them: one large master
+// tile beside a column of the rest, or an even grid. Tiles follow their own
+// order, which raising a window doesn't change, and are kept up to date as
+// windows come and go. Floating windows stay wherever they are moved, above
+// the tiles.
+
+use crate::decoration::DecorationLayout;
+use crate::{ClientId, DecorationMode, Rect, ServerState};
+
+const MIN_MASTER_RATIO: f64 = 0.1;
+const MAX_MASTER_RATIO: f64 = 0.9;
+const MASTER_RATIO_STEP: f64 = 0.05;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+  Stacking,
+  // The first tile takes `master_ratio` of the width, the rest stack beside it
+  MasterStack,
+  Grid
+}
+
+impl Layout {
+  pub fn next(self: Self) -> Self {
+    match self {
+      Layout::Stacking => Layout::MasterStack,
+      Layout::MasterStack => Layout::Grid,
+      Layout::Grid => Layout::Stacking
+    }
+  }
+}
+
+// `count` spans splitting `len` starting at `start` as evenly as pixels allow
+fn split(start: i32, len: i32, count: i32) -> Vec<(i32, i32)> {
+  let edge = |i: i32| start + len * i / count;
+  return (0..count).map(|i| (edge(i), edge(i + 1) - edge(i))).collect();
+}
+
+// Frames for `count` tiles covering `area`, in tile order. Stacking has none.
+pub fn tile(layout: Layout, area: Rect, count: usize, master_ratio: f64) -> Vec<Rect> {
+  let count = count as i32;
+  if count == 0 {
+    return Vec::new();
+  }
+  match layout {
+    Layout::Stacking => {
+      return Vec::new();
+    }
+    Layout::MasterStack => {
+      if count == 1 {
+        return vec![area];
+      }
+      let master_width = (area.width as f64 * master_ratio).round() as i32;
+      let mut out = vec![Rect::new(area.x, area.y, master_width, area.height)];
+      let stack_x = area.x + master_width;
+      for (y, height) in split(area.y, area.height, count - 1) {
+        out.push(Rect::new(stack_x, y, area.width - master_width, height));
+      }
+      return out;
+    }
+    Layout::Grid => {
+      let columns = (1..=count).find(|c| c * c >= count).unwrap();
+      let rows = (count + columns - 1) / columns;
+      let mut out = Vec::new();
+      for (row, (y, height)) in split(area.y, area.height, rows).into_iter().enumerate() {
+        // The last row spreads whatever is left across the width
+        let in_row = (count - row as i32 * columns).min(columns);
+        for (x, width) in split(area.x, area.width, in_row) {
+          out.push(Rect::new(x, y, width, height));
+        }
+      }
+      return out;
+    }
+  }
+}
+
+impl ServerState {
+  // Windows laid out as tiles, in tile order
+  pub fn tiled_windows(self: &Self) -> Vec<ClientId> {
+    if self.wm.layout == Layout::Stacking {
+      return Vec::new();
+    }
+    return self
+      .wm
+      .tiles
+      .iter()
+      .cloned()
+      .filter(|id| !self.wm.floating.contains(id))
+      .filter(|id| self.is_shown(*id) && !self.clients[id].window.maximized)
+      .collect();
+  }
+
+  pub fn is_tiled(self: &Self, client: ClientId) -> bool {
+    return self.tiled_windows().contains(&client);
+  }
+
+  // Configure every tiled window to fill its tile, decorations included
+  pub fn retile(self: &mut Self) {
+    let output = Rect::new(0, 0, self.front.width as i32, self.front.height as i32);
+    let tiled = self.tiled_windows();
+    let frames = tile(self.wm.layout, output, tiled.len(), self.wm.master_ratio);
+    for (client, frame) in tiled.into_iter().zip(frames) {
+      let content = match self.clients[&client].window.decorations {
+        DecorationMode::Server => DecorationLayout::content_for_frame(frame),
+        DecorationMode::Client => frame
+      };
+      self.configure_window(client, content);
+    }
+    for client in self.wm.floating.clone() {
+      self.wm.raise(client);
+    }
+  }
+
+  pub fn set_layout(self: &mut Self, layout: Layout) {
+    self.wm.layout = layout;
+    self.retile();
+  }
+
+  // Take a window out of the tiles or put it back
+  pub fn toggle_floating(self: &mut Self, client: ClientId) {
+    if let Some(ind) = self.wm.floating.iter().position(|c| *c == client) {
+      self.wm.floating.remove(ind);
+    }
+    else if self.clients.contains_key(&client) {
+      self.wm.floating.push(client);
+    }
+    self.retile();
+  }
+
+  pub fn adjust_master_ratio(self: &mut Self, steps: i32) {
+    let ratio = self.wm.master_ratio + steps as f64 * MASTER_RATIO_STEP;
+    self.wm.master_ratio = ratio.clamp(MIN_MASTER_RATIO, MAX_MASTER_RATIO);
+    self.retile();
+  }
+
+  // The tile `offset` places after the focused one, wrapping around
+  fn tile_from_focus(self: &Self, offset: i32) -> Option<(usize, ClientId)> {
+    let tiled = self.tiled_windows();
+    let focus = self.keyboard_focus.and_then(|f| tiled.iter().position(|c| *c == f))?;
+    let ind = (focus as i32 + offset).rem_euclid(tiled.len() as i32) as usize;
+    return Some((ind, tiled[ind]));
+  }
+
+  pub fn focus_tile(self: &mut Self, offset: i32) {
+    let next = match self.tile_from_focus(offset) {
+      Some((_, next)) => Some(next),
+      None => self.tiled_windows().first().cloned()
+    };
+    if let Some(next) = next {
+      self.wm.raise(next);
+      self.set_keyboard_focus(Some(next));
+    }
+  }
+
+  // Swap the focused tile with another, by offset or, with None, the master
+  pub fn swap_tile(self: &mut Self, offset: Option<i32>) {
+    let focus = match self.keyboard_focus {
+      Some(focus) => focus,
+      None => return
+    };
+    let other = match offset {
+      Some(offset) => self.tile_from_focus(offset).map(|(_, c)| c),
+      None => self.tiled_windows().first().cloned()
+    };
+    let tiles = &mut self.wm.tiles;
+    let a = tiles.iter().position(|c| *c == focus);
+    let b = other.and_then(|other| tiles.iter().position(|c| *c == other));
+    if let (Some(a), Some(b)) = (a, b) {
+      tiles.swap(a, b);
+      self.retile();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Message;
+  use std::sync::mpsc;
+
+  #[test]
+  fn test_layouts() {
+    let area = Rect::new(0, 0, 800, 600);
+    assert_eq!(tile(Layout::MasterStack, area, 1, 0.5), vec![area]);
+    assert_eq!(tile(Layout::MasterStack, area, 3, 0.6), vec![
+      Rect::new(0, 0, 480, 600),
+      Rect::new(480, 0, 320, 300),
+      Rect::new(480, 300, 320, 300)
+    ]);
+    // 2 by 2, with one wide tile in the last row
+    assert_eq!(tile(Layout::Grid, area, 3, 0.5), vec![
+      Rect::new(0, 0, 400, 300),
+      Rect::new(400, 0, 400, 300),
+      Rect::new(0, 300, 800, 300)
+    ]);
+    // uneven sizes still cover the area
+    let tiles = tile(Layout::Grid, Rect::new(0, 0, 100, 100), 9, 0.5);
+    assert_eq!(tiles.iter().map(|r| r.width * r.height).sum::<i32>(), 100 * 100);
+    assert!(tile(Layout::Stacking, area, 3, 0.5).is_empty());
+  }
+
+  #[test]
+  fn test_tiling() {
+    let mut state = ServerState::new(800, 600, Vec::new());
+    let (outbox, events) = mpsc::channel();
+    let a = state.add_client(outbox.clone());
+    let b = state.add_client(outbox.clone());
+    state.set_layout(Layout::MasterStack);
+    assert_eq!(state.window_rect(a), Some(Rect::new(0, 0, 440, 600)));
+    assert_eq!(state.window_rect(b), Some(Rect::new(440, 0, 360, 600)));
+    assert!(events.try_iter().any(|m| matches!(
+      m,
+      Message::ResizeEvent { width: 360, height: 600, is_main: true }
+    )));
+
+    // new windows join the stack
+    let c = state.add_client(outbox);
+    assert_eq!(state.window_rect(c), Some(Rect::new(440, 300, 360, 300)));
+
+    state.set_keyboard_focus(Some(c));
+    state.swap_tile(None);
+    assert_eq!(state.tiled_windows(), vec![c, b, a]);
+    state.adjust_master_ratio(-1);
+    assert_eq!(state.window_rect(c), Some(Rect::new(0, 0, 400, 600)));
+    state.focus_tile(-1);
+    assert_eq!(state.keyboard_focus, Some(a));
+
+    state.toggle_floating(a);
+    assert_eq!(state.tiled_windows(), vec![c, b]);
+    assert_eq!(state.window_order().last(), Some(&a));
+    state.remove_client(b);
+    assert_eq!(state.window_rect(c), Some(Rect::new(0, 0, 800, 600)));
+  }
+}
@@ -0,0 +1,104 @@
+// Touch input. Each touch point belongs to the surface it went down on for
+// its whole life, even when it moves off that surface, so gestures keep
+// working. Positions are in the surface's buffer pixels.
+
+use crate::{ClientId, Message, ServerState, SurfaceId, TouchId};
+use std::collections::HashMap;
+
+pub struct TouchTracker {
+  points: HashMap<TouchId, (ClientId, SurfaceId)>
+}
+
+impl TouchTracker {
+  pub fn new() -> Self {
+    TouchTracker { points: HashMap::new() }
+  }
+
+  pub fn remove_client(self: &mut Self, client: ClientId) {
+    self.points.retain(|_, (c, _)| *c != client);
+  }
+}
+
+impl Default for TouchTracker {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ServerState {
+  // Touch point `id` went down at an output position
+  pub fn touch_down(self: &mut Self, id: TouchId, x: f64, y: f64) {
+    let (client, surface) = match self.surface_at(x as i32, y as i32) {
+      Some(hit) => hit,
+      None => return
+    };
+    if let Some((sx, sy)) = self.to_surface_coords(client, surface, x, y) {
+      self.touch.points.insert(id, (client, surface));
+      self.send(client, Message::TouchDownEvent { id, surface, x: sx, y: sy });
+    }
+  }
+
+  pub fn touch_motion(self: &mut Self, id: TouchId, x: f64, y: f64) {
+    let (client, surface) = match self.touch.points.get(&id) {
+      Some(target) => *target,
+      None => return
+    };
+    match self.to_surface_coords(client, surface, x, y) {
+      Some((sx, sy)) => self.send(client, Message::TouchMotionEvent { id, x: sx, y: sy }),
+      // The surface went away under the finger
+      None => self.touch_cancel(id)
+    }
+  }
+
+  pub fn touch_up(self: &mut Self, id: TouchId) {
+    if let Some((client, _)) = self.touch.points.remove(&id) {
+      self.send(client, Message::TouchUpEvent { id });
+    }
+  }
+
+  // The host took the touch point over, e.g. for a system gesture
+  pub fn touch_cancel(self: &mut Self, id: TouchId) {
+    if let Some((client, _)) = self.touch.points.remove(&id) {
+      self.send(client, Message::TouchCancelEvent { id });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::MAIN_SURFACE;
+  use std::sync::mpsc;
+
+  #[test]
+  fn test_touch_routing() {
+    let mut state = ServerState::new(800, 600, Vec::new());
+    let (outbox, events) = mpsc::channel();
+    let client = state.add_client(outbox);
+    let window = state.window_rect(client).unwrap();
+    state.clients.get_mut(&client).unwrap().surfaces.get_mut(MAIN_SURFACE).unwrap().scale = 2;
+    let (x, y) = (window.x as f64, window.y as f64);
+
+    state.touch_down(1, x + 10.0, y + 20.0);
+    // outside every surface
+    state.touch_down(2, 0.0, 0.0);
+    // stays with its surface when moving off it
+    state.touch_motion(1, x - 5.0, y + 20.0);
+    state.touch_motion(2, x, y);
+    state.touch_up(1);
+    state.touch_up(1);
+
+    let got: Vec<String> = events
+      .try_iter()
+      .filter_map(|m| match m {
+        Message::TouchDownEvent { id, surface, x, y } => {
+          Some(format!("down {} {} {} {}", id, surface, x, y))
+        }
+        Message::TouchMotionEvent { id, x, y } => Some(format!("motion {} {} {}", id, x, y)),
+        Message::TouchUpEvent { id } => Some(format!("up {}", id)),
+        _ => None
+      })
+      .collect();
+    assert_eq!(got, vec!["down 1 0 20 40", "motion 1 -10 40", "up 1"]);
+  }
+}
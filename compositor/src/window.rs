@@ -0,0 +1,203 @@
+// Per-client window state: where the main surface sits on the output, its
+// title, the maximized/minimized state that decorations and clients can
+// change, and the output and workspace it is on.
+
+use crate::decoration::DecorationLayout;
+use crate::geometry::Rect;
+use crate::{
+  ClientId, DecorationMode, Message, OutputId, ServerState, SurfaceId, MAIN_SURFACE,
+  MAX_SURFACE_SIZE
+};
+
+pub struct WindowState {
+  // Output position of the main surface's top left corner
+  pub x: i32,
+  pub y: i32,
+  pub title: String,
+  pub decorations: DecorationMode,
+  pub maximized: bool,
+  pub minimized: bool,
+  pub output: OutputId,
+  pub workspace: u32,
+  // Content rect to return to when unmaximized
+  restore: Option<Rect>,
+  // Whether the client was last told the window is shown
+  visible: bool
+}
+
+impl WindowState {
+  pub fn new(x: i32, y: i32) -> Self {
+    WindowState {
+      x,
+      y,
+      title: String::new(),
+      decorations: DecorationMode::Client,
+      maximized: false,
+      minimized: false,
+      output: 0,
+      workspace: 0,
+      restore: None,
+      visible: true
+    }
+  }
+}
+
+impl ServerState {
+  // Visible windows, bottom to top
+  pub fn window_order(self: &Self) -> Vec<ClientId> {
+    let mut ids = self.wm.stack.clone();
+    ids.retain(|id| self.is_shown(*id));
+    return ids;
+  }
+
+  // Not minimized and on the current workspace of its output
+  pub fn is_shown(self: &Self, client: ClientId) -> bool {
+    let window = &self.clients[&client].window;
+    return !window.minimized && window.workspace == self.current_workspace(window.output);
+  }
+
+  // Hidden windows can't keep the keyboard; give it to the topmost shown one
+  // on the output the user is on
+  pub(crate) fn refocus(self: &mut Self) {
+    if !self.keyboard_focus.is_some_and(|f| self.is_shown(f)) {
+      let output = self.wm.active_output;
+      let order = self.window_order();
+      let top = order.into_iter().rfind(|c| self.clients[c].window.output == output);
+      self.set_keyboard_focus(top);
+    }
+  }
+
+  // Tell clients whose windows were hidden or shown since the last call
+  pub(crate) fn update_visibility(self: &mut Self) {
+    let ids: Vec<ClientId> = self.clients.keys().cloned().collect();
+    for id in ids {
+      let visible = self.is_shown(id);
+      let c = self.clients.get_mut(&id).unwrap();
+      if c.window.visible != visible {
+        c.window.visible = visible;
+        c.send(Message::VisibilityEvent { visible });
+      }
+    }
+  }
+
+  // Output rect of a client's main surface
+  pub fn window_rect(self: &Self, client: ClientId) -> Option<Rect> {
+    let c = self.clients.get(&client)?;
+    let (width, height) = c.surfaces.get(MAIN_SURFACE).ok()?.output_size(self.output_scale());
+    return Some(Rect::new(c.window.x, c.window.y, width as i32, height as i32));
+  }
+
+  // Output rects of a client's surfaces, bottom to top. Subsurface offsets
+  // are in main surface buffer pixels.
+  pub fn surface_rects(self: &Self, client: ClientId) -> Vec<(SurfaceId, Rect)> {
+    let c = match self.clients.get(&client) {
+      Some(c) => c,
+      None => return Vec::new()
+    };
+    let output_scale = self.output_scale();
+    let factor = output_scale / c.surfaces.get(MAIN_SURFACE).unwrap().scale as f64;
+    let mut out = Vec::new();
+    for (id, dx, dy) in c.surfaces.render_order() {
+      let (width, height) = c.surfaces.get(id).unwrap().output_size(output_scale);
+      let x = c.window.x + (dx as f64 * factor).round() as i32;
+      let y = c.window.y + (dy as f64 * factor).round() as i32;
+      out.push((id, Rect::new(x, y, width as i32, height as i32)));
+    }
+    return out;
+  }
+
+  // Topmost surface at an output position
+  pub fn surface_at(self: &Self, x: i32, y: i32) -> Option<(ClientId, SurfaceId)> {
+    for client in self.window_order().into_iter().rev() {
+      let rects = self.surface_rects(client);
+      if let Some((id, _)) = rects.iter().rev().find(|(_, r)| r.contains(x, y)) {
+        return Some((client, *id));
+      }
+    }
+    return None;
+  }
+
+  // An output position in a surface's buffer pixels, which may lie outside
+  // the surface
+  pub fn to_surface_coords(
+    self: &Self, client: ClientId, surface: SurfaceId, x: f64, y: f64
+  ) -> Option<(f64, f64)> {
+    let rect = self.surface_rects(client).into_iter().find(|(id, _)| *id == surface)?.1;
+    let buffer_scale = self.clients[&client].surfaces.get(surface).ok()?.scale as f64;
+    let factor = buffer_scale / self.output_scale();
+    return Some(((x - rect.x as f64) * factor, (y - rect.y as f64) * factor));
+  }
+
+  // Move and resize a client's main surface, telling the client if its buffer
+  // size changed
+  pub fn configure_window(self: &mut Self, client: ClientId, rect: Rect) {
+    let output_scale = self.output_scale();
+    let c = match self.clients.get_mut(&client) {
+      Some(c) => c,
+      None => return
+    };
+    c.window.x = rect.x;
+    c.window.y = rect.y;
+    let main = c.surfaces.get_mut(MAIN_SURFACE).unwrap();
+    let factor = main.scale as f64 / output_scale;
+    // Bounded like the surfaces clients create, whatever the scales
+    let size = |len: i32| {
+      return ((len.max(1) as f64 * factor).round() as usize).clamp(1, MAX_SURFACE_SIZE);
+    };
+    let (width, height) = (size(rect.width), size(rect.height));
+    if (main.width, main.height) != (width, height) {
+      main.resize(width, height);
+      c.send(Message::ResizeEvent { width, height, is_main: true });
+    }
+  }
+
+  pub fn set_maximized(self: &mut Self, client: ClientId, maximized: bool) {
+    let current = match self.window_rect(client) {
+      Some(r) => r,
+      None => return
+    };
+    let output = Rect::new(0, 0, self.front.width as i32, self.front.height as i32);
+    let c = self.clients.get_mut(&client).unwrap();
+    if c.window.maximized == maximized {
+      return;
+    }
+    c.window.maximized = maximized;
+    let target = if maximized {
+      c.window.restore = Some(current);
+      match c.window.decorations {
+        DecorationMode::Server => DecorationLayout::content_for_frame(output),
+        DecorationMode::Client => output
+      }
+    }
+    else {
+      c.window.restore.take().unwrap_or(current)
+    };
+    self.configure_window(client, target);
+    self.send_window_state(client);
+    self.retile();
+  }
+
+  pub fn set_minimized(self: &mut Self, client: ClientId, minimized: bool) {
+    if let Some(c) = self.clients.get_mut(&client) {
+      if c.window.minimized != minimized {
+        c.window.minimized = minimized;
+        if !minimized {
+          self.wm.raise(client);
+        }
+        self.send_window_state(client);
+        self.update_visibility();
+        self.retile();
+        self.refocus();
+      }
+    }
+  }
+
+  fn send_window_state(self: &Self, client: ClientId) {
+    if let Some(c) = self.clients.get(&client) {
+      c.send(Message::WindowStateEvent {
+        maximized: c.window.maximized,
+        minimized: c.window.minimized
+      });
+    }
+  }
+}
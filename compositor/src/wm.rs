@@ -0,0 +1,316 @@
+// Stacking window management: where new windows go, which window is on top,
+// and moving and resizing windows with the pointer. Windows are raised and
+// focused when clicked. They move by their title bar or anywhere with Alt
+// held, and resize by their decoration border; the new size is sent to the
+// client when the drag ends. Tiled windows (see tiling.rs) only move with
+// their tile.
+
+use crate::decoration::{resize_rect, DecorationHit, DecorationLayout, ResizeEdge};
+use crate::tiling::Layout;
+use crate::{ClientId, DecorationMode, Message, OutputId, Rect, ServerState};
+use std::collections::HashMap;
+
+pub const MIN_WINDOW_SIZE: i32 = 64;
+// Where the first new window goes, leaving room for decorations
+const CASCADE_ORIGIN: (i32, i32) = (40, 60);
+const CASCADE_STEP: i32 = 32;
+
+// Pointer drag moving or resizing a window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Drag {
+  // Offset of the grab point from the window origin
+  Move {
+    client: ClientId,
+    grab: (i32, i32)
+  },
+  Resize {
+    client: ClientId,
+    edge: ResizeEdge,
+    start: Rect,
+    origin: (i32, i32),
+    current: Rect
+  }
+}
+
+pub struct WindowManager {
+  // Every window, minimized or not, bottom to top
+  pub stack: Vec<ClientId>,
+  pub drag: Option<Drag>,
+  // Index of the workspace each output shows, 0 for outputs not in it
+  pub workspaces: HashMap<OutputId, u32>,
+  // Output the user is on, that of the window last focused
+  pub active_output: OutputId,
+  pub layout: Layout,
+  // Every window in tile order
+  pub tiles: Vec<ClientId>,
+  // Windows left out of the tiles
+  pub floating: Vec<ClientId>,
+  // Share of the output width the master tile takes
+  pub master_ratio: f64,
+  // Windows placed since the cascade last started over
+  cascaded: i32
+}
+
+impl WindowManager {
+  pub fn new() -> Self {
+    WindowManager {
+      stack: Vec::new(),
+      drag: None,
+      workspaces: HashMap::new(),
+      active_output: 0,
+      layout: Layout::Stacking,
+      tiles: Vec::new(),
+      floating: Vec::new(),
+      master_ratio: 0.55,
+      cascaded: 0
+    }
+  }
+
+  // Position for a new window of the given size: each one down and to the
+  // right of the last, starting over when it would leave the output
+  pub fn cascade(self: &mut Self, output: Rect, width: i32, height: i32) -> (i32, i32) {
+    let offset = |n: i32| {
+      return (CASCADE_ORIGIN.0 + n * CASCADE_STEP, CASCADE_ORIGIN.1 + n * CASCADE_STEP);
+    };
+    let (x, y) = offset(self.cascaded);
+    let fits = x + width <= output.x + output.width && y + height <= output.y + output.height;
+    if self.cascaded > 0 && !fits {
+      self.cascaded = 0;
+    }
+    let pos = offset(self.cascaded);
+    self.cascaded += 1;
+    return pos;
+  }
+
+  pub fn add(self: &mut Self, client: ClientId) {
+    self.stack.push(client);
+    self.tiles.push(client);
+  }
+
+  pub fn remove(self: &mut Self, client: ClientId) {
+    self.stack.retain(|c| *c != client);
+    self.tiles.retain(|c| *c != client);
+    self.floating.retain(|c| *c != client);
+    if let Some(Drag::Move { client: c, .. }) | Some(Drag::Resize { client: c, .. }) = self.drag {
+      if c == client {
+        self.drag = None;
+      }
+    }
+  }
+
+  // Put a window on top of the others
+  pub fn raise(self: &mut Self, client: ClientId) {
+    if let Some(ind) = self.stack.iter().position(|c| *c == client) {
+      self.stack.remove(ind);
+      self.stack.push(client);
+    }
+  }
+}
+
+impl Default for WindowManager {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ServerState {
+  // Place a new window on the current workspace of the output the user is on
+  // and put it on top, or in the last tile
+  pub(crate) fn manage_window(self: &mut Self, client: ClientId) {
+    let output = Rect::new(0, 0, self.front.width as i32, self.front.height as i32);
+    let output_id = self.wm.active_output;
+    let workspace = self.current_workspace(output_id);
+    if let Some(rect) = self.window_rect(client) {
+      let (x, y) = self.wm.cascade(output, rect.width, rect.height);
+      let window = &mut self.clients.get_mut(&client).unwrap().window;
+      window.x = x;
+      window.y = y;
+      window.output = output_id;
+      window.workspace = workspace;
+    }
+    self.wm.add(client);
+    self.retile();
+  }
+
+  // Visible windows with server-side decorations, bottom to top
+  pub fn decorated_windows(self: &Self) -> Vec<(ClientId, Rect)> {
+    return self
+      .window_order()
+      .into_iter()
+      .filter(|id| self.clients[id].window.decorations == DecorationMode::Server)
+      .filter_map(|id| Some((id, self.window_rect(id)?)))
+      .collect();
+  }
+
+  // Topmost visible window under (x, y), including its decorations
+  pub fn window_at(self: &Self, x: i32, y: i32) -> Option<ClientId> {
+    return self.window_order().into_iter().rev().find(|id| {
+      let rect = match self.window_rect(*id) {
+        Some(rect) => rect,
+        None => return false
+      };
+      let frame = match self.clients[id].window.decorations {
+        DecorationMode::Server => DecorationLayout::new(rect).frame,
+        DecorationMode::Client => rect
+      };
+      frame.contains(x, y)
+    });
+  }
+
+  // A button press at (x, y): raise and focus the window there, then act on
+  // its decorations or start an Alt drag
+  pub fn pointer_press(self: &mut Self, x: i32, y: i32) {
+    self.wm.drag = None;
+    let client = self.window_at(x, y);
+    if let Some(client) = client {
+      self.wm.raise(client);
+    }
+    self.set_keyboard_focus(client);
+    let client = match client {
+      Some(client) => client,
+      None => return
+    };
+    let rect = self.window_rect(client).unwrap();
+    let window = &self.clients[&client].window;
+    let (maximized, decorations) = (window.maximized, window.decorations);
+    let fixed = maximized || self.is_tiled(client);
+    if self.keyboard.modifiers.alt {
+      if !fixed {
+        self.wm.drag = Some(Drag::Move { client, grab: (x - rect.x, y - rect.y) });
+      }
+      return;
+    }
+    if decorations != DecorationMode::Server {
+      return;
+    }
+    match DecorationLayout::new(rect).hit_test(x, y) {
+      Some(DecorationHit::Close) => self.send(client, Message::CloseRequestEvent),
+      Some(DecorationHit::Maximize) => self.set_maximized(client, !maximized),
+      Some(DecorationHit::Minimize) => self.set_minimized(client, true),
+      Some(DecorationHit::TitleBar) if !fixed => {
+        self.wm.drag = Some(Drag::Move { client, grab: (x - rect.x, y - rect.y) });
+      }
+      Some(DecorationHit::Edge(edge)) if !fixed => {
+        self.wm.drag = Some(Drag::Resize {
+          client,
+          edge,
+          start: rect,
+          origin: (x, y),
+          current: rect
+        });
+      }
+      _ => {}
+    }
+  }
+
+  pub fn pointer_motion(self: &mut Self, x: i32, y: i32) {
+    match &mut self.wm.drag {
+      Some(Drag::Move { client, grab }) => {
+        let (client, grab) = (*client, *grab);
+        if let Some(rect) = self.window_rect(client) {
+          let moved = Rect::new(x - grab.0, y - grab.1, rect.width, rect.height);
+          self.configure_window(client, moved);
+        }
+      }
+      Some(Drag::Resize {
+        edge,
+        start,
+        origin,
+        current,
+        ..
+      }) => {
+        *current = resize_rect(*start, *edge, x - origin.0, y - origin.1, MIN_WINDOW_SIZE);
+      }
+      None => {}
+    }
+  }
+
+  // End of a drag. A resize takes effect now.
+  pub fn pointer_release(self: &mut Self) {
+    if let Some(Drag::Resize { client, current, .. }) = self.wm.drag.take() {
+      self.configure_window(client, current);
+    }
+  }
+
+  // Outline of the size a window is being resized to
+  pub fn resize_outline(self: &Self) -> Option<Rect> {
+    match self.wm.drag {
+      Some(Drag::Resize { current, .. }) => Some(DecorationLayout::new(current).frame),
+      _ => None
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::mpsc;
+  use std::time::Instant;
+
+  #[test]
+  fn test_cascade() {
+    let mut wm = WindowManager::new();
+    let output = Rect::new(0, 0, 200, 200);
+    assert_eq!(wm.cascade(output, 100, 100), (40, 60));
+    assert_eq!(wm.cascade(output, 100, 100), (72, 92));
+    // the third would stick out
+    assert_eq!(wm.cascade(output, 100, 100), (40, 60));
+    // too big for anywhere, so always the origin
+    assert_eq!(wm.cascade(output, 300, 300), (40, 60));
+  }
+
+  #[test]
+  fn test_stacking_and_drags() {
+    let mut state = ServerState::new(800, 600, Vec::new());
+    let (outbox, events) = mpsc::channel();
+    let a = state.add_client(outbox.clone());
+    let b = state.add_client(outbox);
+    state.clients.get_mut(&b).unwrap().window.decorations = DecorationMode::Server;
+    assert_eq!(state.window_rect(a), Some(Rect::new(40, 60, 400, 400)));
+    assert_eq!(state.window_rect(b), Some(Rect::new(72, 92, 400, 400)));
+    assert_eq!(state.window_order(), vec![a, b]);
+
+    // click-to-raise where a shows
+    state.pointer_press(50, 70);
+    state.pointer_release();
+    assert_eq!(state.window_order(), vec![b, a]);
+    assert_eq!(state.keyboard_focus, Some(a));
+
+    // Holding left Alt lets a be dragged from anywhere inside it
+    state.key_input(56, true, Instant::now());
+    state.pointer_press(100, 100);
+    state.pointer_motion(110, 90);
+    state.pointer_release();
+    state.key_input(56, false, Instant::now());
+    assert_eq!(state.window_rect(a), Some(Rect::new(50, 50, 400, 400)));
+
+    // b's bottom right corner resizes it once the drag ends
+    let frame = DecorationLayout::new(state.window_rect(b).unwrap()).frame;
+    let corner = (frame.x + frame.width - 1, frame.y + frame.height - 1);
+    state.pointer_press(corner.0, corner.1);
+    assert_eq!(state.window_order(), vec![a, b]);
+    state.pointer_motion(corner.0 - 100, corner.1 + 20);
+    assert_eq!(state.window_rect(b), Some(Rect::new(72, 92, 400, 400)));
+    assert!(state.resize_outline().is_some());
+    state.pointer_release();
+    assert_eq!(state.window_rect(b), Some(Rect::new(72, 92, 300, 420)));
+    assert!(events.try_iter().any(|m| matches!(
+      m,
+      Message::ResizeEvent { width: 300, height: 420, is_main: true }
+    )));
+
+    // minimizing the focused window moves focus off it, and Logo+Tab brings it back
+    assert_eq!(state.keyboard_focus, Some(b));
+    state.set_minimized(b, true);
+    assert_eq!(state.window_order(), vec![a]);
+    assert_eq!(state.keyboard_focus, Some(a));
+    for (scancode, pressed) in &[(125, true), (15, true), (15, false), (125, false)] {
+      state.key_input(*scancode, *pressed, Instant::now());
+    }
+    assert_eq!(state.window_order(), vec![a, b]);
+    assert_eq!(state.keyboard_focus, Some(b));
+    state.set_minimized(b, true);
+    state.remove_client(a);
+    assert_eq!(state.wm.stack, vec![b]);
+  }
+}
@@ -0,0 +1,168 @@
+// Workspaces: each output shows one of several sets of windows at a time.
+// A window is on one output. New windows open on the current workspace of the
+// output the user is on, the one of the focused window, and the workspace
+// shortcuts act on that output. Clients hear when their window is hidden or
+// shown again, and every client hears which workspace each output shows, so
+// panels can show it.
+
+use crate::{ClientId, Message, OutputId, ServerState};
+
+pub const WORKSPACE_COUNT: u32 = 4;
+
+impl ServerState {
+  pub fn current_workspace(self: &Self, output: OutputId) -> u32 {
+    return self.wm.workspaces.get(&output).cloned().unwrap_or(0);
+  }
+
+  pub fn workspace_event(self: &Self, output: OutputId) -> Message {
+    return Message::WorkspaceEvent {
+      output,
+      current: self.current_workspace(output),
+      count: WORKSPACE_COUNT
+    };
+  }
+
+  // Switch the output the user is on to another workspace
+  pub fn switch_workspace(self: &mut Self, index: u32) {
+    let output = self.wm.active_output;
+    if index >= WORKSPACE_COUNT || index == self.current_workspace(output) {
+      return;
+    }
+    self.wm.workspaces.insert(output, index);
+    self.wm.drag = None;
+    self.update_visibility();
+    self.retile();
+    self.refocus();
+    for c in self.clients.values() {
+      c.send(self.workspace_event(output));
+    }
+  }
+
+  pub fn move_to_workspace(self: &mut Self, client: ClientId, index: u32) {
+    if index >= WORKSPACE_COUNT {
+      return;
+    }
+    if let Some(c) = self.clients.get_mut(&client) {
+      c.window.workspace = index;
+      self.update_visibility();
+      self.retile();
+      self.refocus();
+    }
+  }
+
+  // Put a window on the workspace an output shows
+  pub fn move_to_output(self: &mut Self, client: ClientId, output: OutputId) {
+    let workspace = self.current_workspace(output);
+    if let Some(c) = self.clients.get_mut(&client) {
+      c.window.output = output;
+      c.window.workspace = workspace;
+      self.update_visibility();
+      self.retile();
+      self.refocus();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{OutputInfo, Transform};
+  use std::sync::mpsc;
+  use std::time::Instant;
+
+  #[test]
+  fn test_workspaces() {
+    let mut state = ServerState::new(800, 600, Vec::new());
+    let (a_outbox, a_events) = mpsc::channel();
+    let (b_outbox, b_events) = mpsc::channel();
+    let a = state.add_client(a_outbox);
+    let b = state.add_client(b_outbox);
+    state.set_keyboard_focus(Some(b));
+
+    // Logo+Shift+2 sends the focused window to the second workspace
+    let now = Instant::now();
+    for (scancode, pressed) in &[(125, true), (42, true), (3, true), (3, false), (42, false)] {
+      state.key_input(*scancode, *pressed, now);
+    }
+    assert_eq!(state.window_order(), vec![a]);
+    assert_eq!(state.keyboard_focus, Some(a));
+    let visibility = |events: &mpsc::Receiver<Message>| -> Vec<bool> {
+      return events
+        .try_iter()
+        .filter_map(|m| match m {
+          Message::VisibilityEvent { visible } => Some(visible),
+          _ => None
+        })
+        .collect();
+    };
+    assert_eq!(visibility(&b_events), vec![false]);
+
+    // Logo+2 follows it
+    a_events.try_iter().for_each(drop);
+    state.key_input(3, true, now);
+    state.key_input(3, false, now);
+    assert_eq!(state.window_order(), vec![b]);
+    assert_eq!(state.keyboard_focus, Some(b));
+    let got: Vec<Message> = a_events.try_iter().collect();
+    assert!(matches!(got[..], [
+      Message::VisibilityEvent { visible: false },
+      Message::KeyboardLeaveEvent,
+      Message::WorkspaceEvent { current: 1, count: 4, .. }
+    ]));
+    assert_eq!(visibility(&b_events), vec![true]);
+
+    // new windows open where the user is
+    let (c_outbox, _c_events) = mpsc::channel();
+    let c = state.add_client(c_outbox);
+    assert_eq!(state.window_order(), vec![b, c]);
+    state.switch_workspace(WORKSPACE_COUNT);
+    assert_eq!(state.current_workspace(0), 1);
+  }
+
+  #[test]
+  fn test_workspaces_per_output() {
+    let mut state = ServerState::new(800, 600, Vec::new());
+    for id in 0..2 {
+      state.set_output(OutputInfo {
+        id,
+        name: format!("output {}", id),
+        width: 800,
+        height: 600,
+        physical_width_mm: 0,
+        physical_height_mm: 0,
+        scale: 1.0,
+        refresh_mhz: 0,
+        transform: Transform::Normal
+      });
+    }
+    let (a_outbox, a_events) = mpsc::channel();
+    let (b_outbox, _b_events) = mpsc::channel();
+    let a = state.add_client(a_outbox);
+    let b = state.add_client(b_outbox);
+    state.move_to_output(b, 1);
+    state.set_keyboard_focus(Some(b));
+
+    // Logo+2 switches only the output of the focused window
+    let now = Instant::now();
+    for (scancode, pressed) in &[(125, true), (3, true), (3, false), (125, false)] {
+      state.key_input(*scancode, *pressed, now);
+    }
+    assert_eq!((state.current_workspace(0), state.current_workspace(1)), (0, 1));
+    assert_eq!(state.window_order(), vec![a]);
+    // the keyboard stays on that output, where nothing is shown
+    assert_eq!(state.keyboard_focus, None);
+    let switched = |m: &Message| matches!(m, Message::WorkspaceEvent { output: 1, current: 1, .. });
+    assert!(a_events.try_iter().any(|m| switched(&m)));
+
+    // new windows open on the output the user is on
+    let (c_outbox, _c_events) = mpsc::channel();
+    let c = state.add_client(c_outbox);
+    assert_eq!(state.clients[&c].window.output, 1);
+    assert_eq!(state.window_order(), vec![a, c]);
+
+    // windows of a removed output move to the one left
+    state.remove_output(1);
+    assert_eq!(state.clients[&b].window.output, 0);
+    assert_eq!(state.window_order(), vec![a, b, c]);
+  }
+}
@@ -0,0 +1,192 @@
+// How messages are encoded on the stream after Hello. Hello itself is always
+// bincode so either side can read it; it is where the client picks the codec
+// for the rest of the connection. JSON lines trade size for traffic that can be
+// read with text tools and written by hand.
+
+use crate::Message;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io::{BufRead, Error, ErrorKind, Read, Result, Write};
+
+// Largest encoded message either side sends or accepts. A full frame of a 4K
+// output (about 32 MiB of RGBA) fits as bincode, but JSON lines spell every
+// byte out in decimal and need up to four times as much, so uploads that
+// large have to be compressed or split into smaller rects.
+pub const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+// Length of an encoded message about to be sent, if the peer accepts it
+fn message_len(len: usize) -> Result<u32> {
+  let too_large = || Error::new(ErrorKind::InvalidInput, "Message too large to send");
+  if len > MAX_MESSAGE_SIZE {
+    return Err(too_large());
+  }
+  return u32::try_from(len).map_err(|_| too_large());
+}
+
+pub trait Codec: Send + Sync {
+  fn write(self: &Self, msg: &Message, stream: &mut dyn Write) -> Result<()>;
+  fn read(self: &Self, stream: &mut dyn BufRead) -> Result<Message>;
+}
+
+// Each message prefixed with its length as a u32
+pub struct Bincode;
+
+// One JSON object per line, in serde's externally tagged enum layout, e.g.
+// {"SetTitle":{"title":"Chess"}} or "Pong"
+pub struct JsonLines;
+
+impl Codec for Bincode {
+  fn write(self: &Self, msg: &Message, stream: &mut dyn Write) -> Result<()> {
+    let encoded: Vec<u8> = bincode::serialize(msg).unwrap();
+    let l: u32 = message_len(encoded.len())?;
+    let l_encoded: Vec<u8> = bincode::serialize(&l).unwrap();
+    assert_eq!(l_encoded.len(), 4);
+    stream.write_all(&l_encoded[..])?;
+    stream.write_all(&encoded[..])?;
+    return Ok(());
+  }
+
+  fn read(self: &Self, stream: &mut dyn BufRead) -> Result<Message> {
+    return read_bincode(stream);
+  }
+}
+
+pub(crate) fn read_bincode(stream: &mut dyn Read) -> Result<Message> {
+  let mut l_encoded = [0u8; 4];
+  stream.read_exact(&mut l_encoded)?;
+  let l: u32 = bincode::deserialize(&l_encoded[..]).unwrap();
+  if l as usize > MAX_MESSAGE_SIZE {
+    return Err(Error::new(ErrorKind::InvalidData, "Message too large"));
+  }
+  let mut encoded: Vec<u8> = vec![0; l as usize];
+  stream.read_exact(&mut encoded[..])?;
+  let msg: Message =
+    bincode::deserialize(&encoded[..]).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+  return Ok(msg);
+}
+
+impl Codec for JsonLines {
+  fn write(self: &Self, msg: &Message, stream: &mut dyn Write) -> Result<()> {
+    let mut line = serde_json::to_vec(msg)?;
+    line.push(b'\n');
+    message_len(line.len())?;
+    return stream.write_all(&line);
+  }
+
+  fn read(self: &Self, stream: &mut dyn BufRead) -> Result<Message> {
+    let mut line = String::new();
+    let limit = MAX_MESSAGE_SIZE as u64 + 1;
+    if (&mut *stream).take(limit).read_line(&mut line)? == 0 {
+      return Err(Error::new(ErrorKind::UnexpectedEof, "Stream closed"));
+    }
+    if line.len() > MAX_MESSAGE_SIZE {
+      return Err(Error::new(ErrorKind::InvalidData, "Message too large"));
+    }
+    return Ok(serde_json::from_str(&line)?);
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum CodecKind {
+  Bincode,
+  JsonLines
+}
+
+pub const SUPPORTED_CODECS: &[CodecKind] = &[CodecKind::Bincode, CodecKind::JsonLines];
+
+impl CodecKind {
+  pub fn codec(self: Self) -> Box<dyn Codec> {
+    match self {
+      CodecKind::Bincode => Box::new(Bincode),
+      CodecKind::JsonLines => Box::new(JsonLines)
+    }
+  }
+}
+
+// Unlike compression the client's preference wins, since a codec is chosen for
+// the person reading the traffic. Bincode is always understood.
+pub fn negotiate_codec(server: &[CodecKind], client: &[CodecKind]) -> CodecKind {
+  for c in client {
+    if server.contains(c) {
+      return *c;
+    }
+  }
+  return CodecKind::Bincode;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Keymap, Rect};
+  use std::io::Cursor;
+
+  #[test]
+  fn test_codecs_round_trip() {
+    let msgs = || {
+      vec![
+        Message::SetTitle { title: "Chess\nboard".to_string() },
+        Message::KeymapEvent(Keymap::us()),
+        Message::InputMethodActivateEvent { cursor: Rect { x: -1, y: 2, width: 3, height: 4 } },
+        Message::Pong
+      ]
+    };
+    for kind in SUPPORTED_CODECS {
+      let codec = kind.codec();
+      let mut buf = Vec::new();
+      for m in msgs() {
+        codec.write(&m, &mut buf).unwrap();
+      }
+      let mut stream = Cursor::new(buf);
+      for m in msgs() {
+        match (codec.read(&mut stream).unwrap(), m) {
+          // Map order is not kept
+          (Message::KeymapEvent(got), Message::KeymapEvent(m)) => assert_eq!(got.keys, m.keys),
+          (got, m) => {
+            assert_eq!(bincode::serialize(&got).unwrap(), bincode::serialize(&m).unwrap())
+          }
+        }
+      }
+      assert!(codec.read(&mut stream).is_err());
+    }
+  }
+
+  #[test]
+  fn test_json_lines_by_hand() {
+    let text = "{\"Commit\":{\"surface\":2}}\n\"Pong\"\n";
+    let mut stream = Cursor::new(text.as_bytes());
+    assert!(matches!(JsonLines.read(&mut stream).unwrap(), Message::Commit { surface: 2 }));
+    assert!(matches!(JsonLines.read(&mut stream).unwrap(), Message::Pong));
+
+    let mut out = Vec::new();
+    let state = Message::SetWindowState { maximized: true, minimized: false };
+    JsonLines.write(&state, &mut out).unwrap();
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      "{\"SetWindowState\":{\"maximized\":true,\"minimized\":false}}\n"
+    );
+  }
+
+  #[test]
+  fn test_oversized_message() {
+    let len = bincode::serialize(&(MAX_MESSAGE_SIZE as u32 + 1)).unwrap();
+    let err = Bincode.read(&mut Cursor::new(len)).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    // lines are cut off rather than read until a newline that never comes
+    let line = vec![b' '; MAX_MESSAGE_SIZE + 10];
+    let err = JsonLines.read(&mut Cursor::new(line)).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    // nothing too large for the peer to read is sent
+    assert_eq!(message_len(MAX_MESSAGE_SIZE).unwrap() as usize, MAX_MESSAGE_SIZE);
+    assert_eq!(message_len(MAX_MESSAGE_SIZE + 1).err().unwrap().kind(), ErrorKind::InvalidInput);
+    assert!(message_len(usize::MAX).is_err());
+  }
+
+  #[test]
+  fn test_negotiate_codec() {
+    let json = [CodecKind::JsonLines, CodecKind::Bincode];
+    assert_eq!(negotiate_codec(SUPPORTED_CODECS, &json), CodecKind::JsonLines);
+    assert_eq!(negotiate_codec(&[CodecKind::Bincode], &json), CodecKind::Bincode);
+    assert_eq!(negotiate_codec(SUPPORTED_CODECS, &[]), CodecKind::Bincode);
+  }
+}
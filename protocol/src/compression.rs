@@ -0,0 +1,150 @@
+// Encodings for pixel data uploaded over transports without shared memory.
+// Clients only send the damaged rectangle, and the encoding is negotiated
+// during the Hello exchange.
+
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+
+const MAX_RUN: usize = u8::MAX as usize;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+  None,
+  // Runs of identical pixels, stored as the count followed by the pixel bytes
+  Rle,
+  // LZ4 block format with the uncompressed size prepended
+  Lz4
+}
+
+// Preference order used by the server when picking an encoding
+pub const SUPPORTED_COMPRESSION: &[Compression] =
+  &[Compression::Lz4, Compression::Rle, Compression::None];
+
+// Pick the first encoding from the server's preference list that the client
+// also supports. Uncompressed uploads are always understood.
+pub fn negotiate_compression(server: &[Compression], client: &[Compression]) -> Compression {
+  for c in server {
+    if client.contains(c) {
+      return *c;
+    }
+  }
+  return Compression::None;
+}
+
+fn encode_rle(data: &[u8], bpp: usize) -> Vec<u8> {
+  let mut out = Vec::new();
+  let mut pixels = data.chunks_exact(bpp).peekable();
+  while let Some(pix) = pixels.next() {
+    let mut count: usize = 1;
+    while count < MAX_RUN && pixels.peek() == Some(&pix) {
+      pixels.next();
+      count += 1;
+    }
+    out.push(count as u8);
+    out.extend_from_slice(pix);
+  }
+  return out;
+}
+
+fn decode_rle(data: &[u8], bpp: usize, size: usize) -> std::io::Result<Vec<u8>> {
+  if !data.len().is_multiple_of(bpp + 1) {
+    return Err(Error::new(ErrorKind::InvalidData, "Truncated RLE run"));
+  }
+  let mut out = Vec::with_capacity(size);
+  for run in data.chunks_exact(bpp + 1) {
+    let count = run[0] as usize;
+    if count == 0 {
+      return Err(Error::new(ErrorKind::InvalidData, "Empty RLE run"));
+    }
+    if out.len() + count * bpp > size {
+      return Err(Error::new(ErrorKind::InvalidData, "RLE data longer than expected"));
+    }
+    for _ in 0..count {
+      out.extend_from_slice(&run[1..]);
+    }
+  }
+  return Ok(out);
+}
+
+// The prepended size comes from the peer, so it is only trusted if it is the
+// size the pixels should have
+fn decode_lz4(data: &[u8], size: usize) -> std::io::Result<Vec<u8>> {
+  if data.len() < 4 || u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize != size {
+    return Err(Error::new(ErrorKind::InvalidData, "LZ4 size does not match rect size"));
+  }
+  return lz4_flex::decompress(&data[4..], size)
+    .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()));
+}
+
+// Pixel data is encoded in whatever format the surface uses, with `bpp` bytes
+// per pixel
+pub fn encode(data: &[u8], compression: Compression, bpp: usize) -> Vec<u8> {
+  assert!(data.len().is_multiple_of(bpp));
+  match compression {
+    Compression::None => data.to_vec(),
+    Compression::Rle => encode_rle(data, bpp),
+    Compression::Lz4 => lz4_flex::compress_prepend_size(data)
+  }
+}
+
+// Decode pixel data that should come to `size` bytes, anything else being an
+// error
+pub fn decode(
+  data: &[u8], compression: Compression, bpp: usize, size: usize
+) -> std::io::Result<Vec<u8>> {
+  let out = match compression {
+    Compression::None => data.to_vec(),
+    Compression::Rle => decode_rle(data, bpp, size)?,
+    Compression::Lz4 => decode_lz4(data, size)?
+  };
+  if out.len() != size {
+    return Err(Error::new(ErrorKind::InvalidData, "Decoded data does not match rect size"));
+  }
+  return Ok(out);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn test_pixels() -> Vec<u8> {
+    let mut data = Vec::new();
+    for i in 0..600 {
+      let pix: [u8; 4] = if i < 300 { [0x99, 0x99, 0x99, 0xff] } else { [i as u8, 0, 0, 0xff] };
+      data.extend_from_slice(&pix);
+    }
+    return data;
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let data = test_pixels();
+    for c in &[Compression::None, Compression::Rle, Compression::Lz4] {
+      let encoded = encode(&data, *c, 4);
+      assert_eq!(decode(&encoded, *c, 4, data.len()).unwrap(), data);
+      // the size has to be the one expected
+      assert!(decode(&encoded, *c, 4, data.len() - 4).is_err());
+      assert!(decode(&encoded, *c, 4, data.len() + 4).is_err());
+    }
+    // long uniform runs are split at MAX_RUN
+    assert_eq!(encode(&data[..4 * 300], Compression::Rle, 4).len(), 2 * 5);
+    // two byte pixels run independently of the four byte grouping
+    assert_eq!(encode(&[1, 2, 1, 2, 1, 2], Compression::Rle, 2), vec![3, 1, 2]);
+  }
+
+  #[test]
+  fn test_bad_rle() {
+    assert!(decode(&[0, 1, 2, 3, 4], Compression::Rle, 4, 4).is_err());
+    assert!(decode(&[1, 1, 2, 3], Compression::Rle, 4, 4).is_err());
+    // a peer can't make us allocate more than the rect needs
+    assert!(decode(&[0xff, 0xff, 0xff, 0xff, 0], Compression::Lz4, 4, 4).is_err());
+    assert!(decode(&[0xff, 1, 2, 3, 4].repeat(1000), Compression::Rle, 4, 4).is_err());
+  }
+
+  #[test]
+  fn test_negotiate() {
+    assert_eq!(negotiate_compression(SUPPORTED_COMPRESSION, &[Compression::Rle]), Compression::Rle);
+    assert_eq!(negotiate_compression(SUPPORTED_COMPRESSION, SUPPORTED_COMPRESSION), Compression::Lz4);
+    assert_eq!(negotiate_compression(SUPPORTED_COMPRESSION, &[]), Compression::None);
+  }
+}
@@ -0,0 +1,513 @@
+// Typed message handling. Each side implements the trait for the messages it
+// receives, overriding the ones it cares about; the rest are ignored. The
+// dispatchers match every message without a wildcard, so a new message does
+// not build until it is routed here.
+
+use crate::{
+  CaptureBuffer, CaptureSource, CloseReason, Compression, DecorationMode, Keymap, Keysym, Message,
+  Modifiers, OutputId, OutputInfo, Permission, PixelFormat, Rect, Shortcut, SurfaceId, TouchId,
+  Urgency
+};
+use std::io::Result;
+
+// Messages from a client, handled by the server
+pub trait ServerHandler {
+  // Hello after the handshake, or a message only the server sends
+  fn unexpected(self: &mut Self, _msg: Message) -> Result<()> {
+    return Ok(());
+  }
+  fn request_permission(self: &mut Self, _permission: Permission) -> Result<()> {
+    return Ok(());
+  }
+  fn capture_request(
+    self: &mut Self, _serial: u32, _source: CaptureSource, _buffer: CaptureBuffer
+  ) -> Result<()> {
+    return Ok(());
+  }
+  fn notify(
+    self: &mut Self, _id: u32, _summary: String, _body: String, _urgency: Urgency,
+    _timeout_ms: Option<u32>, _actions: Vec<String>
+  ) -> Result<()> {
+    return Ok(());
+  }
+  fn close_notification(self: &mut Self, _id: u32) -> Result<()> {
+    return Ok(());
+  }
+  fn set_title(self: &mut Self, _title: String) -> Result<()> {
+    return Ok(());
+  }
+  fn set_decoration_mode(self: &mut Self, _mode: DecorationMode) -> Result<()> {
+    return Ok(());
+  }
+  fn set_window_state(self: &mut Self, _maximized: bool, _minimized: bool) -> Result<()> {
+    return Ok(());
+  }
+  fn enable_text_input(self: &mut Self) -> Result<()> {
+    return Ok(());
+  }
+  fn disable_text_input(self: &mut Self) -> Result<()> {
+    return Ok(());
+  }
+  fn set_text_input_cursor(self: &mut Self, _cursor: Rect) -> Result<()> {
+    return Ok(());
+  }
+  fn register_hotkey(self: &mut Self, _id: u32, _shortcut: Shortcut) -> Result<()> {
+    return Ok(());
+  }
+  fn unregister_hotkey(self: &mut Self, _id: u32) -> Result<()> {
+    return Ok(());
+  }
+  fn register_input_method(self: &mut Self) -> Result<()> {
+    return Ok(());
+  }
+  fn preedit(self: &mut Self, _text: String, _cursor: Option<usize>) -> Result<()> {
+    return Ok(());
+  }
+  fn commit_string(self: &mut Self, _text: String) -> Result<()> {
+    return Ok(());
+  }
+  fn forward_key(self: &mut Self, _scancode: u32, _keysym: Keysym, _pressed: bool) -> Result<()> {
+    return Ok(());
+  }
+  fn subscribe_idle(self: &mut Self, _timeout_ms: u32) -> Result<()> {
+    return Ok(());
+  }
+  fn unsubscribe_idle(self: &mut Self) -> Result<()> {
+    return Ok(());
+  }
+  fn create_idle_inhibitor(self: &mut Self, _surface: SurfaceId) -> Result<()> {
+    return Ok(());
+  }
+  fn destroy_idle_inhibitor(self: &mut Self, _surface: SurfaceId) -> Result<()> {
+    return Ok(());
+  }
+  fn set_buffer_scale(self: &mut Self, _surface: SurfaceId, _scale: u32) -> Result<()> {
+    return Ok(());
+  }
+  fn set_format(self: &mut Self, _surface: SurfaceId, _format: PixelFormat) -> Result<()> {
+    return Ok(());
+  }
+  fn create_subsurface(
+    self: &mut Self, _id: SurfaceId, _parent: SurfaceId, _width: usize, _height: usize
+  ) -> Result<()> {
+    return Ok(());
+  }
+  fn set_subsurface_position(self: &mut Self, _id: SurfaceId, _x: i32, _y: i32) -> Result<()> {
+    return Ok(());
+  }
+  fn place_above(self: &mut Self, _id: SurfaceId, _sibling: SurfaceId) -> Result<()> {
+    return Ok(());
+  }
+  fn place_below(self: &mut Self, _id: SurfaceId, _sibling: SurfaceId) -> Result<()> {
+    return Ok(());
+  }
+  fn set_subsurface_sync(self: &mut Self, _id: SurfaceId, _sync: bool) -> Result<()> {
+    return Ok(());
+  }
+  fn destroy_surface(self: &mut Self, _id: SurfaceId) -> Result<()> {
+    return Ok(());
+  }
+  fn commit(self: &mut Self, _surface: SurfaceId) -> Result<()> {
+    return Ok(());
+  }
+  fn request_frame(self: &mut Self, _surface: SurfaceId) -> Result<()> {
+    return Ok(());
+  }
+  fn damage_report(self: &mut Self, _x: usize, _y: usize, _dx: usize, _dy: usize) -> Result<()> {
+    return Ok(());
+  }
+  #[allow(clippy::too_many_arguments)]
+  fn damage_upload(
+    self: &mut Self, _surface: SurfaceId, _x: usize, _y: usize, _dx: usize, _dy: usize,
+    _compression: Compression, _data: Vec<u8>
+  ) -> Result<()> {
+    return Ok(());
+  }
+  fn pong(self: &mut Self) -> Result<()> {
+    return Ok(());
+  }
+}
+
+// Messages from the server, handled by a client
+pub trait ClientHandler {
+  // Hello after the handshake, or a message only clients send
+  fn unexpected(self: &mut Self, _msg: Message) -> Result<()> {
+    return Ok(());
+  }
+  fn buffer_created(self: &mut Self) -> Result<()> {
+    return Ok(());
+  }
+  fn resize(self: &mut Self, _width: usize, _height: usize, _is_main: bool) -> Result<()> {
+    return Ok(());
+  }
+  fn frame_done(self: &mut Self, _surface: SurfaceId, _time_ms: u32) -> Result<()> {
+    return Ok(());
+  }
+  fn ping(self: &mut Self) -> Result<()> {
+    return Ok(());
+  }
+  fn permission(self: &mut Self, _permission: Permission, _granted: bool) -> Result<()> {
+    return Ok(());
+  }
+  fn capture_done(self: &mut Self, _serial: u32, _data: Vec<u8>) -> Result<()> {
+    return Ok(());
+  }
+  fn capture_failed(self: &mut Self, _serial: u32, _reason: String) -> Result<()> {
+    return Ok(());
+  }
+  fn notification_action(self: &mut Self, _id: u32, _action: String) -> Result<()> {
+    return Ok(());
+  }
+  fn notification_closed(self: &mut Self, _id: u32, _reason: CloseReason) -> Result<()> {
+    return Ok(());
+  }
+  fn decoration_mode(self: &mut Self, _mode: DecorationMode) -> Result<()> {
+    return Ok(());
+  }
+  fn close_request(self: &mut Self) -> Result<()> {
+    return Ok(());
+  }
+  fn window_state(self: &mut Self, _maximized: bool, _minimized: bool) -> Result<()> {
+    return Ok(());
+  }
+  fn visibility(self: &mut Self, _visible: bool) -> Result<()> {
+    return Ok(());
+  }
+  fn idled(self: &mut Self) -> Result<()> {
+    return Ok(());
+  }
+  fn resumed(self: &mut Self) -> Result<()> {
+    return Ok(());
+  }
+  fn output(self: &mut Self, _info: OutputInfo) -> Result<()> {
+    return Ok(());
+  }
+  fn output_removed(self: &mut Self, _id: OutputId) -> Result<()> {
+    return Ok(());
+  }
+  fn workspace(self: &mut Self, _output: OutputId, _current: u32, _count: u32) -> Result<()> {
+    return Ok(());
+  }
+  fn keymap(self: &mut Self, _keymap: Keymap) -> Result<()> {
+    return Ok(());
+  }
+  fn keyboard_enter(self: &mut Self, _modifiers: Modifiers) -> Result<()> {
+    return Ok(());
+  }
+  fn keyboard_leave(self: &mut Self) -> Result<()> {
+    return Ok(());
+  }
+  fn key(
+    self: &mut Self, _scancode: u32, _keysym: Keysym, _pressed: bool, _repeat: bool
+  ) -> Result<()> {
+    return Ok(());
+  }
+  fn modifiers(self: &mut Self, _modifiers: Modifiers) -> Result<()> {
+    return Ok(());
+  }
+  fn hotkey_registered(self: &mut Self, _id: u32) -> Result<()> {
+    return Ok(());
+  }
+  fn hotkey_failed(self: &mut Self, _id: u32, _reason: String) -> Result<()> {
+    return Ok(());
+  }
+  fn hotkey(self: &mut Self, _id: u32, _pressed: bool) -> Result<()> {
+    return Ok(());
+  }
+  fn preedit(self: &mut Self, _text: String, _cursor: Option<usize>) -> Result<()> {
+    return Ok(());
+  }
+  fn commit_string(self: &mut Self, _text: String) -> Result<()> {
+    return Ok(());
+  }
+  fn input_method_registered(self: &mut Self, _granted: bool) -> Result<()> {
+    return Ok(());
+  }
+  fn input_method_activate(self: &mut Self, _cursor: Rect) -> Result<()> {
+    return Ok(());
+  }
+  fn input_method_cursor(self: &mut Self, _cursor: Rect) -> Result<()> {
+    return Ok(());
+  }
+  fn input_method_deactivate(self: &mut Self) -> Result<()> {
+    return Ok(());
+  }
+  fn touch_down(
+    self: &mut Self, _id: TouchId, _surface: SurfaceId, _x: f64, _y: f64
+  ) -> Result<()> {
+    return Ok(());
+  }
+  fn touch_motion(self: &mut Self, _id: TouchId, _x: f64, _y: f64) -> Result<()> {
+    return Ok(());
+  }
+  fn touch_up(self: &mut Self, _id: TouchId) -> Result<()> {
+    return Ok(());
+  }
+  fn touch_cancel(self: &mut Self, _id: TouchId) -> Result<()> {
+    return Ok(());
+  }
+}
+
+// Route a message received from a client
+pub fn dispatch_to_server<H: ServerHandler + ?Sized>(handler: &mut H, msg: Message) -> Result<()> {
+  match msg {
+    Message::RequestPermission { permission } => handler.request_permission(permission),
+    Message::CaptureRequest {
+      serial,
+      source,
+      buffer
+    } => {
+      handler.capture_request(serial, source, buffer)
+    }
+    Message::Notify {
+      id,
+      summary,
+      body,
+      urgency,
+      timeout_ms,
+      actions
+    } => {
+      handler.notify(id, summary, body, urgency, timeout_ms, actions)
+    }
+    Message::CloseNotification { id } => handler.close_notification(id),
+    Message::SetTitle { title } => handler.set_title(title),
+    Message::SetDecorationMode { mode } => handler.set_decoration_mode(mode),
+    Message::SetWindowState {
+      maximized,
+      minimized
+    } => {
+      handler.set_window_state(maximized, minimized)
+    }
+    Message::EnableTextInput => handler.enable_text_input(),
+    Message::DisableTextInput => handler.disable_text_input(),
+    Message::SetTextInputCursor { cursor } => handler.set_text_input_cursor(cursor),
+    Message::RegisterHotkey { id, shortcut } => handler.register_hotkey(id, shortcut),
+    Message::UnregisterHotkey { id } => handler.unregister_hotkey(id),
+    Message::RegisterInputMethod => handler.register_input_method(),
+    Message::Preedit { text, cursor } => handler.preedit(text, cursor),
+    Message::CommitString { text } => handler.commit_string(text),
+    Message::ForwardKey {
+      scancode,
+      keysym,
+      pressed
+    } => {
+      handler.forward_key(scancode, keysym, pressed)
+    }
+    Message::SubscribeIdle { timeout_ms } => handler.subscribe_idle(timeout_ms),
+    Message::UnsubscribeIdle => handler.unsubscribe_idle(),
+    Message::CreateIdleInhibitor { surface } => handler.create_idle_inhibitor(surface),
+    Message::DestroyIdleInhibitor { surface } => handler.destroy_idle_inhibitor(surface),
+    Message::SetBufferScale { surface, scale } => handler.set_buffer_scale(surface, scale),
+    Message::SetFormat { surface, format } => handler.set_format(surface, format),
+    Message::CreateSubsurface {
+      id,
+      parent,
+      width,
+      height
+    } => {
+      handler.create_subsurface(id, parent, width, height)
+    }
+    Message::SetSubsurfacePosition { id, x, y } => handler.set_subsurface_position(id, x, y),
+    Message::PlaceAbove { id, sibling } => handler.place_above(id, sibling),
+    Message::PlaceBelow { id, sibling } => handler.place_below(id, sibling),
+    Message::SetSubsurfaceSync { id, sync } => handler.set_subsurface_sync(id, sync),
+    Message::DestroySurface { id } => handler.destroy_surface(id),
+    Message::Commit { surface } => handler.commit(surface),
+    Message::RequestFrame { surface } => handler.request_frame(surface),
+    Message::DamageReport { x, y, dx, dy } => handler.damage_report(x, y, dx, dy),
+    Message::DamageUpload {
+      surface,
+      x,
+      y,
+      dx,
+      dy,
+      compression,
+      data
+    } => {
+      handler.damage_upload(surface, x, y, dx, dy, compression, data)
+    }
+    Message::Pong => handler.pong(),
+    Message::Hello(_)
+    | Message::BufferCreatedEvent()
+    | Message::ResizeEvent { .. }
+    | Message::FrameDoneEvent { .. }
+    | Message::Ping
+    | Message::PermissionEvent { .. }
+    | Message::CaptureDoneEvent { .. }
+    | Message::CaptureFailedEvent { .. }
+    | Message::NotificationActionEvent { .. }
+    | Message::NotificationClosedEvent { .. }
+    | Message::DecorationModeEvent { .. }
+    | Message::CloseRequestEvent
+    | Message::WindowStateEvent { .. }
+    | Message::VisibilityEvent { .. }
+    | Message::IdledEvent
+    | Message::ResumedEvent
+    | Message::OutputEvent(_)
+    | Message::OutputRemovedEvent { .. }
+    | Message::WorkspaceEvent { .. }
+    | Message::KeymapEvent(_)
+    | Message::KeyboardEnterEvent { .. }
+    | Message::KeyboardLeaveEvent
+    | Message::KeyEvent { .. }
+    | Message::ModifiersEvent(_)
+    | Message::HotkeyRegisteredEvent { .. }
+    | Message::HotkeyFailedEvent { .. }
+    | Message::HotkeyEvent { .. }
+    | Message::PreeditEvent { .. }
+    | Message::CommitStringEvent { .. }
+    | Message::InputMethodRegisteredEvent { .. }
+    | Message::InputMethodActivateEvent { .. }
+    | Message::InputMethodCursorEvent { .. }
+    | Message::InputMethodDeactivateEvent
+    | Message::TouchDownEvent { .. }
+    | Message::TouchMotionEvent { .. }
+    | Message::TouchUpEvent { .. }
+    | Message::TouchCancelEvent { .. } => handler.unexpected(msg)
+  }
+}
+
+// Route a message received from the server
+pub fn dispatch_to_client<H: ClientHandler + ?Sized>(handler: &mut H, msg: Message) -> Result<()> {
+  match msg {
+    Message::BufferCreatedEvent() => handler.buffer_created(),
+    Message::ResizeEvent {
+      width,
+      height,
+      is_main
+    } => handler.resize(width, height, is_main),
+    Message::FrameDoneEvent { surface, time_ms } => handler.frame_done(surface, time_ms),
+    Message::Ping => handler.ping(),
+    Message::PermissionEvent {
+      permission,
+      granted
+    } => handler.permission(permission, granted),
+    Message::CaptureDoneEvent { serial, data } => handler.capture_done(serial, data),
+    Message::CaptureFailedEvent { serial, reason } => handler.capture_failed(serial, reason),
+    Message::NotificationActionEvent { id, action } => handler.notification_action(id, action),
+    Message::NotificationClosedEvent { id, reason } => handler.notification_closed(id, reason),
+    Message::DecorationModeEvent { mode } => handler.decoration_mode(mode),
+    Message::CloseRequestEvent => handler.close_request(),
+    Message::WindowStateEvent {
+      maximized,
+      minimized
+    } => {
+      handler.window_state(maximized, minimized)
+    }
+    Message::VisibilityEvent { visible } => handler.visibility(visible),
+    Message::IdledEvent => handler.idled(),
+    Message::ResumedEvent => handler.resumed(),
+    Message::OutputEvent(info) => handler.output(info),
+    Message::OutputRemovedEvent { id } => handler.output_removed(id),
+    Message::WorkspaceEvent {
+      output,
+      current,
+      count
+    } => {
+      handler.workspace(output, current, count)
+    }
+    Message::KeymapEvent(keymap) => handler.keymap(keymap),
+    Message::KeyboardEnterEvent { modifiers } => handler.keyboard_enter(modifiers),
+    Message::KeyboardLeaveEvent => handler.keyboard_leave(),
+    Message::KeyEvent {
+      scancode,
+      keysym,
+      pressed,
+      repeat
+    } => {
+      handler.key(scancode, keysym, pressed, repeat)
+    }
+    Message::ModifiersEvent(modifiers) => handler.modifiers(modifiers),
+    Message::HotkeyRegisteredEvent { id } => handler.hotkey_registered(id),
+    Message::HotkeyFailedEvent { id, reason } => handler.hotkey_failed(id, reason),
+    Message::HotkeyEvent { id, pressed } => handler.hotkey(id, pressed),
+    Message::PreeditEvent { text, cursor } => handler.preedit(text, cursor),
+    Message::CommitStringEvent { text } => handler.commit_string(text),
+    Message::InputMethodRegisteredEvent { granted } => handler.input_method_registered(granted),
+    Message::InputMethodActivateEvent { cursor } => handler.input_method_activate(cursor),
+    Message::InputMethodCursorEvent { cursor } => handler.input_method_cursor(cursor),
+    Message::InputMethodDeactivateEvent => handler.input_method_deactivate(),
+    Message::TouchDownEvent { id, surface, x, y } => handler.touch_down(id, surface, x, y),
+    Message::TouchMotionEvent { id, x, y } => handler.touch_motion(id, x, y),
+    Message::TouchUpEvent { id } => handler.touch_up(id),
+    Message::TouchCancelEvent { id } => handler.touch_cancel(id),
+    Message::Hello(_)
+    | Message::RequestPermission { .. }
+    | Message::CaptureRequest { .. }
+    | Message::Notify { .. }
+    | Message::CloseNotification { .. }
+    | Message::SetTitle { .. }
+    | Message::SetDecorationMode { .. }
+    | Message::SetWindowState { .. }
+    | Message::EnableTextInput
+    | Message::DisableTextInput
+    | Message::SetTextInputCursor { .. }
+    | Message::RegisterHotkey { .. }
+    | Message::UnregisterHotkey { .. }
+    | Message::RegisterInputMethod
+    | Message::Preedit { .. }
+    | Message::CommitString { .. }
+    | Message::ForwardKey { .. }
+    | Message::SubscribeIdle { .. }
+    | Message::UnsubscribeIdle
+    | Message::CreateIdleInhibitor { .. }
+    | Message::DestroyIdleInhibitor { .. }
+    | Message::SetBufferScale { .. }
+    | Message::SetFormat { .. }
+    | Message::CreateSubsurface { .. }
+    | Message::SetSubsurfacePosition { .. }
+    | Message::PlaceAbove { .. }
+    | Message::PlaceBelow { .. }
+    | Message::SetSubsurfaceSync { .. }
+    | Message::DestroySurface { .. }
+    | Message::Commit { .. }
+    | Message::RequestFrame { .. }
+    | Message::DamageReport { .. }
+    | Message::DamageUpload { .. }
+    | Message::Pong => handler.unexpected(msg)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(Default)]
+  struct Recorder {
+    got: Vec<String>
+  }
+
+  impl ServerHandler for Recorder {
+    fn unexpected(self: &mut Self, _msg: Message) -> Result<()> {
+      self.got.push("unexpected".to_string());
+      return Ok(());
+    }
+    fn commit(self: &mut Self, surface: SurfaceId) -> Result<()> {
+      self.got.push(format!("commit {}", surface));
+      return Ok(());
+    }
+  }
+
+  impl ClientHandler for Recorder {
+    fn resize(self: &mut Self, width: usize, height: usize, _is_main: bool) -> Result<()> {
+      self.got.push(format!("resize {}x{}", width, height));
+      return Ok(());
+    }
+  }
+
+  #[test]
+  fn test_dispatch() {
+    let mut server = Recorder::default();
+    dispatch_to_server(&mut server, Message::Commit { surface: 3 }).unwrap();
+    // handled by the default
+    dispatch_to_server(&mut server, Message::SetTitle { title: "t".to_string() }).unwrap();
+    dispatch_to_server(&mut server, Message::Ping).unwrap();
+    assert_eq!(server.got, vec!["commit 3", "unexpected"]);
+
+    let mut client = Recorder::default();
+    let resize = Message::ResizeEvent { width: 4, height: 2, is_main: true };
+    dispatch_to_client(&mut client, resize).unwrap();
+    dispatch_to_client(&mut client, Message::Commit { surface: 3 }).unwrap();
+    assert_eq!(client.got, vec!["resize 4x2"]);
+  }
+}
@@ -0,0 +1,129 @@
+// Pixel formats clients may render in. Surfaces are converted on upload to
+// the compositor's internal format, premultiplied RGBA bytes, so compositing
+// only ever has to deal with one layout.
+
+use serde::{Deserialize, Serialize};
+
+pub const INTERNAL_BYTES_PER_PIXEL: usize = 4;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+  // Native-endian 0xAARRGGBB words with premultiplied alpha, i.e. bytes
+  // [B, G, R, A] on little-endian (cairo's ARgb32)
+  Argb8888,
+  // As Argb8888 but the alpha byte is ignored and the pixel is opaque
+  // (cairo's Rgb24)
+  Xrgb8888,
+  // Bytes [R, G, B, A] with straight alpha, as in a `pixels` frame
+  Rgba8888,
+  // Little-endian u16 words with 5 bits red (high), 6 green, 5 blue
+  Rgb565
+}
+
+// Advertised by the server at Hello
+pub const SUPPORTED_FORMATS: &[PixelFormat] =
+  &[PixelFormat::Argb8888, PixelFormat::Xrgb8888, PixelFormat::Rgba8888, PixelFormat::Rgb565];
+
+fn premultiply(c: u8, a: u8) -> u8 {
+  return ((c as u32 * a as u32 + 127) / 255) as u8;
+}
+
+fn unpremultiply(c: u8, a: u8) -> u8 {
+  if a == 0 {
+    return 0;
+  }
+  return ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8;
+}
+
+fn expand_bits(v: u16, bits: u32) -> u8 {
+  let max = (1u32 << bits) - 1;
+  return ((v as u32 * 255 + max / 2) / max) as u8;
+}
+
+impl PixelFormat {
+  pub fn bytes_per_pixel(self: Self) -> usize {
+    match self {
+      PixelFormat::Argb8888 | PixelFormat::Xrgb8888 | PixelFormat::Rgba8888 => 4,
+      PixelFormat::Rgb565 => 2
+    }
+  }
+
+  // Convert tightly packed pixels in this format to premultiplied RGBA
+  pub fn to_internal(self: Self, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / self.bytes_per_pixel() * INTERNAL_BYTES_PER_PIXEL);
+    for pix in data.chunks_exact(self.bytes_per_pixel()) {
+      let rgba: [u8; 4] = match self {
+        PixelFormat::Argb8888 => [pix[2], pix[1], pix[0], pix[3]],
+        PixelFormat::Xrgb8888 => [pix[2], pix[1], pix[0], 0xff],
+        PixelFormat::Rgba8888 => {
+          let a = pix[3];
+          [premultiply(pix[0], a), premultiply(pix[1], a), premultiply(pix[2], a), a]
+        }
+        PixelFormat::Rgb565 => {
+          let v = u16::from_le_bytes([pix[0], pix[1]]);
+          [expand_bits(v >> 11, 5), expand_bits((v >> 5) & 0x3f, 6), expand_bits(v & 0x1f, 5), 0xff]
+        }
+      };
+      out.extend_from_slice(&rgba);
+    }
+    return out;
+  }
+
+  // Convert premultiplied RGBA to tightly packed pixels in this format
+  pub fn from_internal(self: Self, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / INTERNAL_BYTES_PER_PIXEL * self.bytes_per_pixel());
+    for pix in data.chunks_exact(INTERNAL_BYTES_PER_PIXEL) {
+      let (r, g, b, a) = (pix[0], pix[1], pix[2], pix[3]);
+      match self {
+        PixelFormat::Argb8888 => out.extend_from_slice(&[b, g, r, a]),
+        PixelFormat::Xrgb8888 => out.extend_from_slice(&[b, g, r, 0xff]),
+        PixelFormat::Rgba8888 => out.extend_from_slice(&[
+          unpremultiply(r, a),
+          unpremultiply(g, a),
+          unpremultiply(b, a),
+          a
+        ]),
+        PixelFormat::Rgb565 => {
+          let v = ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3);
+          out.extend_from_slice(&v.to_le_bytes());
+        }
+      }
+    }
+    return out;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_to_internal() {
+    // cairo stores opaque red as [B, G, R, A]
+    assert_eq!(PixelFormat::Argb8888.to_internal(&[0, 0, 0xff, 0xff]), vec![0xff, 0, 0, 0xff]);
+    assert_eq!(PixelFormat::Xrgb8888.to_internal(&[0x10, 0x20, 0x30, 0]), vec![
+      0x30, 0x20, 0x10, 0xff
+    ]);
+    // half transparent white premultiplies to half intensity
+    assert_eq!(PixelFormat::Rgba8888.to_internal(&[0xff, 0xff, 0xff, 0x80]), vec![
+      0x80, 0x80, 0x80, 0x80
+    ]);
+    // pure green in 565 is 0x07e0
+    assert_eq!(PixelFormat::Rgb565.to_internal(&[0xe0, 0x07]), vec![0, 0xff, 0, 0xff]);
+    assert_eq!(PixelFormat::Rgb565.to_internal(&[0xff, 0xff]), vec![0xff, 0xff, 0xff, 0xff]);
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let argb: [u8; 8] = [0x10, 0x20, 0x30, 0xff, 0x00, 0x40, 0x40, 0x80];
+    let rgb565: [u8; 4] = [0xe0, 0x07, 0x1f, 0xf8];
+    for (format, data) in
+      &[(PixelFormat::Argb8888, &argb[..]), (PixelFormat::Rgb565, &rgb565[..])]
+    {
+      assert_eq!(format.from_internal(&format.to_internal(data)), data.to_vec());
+    }
+    // straight alpha comes back out of premultiplied storage
+    let internal = PixelFormat::Argb8888.to_internal(&argb);
+    assert_eq!(PixelFormat::Rgba8888.from_internal(&internal)[4..], [0x80, 0x80, 0x00, 0x80]);
+  }
+}
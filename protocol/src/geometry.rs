@@ -0,0 +1,65 @@
+// Rectangles in output or surface pixels.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+  pub x: i32,
+  pub y: i32,
+  pub width: i32,
+  pub height: i32
+}
+
+impl Rect {
+  pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+    Rect { x, y, width, height }
+  }
+
+  pub fn contains(self: &Self, px: i32, py: i32) -> bool {
+    return self.x <= px && px < self.x + self.width && self.y <= py && py < self.y + self.height;
+  }
+
+  pub fn is_empty(self: &Self) -> bool {
+    return self.width <= 0 || self.height <= 0;
+  }
+
+  // Overlap of the two, None if they do not overlap
+  pub fn intersect(self: &Self, other: &Rect) -> Option<Rect> {
+    let x = self.x.max(other.x);
+    let y = self.y.max(other.y);
+    let right = (self.x + self.width).min(other.x + other.width);
+    let bottom = (self.y + self.height).min(other.y + other.height);
+    let r = Rect::new(x, y, right - x, bottom - y);
+    return if r.is_empty() { None } else { Some(r) };
+  }
+
+  // Bounding box of the two
+  pub fn union(self: &Self, other: &Rect) -> Rect {
+    if self.is_empty() {
+      return *other;
+    }
+    if other.is_empty() {
+      return *self;
+    }
+    let x = self.x.min(other.x);
+    let y = self.y.min(other.y);
+    let right = (self.x + self.width).max(other.x + other.width);
+    let bottom = (self.y + self.height).max(other.y + other.height);
+    return Rect::new(x, y, right - x, bottom - y);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_rect_ops() {
+    let a = Rect::new(0, 0, 10, 10);
+    let b = Rect::new(5, -5, 10, 10);
+    assert_eq!(a.intersect(&b), Some(Rect::new(5, 0, 5, 5)));
+    assert_eq!(a.intersect(&Rect::new(10, 0, 5, 5)), None);
+    assert_eq!(a.union(&b), Rect::new(0, -5, 15, 15));
+    assert_eq!(Rect::new(3, 3, 0, 0).union(&b), b);
+  }
+}
@@ -0,0 +1,220 @@
+// Keyboard vocabulary shared by the compositor and clients: keysyms, layouts
+// mapping scancodes to them, modifier state and shortcuts.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+// X11 keysym values; printable characters map to their Latin-1 code or
+// 0x01000000 plus their code point
+pub type Keysym = u32;
+
+pub const KEY_BACKSPACE: Keysym = 0xff08;
+pub const KEY_TAB: Keysym = 0xff09;
+pub const KEY_RETURN: Keysym = 0xff0d;
+pub const KEY_ESCAPE: Keysym = 0xff1b;
+pub const KEY_HOME: Keysym = 0xff50;
+pub const KEY_LEFT: Keysym = 0xff51;
+pub const KEY_UP: Keysym = 0xff52;
+pub const KEY_RIGHT: Keysym = 0xff53;
+pub const KEY_DOWN: Keysym = 0xff54;
+pub const KEY_END: Keysym = 0xff57;
+pub const KEY_SHIFT_L: Keysym = 0xffe1;
+pub const KEY_SHIFT_R: Keysym = 0xffe2;
+pub const KEY_CONTROL_L: Keysym = 0xffe3;
+pub const KEY_CONTROL_R: Keysym = 0xffe4;
+pub const KEY_CAPS_LOCK: Keysym = 0xffe5;
+pub const KEY_ALT_L: Keysym = 0xffe9;
+pub const KEY_ALT_R: Keysym = 0xffea;
+pub const KEY_SUPER_L: Keysym = 0xffeb;
+pub const KEY_DELETE: Keysym = 0xffff;
+
+pub const MODIFIER_KEYS: &[Keysym] = &[
+  KEY_SHIFT_L,
+  KEY_SHIFT_R,
+  KEY_CONTROL_L,
+  KEY_CONTROL_R,
+  KEY_CAPS_LOCK,
+  KEY_ALT_L,
+  KEY_ALT_R,
+  KEY_SUPER_L
+];
+
+// Names usable in layout files
+const NAMED_KEYS: &[(&str, Keysym)] = &[
+  ("BackSpace", KEY_BACKSPACE),
+  ("Tab", KEY_TAB),
+  ("Return", KEY_RETURN),
+  ("Escape", KEY_ESCAPE),
+  ("Home", KEY_HOME),
+  ("Left", KEY_LEFT),
+  ("Up", KEY_UP),
+  ("Right", KEY_RIGHT),
+  ("Down", KEY_DOWN),
+  ("End", KEY_END),
+  ("Shift_L", KEY_SHIFT_L),
+  ("Shift_R", KEY_SHIFT_R),
+  ("Control_L", KEY_CONTROL_L),
+  ("Control_R", KEY_CONTROL_R),
+  ("Caps_Lock", KEY_CAPS_LOCK),
+  ("Alt_L", KEY_ALT_L),
+  ("Alt_R", KEY_ALT_R),
+  ("Super_L", KEY_SUPER_L),
+  ("Delete", KEY_DELETE),
+  ("space", 0x20)
+];
+
+pub fn keysym_for_char(c: char) -> Keysym {
+  let code = c as u32;
+  if code < 0x100 {
+    return code;
+  }
+  return 0x0100_0000 + code;
+}
+
+// The character a keysym types, if any
+pub fn keysym_to_char(sym: Keysym) -> Option<char> {
+  match sym {
+    0x20..=0x7e | 0xa0..=0xff => std::char::from_u32(sym),
+    0x0100_0100..=0x0110_ffff => std::char::from_u32(sym - 0x0100_0000),
+    _ => None
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Keymap {
+  pub name: String,
+  // Scancode to unshifted and shifted keysym
+  pub keys: HashMap<u32, (Keysym, Keysym)>
+}
+
+impl Keymap {
+  // US QWERTY on evdev scancodes
+  pub fn us() -> Self {
+    let mut keys = HashMap::new();
+    let rows = [
+      (2, "1234567890-=", "!@#$%^&*()_+"),
+      (16, "qwertyuiop[]", "QWERTYUIOP{}"),
+      (30, "asdfghjkl;'`", "ASDFGHJKL:\"~"),
+      (43, "\\zxcvbnm,./", "|ZXCVBNM<>?")
+    ];
+    for (start, plain, shifted) in rows.iter() {
+      for (i, (p, s)) in plain.chars().zip(shifted.chars()).enumerate() {
+        keys.insert(start + i as u32, (keysym_for_char(p), keysym_for_char(s)));
+      }
+    }
+    let named = [
+      (1, KEY_ESCAPE),
+      (14, KEY_BACKSPACE),
+      (15, KEY_TAB),
+      (28, KEY_RETURN),
+      (29, KEY_CONTROL_L),
+      (42, KEY_SHIFT_L),
+      (54, KEY_SHIFT_R),
+      (56, KEY_ALT_L),
+      (57, 0x20),
+      (58, KEY_CAPS_LOCK),
+      (97, KEY_CONTROL_R),
+      (100, KEY_ALT_R),
+      (102, KEY_HOME),
+      (103, KEY_UP),
+      (105, KEY_LEFT),
+      (106, KEY_RIGHT),
+      (107, KEY_END),
+      (108, KEY_DOWN),
+      (111, KEY_DELETE),
+      (125, KEY_SUPER_L)
+    ];
+    for (code, sym) in named.iter() {
+      keys.insert(*code, (*sym, *sym));
+    }
+    return Keymap { name: "us".to_string(), keys };
+  }
+
+  // Parse a layout: lines of `<scancode> <keysym> [<shifted keysym>]`
+  // overriding the US layout, where a keysym is a single character, a name
+  // like `Return`, or a 0x-prefixed number. `#` starts a comment.
+  pub fn parse(name: &str, text: &str) -> std::io::Result<Self> {
+    let mut keymap = Keymap::us();
+    keymap.name = name.to_string();
+    for (n, line) in text.lines().enumerate() {
+      let line = line.split('#').next().unwrap().trim();
+      if line.is_empty() {
+        continue;
+      }
+      let bad = || Error::new(ErrorKind::InvalidData, format!("Bad keymap line {}", n + 1));
+      let fields: Vec<&str> = line.split_whitespace().collect();
+      if fields.len() < 2 || fields.len() > 3 {
+        return Err(bad());
+      }
+      let code: u32 = fields[0].parse().map_err(|_| bad())?;
+      let plain = parse_keysym(fields[1]).ok_or_else(bad)?;
+      let shifted = match fields.get(2) {
+        Some(f) => parse_keysym(f).ok_or_else(bad)?,
+        None => plain
+      };
+      keymap.keys.insert(code, (plain, shifted));
+    }
+    return Ok(keymap);
+  }
+}
+
+fn parse_keysym(field: &str) -> Option<Keysym> {
+  if let Some(hex) = field.strip_prefix("0x") {
+    return u32::from_str_radix(hex, 16).ok();
+  }
+  let mut chars = field.chars();
+  if let (Some(c), None) = (chars.next(), chars.next()) {
+    return Some(keysym_for_char(c));
+  }
+  return NAMED_KEYS.iter().find(|(n, _)| *n == field).map(|(_, sym)| *sym);
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Modifiers {
+  pub shift: bool,
+  pub ctrl: bool,
+  pub alt: bool,
+  pub logo: bool,
+  pub caps_lock: bool
+}
+
+// Modifiers plus the unshifted keysym, so Shift+a is `shift: true` with 'a'
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Shortcut {
+  pub ctrl: bool,
+  pub alt: bool,
+  pub shift: bool,
+  pub logo: bool,
+  pub keysym: Keysym
+}
+
+impl Shortcut {
+  pub fn new(modifiers: Modifiers, keysym: Keysym) -> Self {
+    Shortcut {
+      ctrl: modifiers.ctrl,
+      alt: modifiers.alt,
+      shift: modifiers.shift,
+      logo: modifiers.logo,
+      keysym
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_layout() {
+    let layout = "# German letters\n21 z Z\n44 y Y\n39 0xf6 0xd6\n28 Return";
+    let keymap = Keymap::parse("de", layout).unwrap();
+    assert_eq!(keymap.keys[&21], ('z' as Keysym, 'Z' as Keysym));
+    assert_eq!(keymap.keys[&39], (0xf6, 0xd6));
+    assert_eq!(keymap.keys[&28], (KEY_RETURN, KEY_RETURN));
+    // untouched keys come from the US layout
+    assert_eq!(keymap.keys[&30], ('a' as Keysym, 'A' as Keysym));
+    assert_eq!(keysym_to_char(keysym_for_char('ж')), Some('ж'));
+    assert!(Keymap::parse("bad", "21 NoSuchKey").is_err());
+  }
+}
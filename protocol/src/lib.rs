@@ -0,0 +1,433 @@
+// Wire protocol between the compositor and its clients: the messages each
+// side sends, the types they carry, and how they are framed on a stream.
+// Currently carried over unix sockets in this emulator stage.
+
+// Explicit returns and typed self are house style
+#![allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
+
+mod codec;
+mod compression;
+mod dispatch;
+mod format;
+mod geometry;
+mod keymap;
+mod output;
+mod permission;
+
+pub use codec::{
+  negotiate_codec, Bincode, Codec, CodecKind, JsonLines, MAX_MESSAGE_SIZE, SUPPORTED_CODECS
+};
+pub use compression::{decode, encode, negotiate_compression, Compression, SUPPORTED_COMPRESSION};
+pub use dispatch::{dispatch_to_client, dispatch_to_server, ClientHandler, ServerHandler};
+pub use format::{PixelFormat, INTERNAL_BYTES_PER_PIXEL, SUPPORTED_FORMATS};
+pub use geometry::Rect;
+pub use keymap::{
+  keysym_for_char, keysym_to_char, Keymap, Keysym, Modifiers, Shortcut, KEY_ALT_L, KEY_ALT_R,
+  KEY_BACKSPACE, KEY_CAPS_LOCK, KEY_CONTROL_L, KEY_CONTROL_R, KEY_DELETE, KEY_DOWN, KEY_END,
+  KEY_ESCAPE, KEY_HOME, KEY_LEFT, KEY_RETURN, KEY_RIGHT, KEY_SHIFT_L, KEY_SHIFT_R, KEY_SUPER_L,
+  KEY_TAB, KEY_UP, MODIFIER_KEYS
+};
+pub use output::{OutputId, OutputInfo, Transform};
+pub use permission::Permission;
+
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind, Read, Write};
+
+pub const DEFAULT_SOCK_PATH: &str = "/tmp/gfcomp_sock";
+// Until the client says otherwise, it renders like a `pixels` frame
+pub const DEFAULT_SURFACE_FORMAT: PixelFormat = PixelFormat::Rgba8888;
+
+pub type SurfaceId = u32;
+pub const MAIN_SURFACE: SurfaceId = 0;
+
+pub type TouchId = u64;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum CaptureSource {
+  // The whole front buffer
+  Output,
+  // The rect at (x, y) of size (dx, dy) of the front buffer
+  Region {
+    x: usize,
+    y: usize,
+    dx: usize,
+    dy: usize
+  },
+  // One of the requesting client's own surfaces, as last committed
+  Surface(SurfaceId)
+}
+
+// The client's buffer a capture is copied into: an image of `width` by
+// `height` pixels in `format`, with rows `stride` bytes apart
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CaptureBuffer {
+  pub width: usize,
+  pub height: usize,
+  pub stride: usize,
+  pub format: PixelFormat
+}
+
+impl CaptureBuffer {
+  // Tightly packed rows
+  pub fn packed(width: usize, height: usize, format: PixelFormat) -> Self {
+    CaptureBuffer { width, height, stride: width * format.bytes_per_pixel(), format }
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Urgency {
+  Low,
+  Normal,
+  // Stays up until dismissed unless the client gives a timeout
+  Critical
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum CloseReason {
+  Expired,
+  // Clicked away by the user
+  Dismissed,
+  // Closed by the client that posted it
+  Closed
+}
+
+// Who draws the window frame
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum DecorationMode {
+  Client,
+  Server
+}
+
+// Capabilities each side advertises at Hello
+#[derive(Serialize, Deserialize)]
+pub struct Handshake {
+  pub compression: Vec<Compression>,
+  pub formats: Vec<PixelFormat>,
+  // In preference order; the client's choice is used after Hello
+  pub codecs: Vec<CodecKind>
+}
+
+impl Handshake {
+  pub fn supported() -> Self {
+    Handshake {
+      compression: SUPPORTED_COMPRESSION.to_vec(),
+      formats: SUPPORTED_FORMATS.to_vec(),
+      codecs: SUPPORTED_CODECS.to_vec()
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Message {
+  // FORNOW: only advertises capabilities -- could be extended to some sort of
+  // versioning in the future
+  Hello(Handshake),
+  // Server -> Client
+  BufferCreatedEvent(),
+  // New buffer size of a surface in pixels, including its buffer scale
+  ResizeEvent {
+    width: usize,
+    height: usize,
+    is_main: bool
+  },
+  // A frame showing the surface was presented, in answer to RequestFrame.
+  // Times are milliseconds from an arbitrary start.
+  FrameDoneEvent {
+    surface: SurfaceId,
+    time_ms: u32
+  },
+  Ping,
+  PermissionEvent {
+    permission: Permission,
+    granted: bool
+  },
+  // Reply to a CaptureRequest with the contents of its buffer, `stride` times
+  // `height` bytes laid out as the buffer describes
+  CaptureDoneEvent {
+    serial: u32,
+    data: Vec<u8>
+  },
+  CaptureFailedEvent {
+    serial: u32,
+    reason: String
+  },
+  NotificationActionEvent {
+    id: u32,
+    action: String
+  },
+  NotificationClosedEvent {
+    id: u32,
+    reason: CloseReason
+  },
+  // Who draws the window frame, in answer to SetDecorationMode
+  DecorationModeEvent {
+    mode: DecorationMode
+  },
+  // The user asked to close the window
+  CloseRequestEvent,
+  WindowStateEvent {
+    maximized: bool,
+    minimized: bool
+  },
+  // The window stopped or started being shown, e.g. on another workspace or
+  // minimized. Hidden windows needn't keep drawing.
+  VisibilityEvent {
+    visible: bool
+  },
+  // No input for the subscribed timeout, and later input again
+  IdledEvent,
+  ResumedEvent,
+  // An output appeared or changed; sent for every output at connect
+  OutputEvent(OutputInfo),
+  OutputRemovedEvent {
+    id: OutputId
+  },
+  // Workspace shown on an output, sent at connect and on change
+  WorkspaceEvent {
+    output: OutputId,
+    current: u32,
+    count: u32
+  },
+  // Layout used to translate scancodes, sent at connect and on change
+  KeymapEvent(Keymap),
+  KeyboardEnterEvent {
+    modifiers: Modifiers
+  },
+  KeyboardLeaveEvent,
+  KeyEvent {
+    scancode: u32,
+    keysym: Keysym,
+    pressed: bool,
+    // Generated by server-side key repeat
+    repeat: bool
+  },
+  ModifiersEvent(Modifiers),
+  // Answers to RegisterHotkey
+  HotkeyRegisteredEvent {
+    id: u32
+  },
+  HotkeyFailedEvent {
+    id: u32,
+    reason: String
+  },
+  HotkeyEvent {
+    id: u32,
+    pressed: bool
+  },
+  // Text being composed by the input method, replacing any earlier preedit,
+  // with the cursor as a byte offset
+  PreeditEvent {
+    text: String,
+    cursor: Option<usize>
+  },
+  // Insert text at the cursor, clearing the preedit
+  CommitStringEvent {
+    text: String
+  },
+  // Answer to RegisterInputMethod
+  InputMethodRegisteredEvent {
+    granted: bool
+  },
+  // To the input method: a client with text input enabled has the keyboard,
+  // and where its text cursor is on the output
+  InputMethodActivateEvent {
+    cursor: Rect
+  },
+  InputMethodCursorEvent {
+    cursor: Rect
+  },
+  InputMethodDeactivateEvent,
+  // A touch point went down on a surface; later events for the same id go
+  // to that surface, in its buffer pixels
+  TouchDownEvent {
+    id: TouchId,
+    surface: SurfaceId,
+    x: f64,
+    y: f64
+  },
+  TouchMotionEvent {
+    id: TouchId,
+    x: f64,
+    y: f64
+  },
+  TouchUpEvent {
+    id: TouchId
+  },
+  // The touch point is no longer the client's; undo whatever it started
+  TouchCancelEvent {
+    id: TouchId
+  },
+  // Client -> Server
+  RequestPermission {
+    permission: Permission
+  },
+  // Copy the source into the client's buffer, answered by a CaptureDoneEvent
+  // or CaptureFailedEvent with the same serial. Fails unless the buffer is
+  // the size of the source.
+  CaptureRequest {
+    serial: u32,
+    source: CaptureSource,
+    buffer: CaptureBuffer
+  },
+  // Post or replace the notification with this client-chosen id. A timeout of
+  // None uses the compositor default for the urgency, Some(0) never expires.
+  Notify {
+    id: u32,
+    summary: String,
+    body: String,
+    urgency: Urgency,
+    timeout_ms: Option<u32>,
+    actions: Vec<String>
+  },
+  CloseNotification {
+    id: u32
+  },
+  // Shown in the title bar when the compositor draws decorations
+  SetTitle {
+    title: String
+  },
+  // Ask for server-side decorations; the compositor has the final say
+  SetDecorationMode {
+    mode: DecorationMode
+  },
+  SetWindowState {
+    maximized: bool,
+    minimized: bool
+  },
+  // A text field has focus; the input method serves the client while it has
+  // the keyboard
+  EnableTextInput,
+  DisableTextInput,
+  // Text cursor in main surface buffer pixels, for placing candidate windows
+  SetTextInputCursor {
+    cursor: Rect
+  },
+  // Needs the GlobalShortcuts permission. The id is chosen by the client and
+  // re-registering it replaces the shortcut.
+  RegisterHotkey {
+    id: u32,
+    shortcut: Shortcut
+  },
+  UnregisterHotkey {
+    id: u32
+  },
+  // Become the input method. Needs the InputMethod permission.
+  RegisterInputMethod,
+  // From the input method, passed on as PreeditEvent, CommitStringEvent and
+  // KeyEvent to the client it serves
+  Preedit {
+    text: String,
+    cursor: Option<usize>
+  },
+  CommitString {
+    text: String
+  },
+  ForwardKey {
+    scancode: u32,
+    keysym: Keysym,
+    pressed: bool
+  },
+  // Get IdledEvent after this long without input; replaces any earlier
+  // subscription
+  SubscribeIdle {
+    timeout_ms: u32
+  },
+  UnsubscribeIdle,
+  // Keep the session from going idle while the surface is visible
+  CreateIdleInhibitor {
+    surface: SurfaceId
+  },
+  DestroyIdleInhibitor {
+    surface: SurfaceId
+  },
+  // Buffer pixels per logical pixel of the surface, 1 by default. The main
+  // surface keeps its size on the output, so it gets a ResizeEvent for the
+  // new buffer size.
+  SetBufferScale {
+    surface: SurfaceId,
+    scale: u32
+  },
+  // Pixel format of all subsequent uploads to the surface, one of those the
+  // server advertised at Hello
+  SetFormat {
+    surface: SurfaceId,
+    format: PixelFormat
+  },
+  // Child surface drawn relative to `parent`, initially synchronized at the
+  // parent's origin on top of its siblings
+  CreateSubsurface {
+    id: SurfaceId,
+    parent: SurfaceId,
+    width: usize,
+    height: usize
+  },
+  // Position and stacking of a subsurface apply on the next parent commit
+  SetSubsurfacePosition {
+    id: SurfaceId,
+    x: i32,
+    y: i32
+  },
+  PlaceAbove {
+    id: SurfaceId,
+    sibling: SurfaceId
+  },
+  PlaceBelow {
+    id: SurfaceId,
+    sibling: SurfaceId
+  },
+  SetSubsurfaceSync {
+    id: SurfaceId,
+    sync: bool
+  },
+  DestroySurface {
+    id: SurfaceId
+  },
+  // Make uploads since the last commit visible
+  Commit {
+    surface: SurfaceId
+  },
+  // Ask for a FrameDoneEvent once the surface is next shown, to draw in step
+  // with the output instead of as fast as possible
+  RequestFrame {
+    surface: SurfaceId
+  },
+  DamageReport {
+    x: usize,
+    y: usize,
+    dx: usize,
+    dy: usize
+  },
+  // Damage for transports without shared memory, carrying the encoded pixels
+  // of the damaged rect
+  DamageUpload {
+    surface: SurfaceId,
+    x: usize,
+    y: usize,
+    dx: usize,
+    dy: usize,
+    compression: Compression,
+    data: Vec<u8>
+  },
+  Pong
+}
+
+pub fn check_hello(msg: Message) -> std::io::Result<Handshake> {
+  match msg {
+    Message::Hello(handshake) => {
+      return Ok(handshake);
+    }
+    _ => {
+      let reason = "Interlocutor is speaking an unexpected protocol";
+      return Err(Error::new(ErrorKind::InvalidData, reason));
+    }
+  }
+}
+
+// Hello is always sent with bincode framing
+pub fn send_message<W: Write>(msg: Message, stream: &mut W) -> std::io::Result<()> {
+  return Bincode.write(&msg, stream);
+}
+
+pub fn recv_message<R: Read>(stream: &mut R) -> std::io::Result<Message> {
+  return codec::read_bincode(stream);
+}
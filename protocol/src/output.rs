@@ -0,0 +1,84 @@
+// Description of the outputs the compositor presents on, so clients can pick
+// a buffer size and density to render at.
+
+use serde::{Deserialize, Serialize};
+
+pub type OutputId = u32;
+
+// Rotation (counter-clockwise) and flip of the output content relative to
+// the panel
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Transform {
+  Normal,
+  Rotate90,
+  Rotate180,
+  Rotate270,
+  Flipped,
+  Flipped90,
+  Flipped180,
+  Flipped270
+}
+
+impl Transform {
+  // Whether width and height trade places
+  pub fn swaps_axes(self: &Self) -> bool {
+    return matches!(
+      self,
+      Transform::Rotate90 | Transform::Rotate270 | Transform::Flipped90 | Transform::Flipped270
+    );
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OutputInfo {
+  pub id: OutputId,
+  pub name: String,
+  // Resolution in pixels, before the transform
+  pub width: usize,
+  pub height: usize,
+  // Physical size in millimeters, 0 if unknown
+  pub physical_width_mm: u32,
+  pub physical_height_mm: u32,
+  // Output pixels per logical pixel; may be fractional
+  pub scale: f64,
+  // In millihertz, 0 if unknown
+  pub refresh_mhz: u32,
+  pub transform: Transform
+}
+
+impl OutputInfo {
+  // Size in logical pixels as clients see it, after the transform
+  pub fn logical_size(self: &Self) -> (usize, usize) {
+    let (w, h) = if self.transform.swaps_axes() {
+      (self.height, self.width)
+    }
+    else {
+      (self.width, self.height)
+    };
+    return ((w as f64 / self.scale).round() as usize, (h as f64 / self.scale).round() as usize);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_logical_size() {
+    let mut info = OutputInfo {
+      id: 0,
+      name: "test".to_string(),
+      width: 2560,
+      height: 1440,
+      physical_width_mm: 600,
+      physical_height_mm: 340,
+      scale: 1.5,
+      refresh_mhz: 60000,
+      transform: Transform::Normal
+    };
+    assert_eq!(info.logical_size(), (1707, 960));
+    info.transform = Transform::Flipped270;
+    info.scale = 2.0;
+    assert_eq!(info.logical_size(), (720, 1280));
+  }
+}
@@ -0,0 +1,27 @@
+// Privileged requests a client must be granted before the compositor honors
+// them. Which ones are granted is up to the compositor's policy.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Permission {
+  // Read back the composed output or surfaces
+  ScreenCapture,
+  // Register compositor-wide keyboard shortcuts
+  GlobalShortcuts,
+  // Become the input method, which sees typed text and can send keys to the
+  // focused client
+  InputMethod
+}
+
+impl Permission {
+  // Name used to grant the permission on the command line
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "screen-capture" => Some(Permission::ScreenCapture),
+      "global-shortcuts" => Some(Permission::GlobalShortcuts),
+      "input-method" => Some(Permission::InputMethod),
+      _ => None
+    }
+  }
+}